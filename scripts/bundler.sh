@@ -3,7 +3,6 @@ set -euo pipefail
 
 BINARY_NAME="tiles"
 DIST_DIR="dist"
-SERVER_DIR="server"
 TARGET="release"
 
 VERSION=$(grep '^version' Cargo.toml | head -1 | awk -F'"' '{print $2}')
@@ -15,11 +14,8 @@ echo "🚀 Building ${BINARY_NAME} (${TARGET} mode)..."
 cargo build --${TARGET}
 
 mkdir -p "${DIST_DIR}/tmp"
+# The server project is embedded in the binary, `tiles setup` writes it out
 cp "target/${TARGET}/${BINARY_NAME}" "${DIST_DIR}/tmp/"
-cp -r "${SERVER_DIR}" "${DIST_DIR}/tmp/"
-
-rm -rf "${DIST_DIR}/tmp/server/__pycache__"
-rm -rf "${DIST_DIR}/tmp/server/.venv"
 
 echo "📦 Creating ${OUT_NAME}.tar.gz..."
 tar -czf "${DIST_DIR}/${OUT_NAME}.tar.gz" -C "${DIST_DIR}/tmp" .
@@ -55,13 +55,8 @@ log "📦 Installing tiles binary to ${INSTALL_DIR}..."
 mkdir -p "${INSTALL_DIR}"
 install -m 755 "${TMPDIR}/tiles" "${INSTALL_DIR}/tiles"
 
-log "📦 Installing Python server to ${SERVER_DIR}..."
-mkdir -p "${SERVER_DIR}"
-cp -r "${TMPDIR}/server"/* "${SERVER_DIR}/"
-
-log "🔧 Setting up Python environment..."
-cd "${SERVER_DIR}"
-uv sync --frozen || err "Dependency setup failed."
+log "🔧 Installing Python server to ${SERVER_DIR}..."
+"${INSTALL_DIR}/tiles" setup || err "Dependency setup failed."
 
 rm -rf "${TMPDIR}"
 
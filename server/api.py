@@ -20,16 +20,21 @@
 # OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 # SOFTWARE.
 
-from fastapi import FastAPI, HTTPException
-from .config import SYSTEM_PROMPT
+from fastapi import FastAPI, HTTPException, Request
+from .config import SYSTEM_PROMPT, VERSION, API_VERSION, CAPABILITIES, SESSION_IDLE_TIMEOUT
 
+import asyncio
+import hmac
 import json
+import os
+import threading
 import time
 import uuid
+from collections import OrderedDict
 from collections.abc import AsyncGenerator
 from typing import Any, Dict, List, Optional, Union
 
-from fastapi.responses import StreamingResponse
+from fastapi.responses import JSONResponse, StreamingResponse
 from pydantic import BaseModel, Field
 
 from .cache_utils import (
@@ -40,12 +45,12 @@ from .mlx_runner import MLXRunner
 from server.mem_agent.utils import extract_python_code, extract_reply, extract_thoughts, create_memory_if_not_exists, format_results
 from server.mem_agent.engine import execute_sandboxed_code
 # Global model cache and configuration
-_model_cache: Dict[str, MLXRunner] = {}
-_current_model_path: Optional[str] = None
+_model_cache: "OrderedDict[str, LoadedModel]" = OrderedDict()
+# Generations run in FastAPI's threadpool, this guards loading and unloading
+_cache_lock = threading.RLock()
 _default_max_tokens: Optional[int] = None  # Use dynamic model-aware limits by default
 _runner: MLXRunner = {}
 _max_tool_turns = 5
-_memory_path = ""
 
 class CompletionRequest(BaseModel):
     model: str
@@ -62,8 +67,6 @@ class ChatMessage(BaseModel):
     role: str = Field(..., pattern="^(system|user|assistant)$")
     content: str
 
-_messages: list[ChatMessage]= []
-
 class ChatCompletionRequest(BaseModel):
     model: str
     messages: List[ChatMessage]
@@ -73,6 +76,9 @@ class ChatCompletionRequest(BaseModel):
     stream: Optional[bool] = False
     stop: Optional[Union[str, List[str]]] = None
     repetition_penalty: Optional[float] = 1.1
+    session_id: Optional[str] = None
+    # Rendered with the Modelfile's TEMPLATE, used instead of the chat template
+    prompt: Optional[str] = None
 
 
 class CompletionResponse(BaseModel):
@@ -90,7 +96,9 @@ class ChatCompletionResponse(BaseModel):
     created: int
     model: str
     choices: List[Dict[str, Any]]
-    # usage: Dict[str, int]
+    usage: Optional[Dict[str, int]] = None
+    # Seconds to the first token since the request arrived, and spent generating
+    timings: Optional[Dict[str, Optional[float]]] = None
 
 
 class ModelInfo(BaseModel):
@@ -103,6 +111,41 @@ class ModelInfo(BaseModel):
 class StartRequest(BaseModel):
     model: str
     memory_path: str
+    system_prompt: Optional[str] = None
+    session_id: Optional[str] = None
+    keep_alive: Optional[int] = None
+
+class LoadRequest(BaseModel):
+    model: str
+    keep_alive: Optional[int] = None
+
+class UnloadRequest(BaseModel):
+    # Every model when unset
+    model: Optional[str] = None
+
+# Clients that predate sessions all share this one
+DEFAULT_SESSION = "default"
+
+class Session:
+    """A conversation with its own history, so clients don't see each other's messages"""
+    def __init__(self, session_id: str, model: str, memory_path: str, system_prompt: str):
+        self.id = session_id
+        self.model = model
+        self.memory_path = memory_path
+        self.messages: List[ChatMessage] = [ChatMessage(role="system", content=system_prompt)]
+        self.created = int(time.time())
+        self.last_active = self.created
+
+    def info(self) -> Dict[str, Any]:
+        return {
+            "id": self.id,
+            "model": self.model,
+            "messages": len(self.messages),
+            "created": self.created,
+            "last_active": self.last_active,
+        }
+
+_sessions: Dict[str, Session] = {}
 
 class Agent:
     def __init__(
@@ -128,16 +171,90 @@ class Agent:
         self.use_vllm = use_vllm
             
 app = FastAPI()
+app.state.token_file = None
+app.state.cancel_dir = None
+# Seconds an idle model stays loaded, negative for ever, and models loaded at once
+app.state.keep_alive = 300
+app.state.max_loaded = 1
 
 agent: Agent()
 
-def get_or_load_model(model_spec: str, verbose: bool = False) -> MLXRunner:
-    """Get model from cache or load it if not cached."""
-    global _model_cache, _current_model_path
-
-    print(model_spec)
-    # Use the existing model path resolution from cache_utils
-
+def read_token(token_file: str) -> Optional[str]:
+    try:
+        with open(token_file, "r", encoding="utf-8") as f:
+            return f.read().strip() or None
+    except OSError:
+        return None
+
+def cancel_file_for(request_id: Optional[str]) -> Optional[str]:
+    """The proxy in front of the server creates this file when the request's client went away"""
+    cancel_dir = app.state.cancel_dir
+    if not cancel_dir or not request_id or not all(c.isalnum() or c in "-_" for c in request_id):
+        return None
+    return os.path.join(cancel_dir, request_id)
+
+@app.middleware("http")
+async def require_token(request: Request, call_next):
+    """Rejects requests without the bearer token, read on every request so `tiles token rotate` applies at once"""
+    token_file = app.state.token_file
+    if token_file:
+        token = read_token(token_file)
+        supplied = request.headers.get("authorization", "")
+        if token is None or not hmac.compare_digest(supplied.encode(), f"Bearer {token}".encode()):
+            return JSONResponse(status_code=401, content={"detail": "missing or invalid token"})
+    return await call_next(request)
+
+class LoadedModel:
+    """A model held in memory, unloaded once idle for longer than its keep_alive"""
+
+    def __init__(self, runner: MLXRunner, model: str, path: str, keep_alive: int, modelfile: Optional[str]):
+        self.runner = runner
+        self.model = model
+        self.path = path
+        self.modelfile = modelfile
+        # Seconds, negative keeps the model loaded until it is unloaded explicitly
+        self.keep_alive = keep_alive
+        self.loaded_at = int(time.time())
+        self.last_used = time.time()
+        self.in_use = 0
+        self.size = int(runner.get_memory_usage()["model_gb"] * 1024**3)
+
+    def expires_at(self) -> Optional[int]:
+        if self.keep_alive < 0:
+            return None
+        return int(self.last_used + self.keep_alive)
+
+    def info(self) -> Dict[str, Any]:
+        return {
+            "model": self.model,
+            "path": self.path,
+            "modelfile": self.modelfile,
+            "size": self.size,
+            "loaded_at": self.loaded_at,
+            "last_used": int(self.last_used),
+            "keep_alive": self.keep_alive,
+            "expires_at": self.expires_at(),
+        }
+
+def unload(path: str):
+    with _cache_lock:
+        loaded = _model_cache.pop(path, None)
+    if loaded is not None:
+        try:
+            loaded.runner.cleanup()
+        except Exception:
+            pass
+
+def get_or_load_model(
+    model_spec: str,
+    verbose: bool = False,
+    keep_alive: Optional[int] = None,
+    modelfile: Optional[str] = None,
+    hold: bool = False,
+) -> LoadedModel:
+    """Get model from cache or load it, unloading the least recently used
+    idle models beyond app.state.max_loaded first. Specs that resolve to the
+    same path share one entry, `hold` marks it in use before the lock is released."""
     try:
         model_path, model_name, commit_hash = get_model_path(model_spec)
         if not model_path.exists():
@@ -145,36 +262,68 @@ def get_or_load_model(model_spec: str, verbose: bool = False) -> MLXRunner:
     except Exception as e:
         raise HTTPException(status_code=404, detail=f"Model {model_spec} not found: {str(e)}")
 
-    # Check if it's an MLX model
-
     model_path_str = str(model_path)
-
-    print(_current_model_path)
-    print(model_path_str)
-    # Check if we need to load a different model
-    if _current_model_path != model_path_str:
-        # Proactively clean up any previously loaded runner to release memory
-        if _model_cache:
-            try:
-                for _old_runner in list(_model_cache.values()):
-                    try:
-                        _old_runner.cleanup()
-                    except Exception:
-                        pass
-            finally:
-                _model_cache.clear()
-
-        # Load new model
-        if verbose:
-            print(f"Loading model: {model_name}")
-
-        runner = MLXRunner(model_path_str, verbose=verbose)
-        runner.load_model()
-
-        _model_cache[model_path_str] = runner
-        _current_model_path = model_path_str
-
-    return _model_cache[model_path_str]
+    with _cache_lock:
+        loaded = _model_cache.get(model_path_str)
+        if loaded is None:
+            # Proactively clean up idle runners over the limit to release memory
+            while len(_model_cache) >= max(app.state.max_loaded, 1):
+                idle = next((path for path, cached in _model_cache.items() if cached.in_use == 0), None)
+                if idle is None:
+                    break
+                unload(idle)
+
+            if verbose:
+                print(f"Loading model: {model_name}")
+            runner = MLXRunner(model_path_str, verbose=verbose)
+            runner.load_model()
+            loaded = LoadedModel(
+                runner,
+                model_spec,
+                model_path_str,
+                app.state.keep_alive if keep_alive is None else keep_alive,
+                modelfile,
+            )
+            _model_cache[model_path_str] = loaded
+        elif keep_alive is not None:
+            loaded.keep_alive = keep_alive
+        if modelfile:
+            loaded.modelfile = modelfile
+        if hold:
+            loaded.in_use += 1
+        loaded.last_used = time.time()
+        _model_cache.move_to_end(model_path_str)
+        return loaded
+
+def close_idle_sessions():
+    """Closes sessions idle for longer than SESSION_IDLE_TIMEOUT, the shared default one stays"""
+    now = time.time()
+    for session_id, session in list(_sessions.items()):
+        if session_id != DEFAULT_SESSION and now - session.last_active > SESSION_IDLE_TIMEOUT:
+            print(f"Closing session {session_id}, idle for {int(now - session.last_active)}s")
+            _sessions.pop(session_id, None)
+
+async def unload_idle_models():
+    """Unloads models whose keep_alive ran out and closes idle sessions, checked every second"""
+    while True:
+        await asyncio.sleep(1)
+        close_idle_sessions()
+        # A model is loading, check again next time rather than block the event loop
+        if not _cache_lock.acquire(blocking=False):
+            continue
+        try:
+            now = time.time()
+            for path, loaded in list(_model_cache.items()):
+                expires_at = loaded.expires_at()
+                if loaded.in_use == 0 and expires_at is not None and expires_at <= now:
+                    print(f"Unloading {loaded.model}, idle for {loaded.keep_alive}s")
+                    unload(path)
+        finally:
+            _cache_lock.release()
+
+@app.on_event("startup")
+async def start_unloading_idle_models():
+    asyncio.get_running_loop().create_task(unload_idle_models())
 
 def format_chat_messages_for_runner(messages: List[ChatMessage]) -> List[Dict[str, str]]:
     """Convert chat messages to format expected by MLXRunner.
@@ -192,25 +341,104 @@ def count_tokens(text: str) -> int:
 async def ping():
     return {"message": "Badda-Bing Badda-Bang"} 
 
+@app.get("/version")
+async def version():
+    """Version and capabilities, checked by the CLI before it talks to the server"""
+    return {"version": VERSION, "api_version": API_VERSION, "capabilities": CAPABILITIES}
+
 @app.post("/start")
-async def start_model(request: StartRequest):
-    """Load the model and start the agent"""
-    global _messages, _runner,_memory_path
+def start_model(request: StartRequest, http_request: Request):
+    """Load the model and open a session, replacing one with the same id"""
+    global _runner
     print(str(request))
-    _messages = [ChatMessage(role="system", content=SYSTEM_PROMPT)]
-    _memory_path = request.memory_path
+    session_id = request.session_id or DEFAULT_SESSION
+    try:
+        _runner = get_or_load_model(
+            request.model,
+            keep_alive=request.keep_alive,
+            modelfile=http_request.headers.get("x-tiles-modelfile"),
+        ).runner
+    except Exception as e:
+        raise HTTPException(status_code=500, detail=str(e))
+    _sessions[session_id] = Session(
+        session_id, request.model, request.memory_path, request.system_prompt or SYSTEM_PROMPT
+    )
+    return {"message": "Model loaded", "session_id": session_id}
+
+@app.get("/models")
+async def list_loaded_models():
+    """Models in memory, most recently used last"""
+    return {"models": [loaded.info() for loaded in _model_cache.values()]}
+
+@app.post("/models/load")
+def load_model(request: LoadRequest, http_request: Request):
+    """Load a model ahead of its first chat"""
     try:
-        _runner = get_or_load_model(request.model)
-        return {"message": "Model loaded"}
+        loaded = get_or_load_model(
+            request.model,
+            keep_alive=request.keep_alive,
+            modelfile=http_request.headers.get("x-tiles-modelfile"),
+        )
+    except HTTPException:
+        raise
     except Exception as e:
         raise HTTPException(status_code=500, detail=str(e))
+    return loaded.info()
+
+@app.post("/models/unload")
+def unload_models(request: UnloadRequest):
+    """Unload a model, or every model, sessions load theirs again on their next chat"""
+    resolved = None
+    if request.model is not None:
+        try:
+            resolved = str(get_model_path(request.model)[0])
+        except Exception:
+            pass
+    with _cache_lock:
+        unloaded = [
+            (path, loaded.model)
+            for path, loaded in list(_model_cache.items())
+            if request.model is None or loaded.model == request.model or path == resolved
+        ]
+        for path, _ in unloaded:
+            unload(path)
+    if request.model is not None and not unloaded:
+        raise HTTPException(status_code=404, detail=f"{request.model} is not loaded")
+    return {"unloaded": [model for _, model in unloaded]}
+
+@app.get("/sessions")
+async def list_sessions():
+    return {"sessions": [session.info() for session in _sessions.values()]}
+
+@app.delete("/sessions/{session_id}")
+async def close_session(session_id: str):
+    if _sessions.pop(session_id, None) is None:
+        raise HTTPException(status_code=404, detail=f"no session {session_id}")
+    return {"message": "Session closed"}
 
 @app.post("/v1/chat/completions")
-async def create_chat_completion(request: ChatCompletionRequest):
-    """Create a chat completion."""
-    global _messages, _max_tool_turns, _memory_path
+def create_chat_completion(request: ChatCompletionRequest, http_request: Request):
+    """Create a chat completion, run in FastAPI's threadpool so generating
+    doesn't hold up other requests"""
+    global _max_tool_turns
+    session_id = request.session_id or DEFAULT_SESSION
+    session = _sessions.get(session_id)
+    if session is None:
+        raise HTTPException(status_code=404, detail=f"no session {session_id}, load the model with /start first")
+    session.last_active = int(time.time())
+    cancel_file = cancel_file_for(http_request.headers.get("x-tiles-request-id"))
+    should_stop = (lambda: os.path.exists(cancel_file)) if cancel_file else None
+    stop = [request.stop] if isinstance(request.stop, str) else request.stop
+    received_at = time.time()
+    # Summed over every generation, the mem-agent may generate several times for one reply
+    stats = {"tokens": 0, "first_token_at": None, "seconds": 0.0}
+
+    loaded = None
     try:
-        runner = get_or_load_model(request.model)
+        loaded = get_or_load_model(
+            request.model, modelfile=http_request.headers.get("x-tiles-modelfile"), hold=True
+        )
+        runner = loaded.runner
 
         # if request.stream:
         #     # Streaming response
@@ -225,11 +453,10 @@ async def create_chat_completion(request: ChatCompletionRequest):
         created = int(time.time())
 
         # Convert messages to dict format for runner
-        # _messages.append(system_message)
-        _messages.extend(request.messages)
-        message_dicts = format_chat_messages_for_runner(_messages)
-        # Let the runner format with chat templates
-        prompt = runner._format_conversation(message_dicts, use_chat_template=True)
+        session.messages.extend(request.messages)
+        message_dicts = format_chat_messages_for_runner(session.messages)
+        # Let the runner format with chat templates, unless the client rendered its own
+        prompt = request.prompt or runner._format_conversation(message_dicts, use_chat_template=True)
 
         generated_text = runner.generate_batch(
             prompt=prompt,
@@ -237,7 +464,10 @@ async def create_chat_completion(request: ChatCompletionRequest):
             temperature=request.temperature,
             top_p=request.top_p,
             repetition_penalty=request.repetition_penalty,
-            use_chat_template=False  # Already applied in _format_conversation
+            use_chat_template=False,  # Already applied in _format_conversation
+            should_stop=should_stop,
+            stop=stop,
+            stats=stats,
         )
 
         # Token counting
@@ -254,7 +484,7 @@ async def create_chat_completion(request: ChatCompletionRequest):
             create_memory_if_not_exists()
             result = execute_sandboxed_code(
                 code=python_code,
-                allowed_path=_memory_path,
+                allowed_path=session.memory_path,
                 import_module="server.mem_agent.tools",
             )
 
@@ -262,13 +492,16 @@ async def create_chat_completion(request: ChatCompletionRequest):
         print(str(result))        
 
         remaining_tool_turns = _max_tool_turns
-        while remaining_tool_turns > 0 and not reply:
-            _messages.append(ChatMessage(role="user", content=format_results(result[0], result[1])))
-            message_dicts = format_chat_messages_for_runner(_messages)
+        while remaining_tool_turns > 0 and not reply and not (should_stop and should_stop()):
+            session.messages.append(ChatMessage(role="user", content=format_results(result[0], result[1])))
+            message_dicts = format_chat_messages_for_runner(session.messages)
             # Let the runner format with chat templates
             prompt = runner._format_conversation(message_dicts, use_chat_template=True)
             generated_text = runner.generate_batch(
-                prompt=prompt
+                prompt=prompt,
+                should_stop=should_stop,
+                stop=stop,
+                stats=stats,
             )
             print(generated_text)
             # Extract the thoughts, reply and python code from the response
@@ -276,12 +509,12 @@ async def create_chat_completion(request: ChatCompletionRequest):
             reply = extract_reply(generated_text)
             python_code = extract_python_code(generated_text)
 
-            _messages.append(ChatMessage(role="assistant", content=generated_text))
+            session.messages.append(ChatMessage(role="assistant", content=generated_text))
             if python_code:
                 create_memory_if_not_exists()
                 result = execute_sandboxed_code(
                     code=python_code,
-                    allowed_path=_memory_path,
+                    allowed_path=session.memory_path,
                     import_module="server.mem_agent.tools",
                 )
             else:
@@ -303,11 +536,20 @@ async def create_chat_completion(request: ChatCompletionRequest):
                     "finish_reason": "stop"
                 }
             ],
-            # usage={
-            #     "prompt_tokens": prompt_tokens,
-            #     "completion_tokens": completion_tokens,
-            #     "total_tokens": prompt_tokens + completion_tokens
-            # }
+            usage={"completion_tokens": stats["tokens"]},
+            timings={
+                "time_to_first_token": stats["first_token_at"] - received_at
+                if stats["first_token_at"] is not None
+                else None,
+                "generation_seconds": stats["seconds"],
+            },
         )
     except Exception as e:
         raise HTTPException(status_code=500, detail=str(e))
+    finally:
+        if loaded is not None:
+            with _cache_lock:
+                loaded.in_use -= 1
+                loaded.last_used = time.time()
+        if cancel_file and os.path.exists(cancel_file):
+            os.remove(cancel_file)
@@ -1,7 +1,27 @@
+from importlib.metadata import PackageNotFoundError, version
 from pathlib import Path
 import os
+import re
 PORT = 6969
+
+
+def _project_version() -> str:
+    # uv installs the project into its venv, so pyproject.toml is the one place the version is kept
+    try:
+        return version("server")
+    except PackageNotFoundError:
+        pyproject = (Path(__file__).parent / "pyproject.toml").read_text(encoding="utf-8")
+        match = re.search(r'^version\s*=\s*"([^"]+)"', pyproject, re.MULTILINE)
+        return match.group(1) if match else "unknown"
+
+
+# Bump API_VERSION when an endpoint changes shape, the CLI refuses servers it doesn't speak
+VERSION = _project_version()
+API_VERSION = 1
+CAPABILITIES = ["start", "chat_completions", "sampling_options", "mem_agent", "auth", "sessions", "keep_alive", "prompt"]
 MODEL_ID = "driaforall/mem-agent"
+# Sessions nobody chatted with for this long are closed, clients that crashed never close theirs
+SESSION_IDLE_TIMEOUT = 60 * 60
 
 prompt_path = Path(__file__).parent / "system_prompt.txt"
 MEMORY_PATH = os.path.expanduser("~") + "/tiles_memory"
@@ -1,14 +1,52 @@
-# import os
+import argparse
+import os
+import threading
+import time
 import uvicorn
 from .api import app
 from .config import  PORT
 
+def parse_args():
+    parser = argparse.ArgumentParser(description="tiles server")
+    parser.add_argument("--uds", help="listen on this Unix socket instead of TCP")
+    parser.add_argument("--host", default="127.0.0.1")
+    parser.add_argument("--port", type=int, default=PORT)
+    parser.add_argument("--token-file", help="reject requests without the bearer token in this file")
+    parser.add_argument("--cancel-dir", help="stop generations whose request id shows up here")
+    parser.add_argument("--keep-alive", type=int, default=300, help="seconds an idle model stays loaded, negative for ever")
+    parser.add_argument("--max-loaded", type=int, default=1, help="models loaded at once, the least recently used goes first")
+    parser.add_argument("--watch-pid", type=int, help="exit once the process with this pid is gone")
+    return parser.parse_args()
+
+def watch(pid):
+    # A proxy killed outright can't stop the server, so the server stops itself
+    while True:
+        time.sleep(2)
+        try:
+            os.kill(pid, 0)
+        except ProcessLookupError:
+            os._exit(1)
+        except PermissionError:
+            pass
+
 def run():
+    args = parse_args()
+    app.state.token_file = args.token_file
+    app.state.cancel_dir = args.cancel_dir
+    app.state.keep_alive = args.keep_alive
+    app.state.max_loaded = args.max_loaded
+    if args.watch_pid:
+        threading.Thread(target=watch, args=(args.watch_pid,), daemon=True).start()
     # Write PID file
     # PID_FILE.write_text(str(os.getpid()))
 
     # try:
-    uvicorn.run(app, host="127.0.0.1", port=PORT)
+    if args.uds:
+        # The socket is created with 0600 so only this user can reach the server
+        os.umask(0o177)
+        uvicorn.run(app, uds=args.uds)
+    else:
+        uvicorn.run(app, host=args.host, port=args.port)
     # finally:
         # if PID_FILE.exists():
             # PID_FILE.unlink()
@@ -30,7 +30,7 @@ import os
 import time
 from collections.abc import Iterator
 from pathlib import Path
-from typing import Dict, Optional
+from typing import Any, Callable, Dict, List, Optional
 
 import mlx.core as mx
 from mlx_lm import load
@@ -477,7 +477,10 @@ class MLXRunner:
         # Keep a sliding window of recent tokens for context
         context_window = 10  # Decode last N tokens for proper spacing
 
+        first_token_at = None
         for token, _ in generator:
+            if first_token_at is None:
+                first_token_at = time.time()
             # Token might be an array or an int
             token_id = token.item() if hasattr(token, 'item') else token
             generated_tokens.append(token_id)
@@ -591,6 +594,9 @@ class MLXRunner:
         repetition_context_size: int = 20,
         use_chat_template: bool = True,
         interactive: bool = False,
+        should_stop: Optional[Callable[[], bool]] = None,
+        stop: Optional[List[str]] = None,
+        stats: Optional[Dict[str, Any]] = None,
     ) -> str:
         """Generate text in batch mode (non-streaming).
         
@@ -603,12 +609,17 @@ class MLXRunner:
             repetition_context_size: Context size for repetition penalty
             use_chat_template: Apply tokenizer's chat template if available
             interactive: True if this is interactive mode (affects token limits)
+            should_stop: Checked every few tokens, generation ends early when it returns True
+            stop: Strings that end the response, which is cut before the first of them
+            stats: Filled with tokens, time of the first token and seconds taken, added
+                to what it holds. Owned by the caller since generations share the runner
             
         Returns:
             Generated text
         """
         if not self.model or not self.tokenizer:
             raise RuntimeError("Model not loaded. Call load_model() first.")
+        stop = [s for s in (stop or []) if s]
 
         # Apply context-aware token limits
         effective_max_tokens = self.get_effective_max_tokens(max_tokens, interactive)
@@ -643,6 +654,7 @@ class MLXRunner:
         # Generate all tokens at once
         generated_tokens = []
         all_tokens = list(prompt_tokens)  # Keep prompt for proper decoding
+        first_token_at = None
 
         generator = generate_step(
             prompt=prompt_array,
@@ -653,6 +665,8 @@ class MLXRunner:
         )
 
         for token, _ in generator:
+            if first_token_at is None:
+                first_token_at = time.time()
             # Token might be an array or an int
             token_id = token.item() if hasattr(token, 'item') else token
             generated_tokens.append(token_id)
@@ -662,6 +676,15 @@ class MLXRunner:
             if token_id == self.tokenizer.eos_token_id:
                 break
 
+            if should_stop and len(generated_tokens) % 8 == 0 and should_stop():
+                break
+
+            # Decoding is costly, check for stop strings every few tokens and cut below
+            if stop and len(generated_tokens) % 8 == 0:
+                decoded = self.tokenizer.decode(generated_tokens)
+                if any(s in decoded for s in stop):
+                    break
+
         # Decode all tokens together for proper spacing
         full_response = self.tokenizer.decode(all_tokens)
 
@@ -674,11 +697,20 @@ class MLXRunner:
 
         # Apply end-token filtering (same logic as streaming mode for Issue #20)
         response = self._filter_end_tokens_from_response(response, use_chat_stop_tokens=False)
+
+        cut = min((response.find(s) for s in stop if s in response), default=None)
+        if cut is not None:
+            response = response[:cut]
         
         # Format reasoning models output
         response = self._format_reasoning_response(response)
 
         generation_time = time.time() - start_time
+        if stats is not None:
+            stats["tokens"] = stats.get("tokens", 0) + len(generated_tokens)
+            stats["seconds"] = stats.get("seconds", 0.0) + generation_time
+            if stats.get("first_token_at") is None:
+                stats["first_token_at"] = first_token_at
 
         # Count tokens for statistics
         if self.verbose:
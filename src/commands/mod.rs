@@ -1,30 +1,994 @@
 // Module that handles CLI commands
 
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
 use tiles::{
     core::{
+        alias::{Alias, AliasSource, AliasTable},
+        convert::{self, Format},
         health,
-        modelfile::{self},
+        hf_cache::{CachedModel, Framework, HfCache},
+        inspect::{self, ModelfileInfo},
+        logging::LogFormat,
+        modelfile::{self, Modelfile, Overrides, ResolveOptions},
+        ollama::{OllamaName, OllamaStore},
+        registry::{self, ModelName, Registry},
+        setup::ServerEnv,
+        token::TokenStore,
+    },
+    gateway::{self, Api, Backends, Catalog, Gateway},
+    runner::{
+        Backend, mlx,
+        protocol::{self, LoadRequest, ServerState},
+        proxy,
+        server::{self, Transport},
+        service::{self, Scope, Unit},
     },
-    runner::mlx,
 };
 
-pub async fn run(modelfile: &str) {
-    match modelfile::parse_from_file(modelfile) {
+// Resolves a Modelfile path or registered model name and applies overrides
+fn load(
+    reference: &str,
+    options: &ResolveOptions,
+    overrides: &Overrides,
+) -> Result<Modelfile, String> {
+    let path = registry::locate(reference)?;
+    modelfile::resolve_from_file(&path.to_string_lossy(), options)
+        .and_then(|modelfile| modelfile.apply_overrides(overrides))
+}
+
+pub async fn run(reference: &str, options: ResolveOptions, overrides: Overrides, verbose: bool) {
+    match load(reference, &options, &overrides) {
         Ok(modelfile) => {
-            mlx::run(modelfile).await;
+            if verbose {
+                println!("Effective configuration:\n{}\n", modelfile);
+            }
+            // Metrics and logs label generations with the name the Modelfile goes by
+            let name = registry::name_for(reference)
+                .map(|name| name.to_string())
+                .unwrap_or_else(|_| reference.to_owned());
+            mlx::run(modelfile, &name).await;
         }
         Err(err) => println!("{}", err),
     }
 }
 
-pub fn check_health() {
-    health::check_health();
+pub async fn check_health(
+    models: bool,
+    modelfiles: &[String],
+    sha256: bool,
+    json: bool,
+    fix: bool,
+) {
+    let mut report = health::check_health();
+    if fix {
+        let fixes: Vec<health::Fix> = report
+            .checks
+            .iter()
+            .filter_map(|check| check.fix.clone())
+            .collect();
+        for remedy in &fixes {
+            if !json {
+                println!("Fixing: {}", remedy);
+            }
+            if let Err(err) = remedy.apply() {
+                eprintln!("{:#}", err);
+            }
+        }
+        if !fixes.is_empty() {
+            report = health::check_health();
+        }
+    }
+    report.push(health::check_server_version().await);
+    if models {
+        for check in model_checks(modelfiles, sha256) {
+            report.push(check);
+        }
+    }
+    if json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(output) => println!("{}", output),
+            Err(err) => eprintln!("{}", err),
+        }
+    } else {
+        println!("Running diagnosis...");
+        for check in &report.checks {
+            print_check(check);
+        }
+    }
+    if !report.healthy {
+        std::process::exit(1);
+    }
+}
+
+fn model_checks(modelfiles: &[String], sha256: bool) -> Vec<health::Check> {
+    let mut checks = Vec::new();
+    let mut targets: Vec<(String, Result<Modelfile, String>)> = modelfiles
+        .iter()
+        .map(|reference| {
+            let modelfile = load(reference, &ResolveOptions::default(), &Overrides::default());
+            (reference.clone(), modelfile)
+        })
+        .collect();
+    if modelfiles.is_empty() {
+        match Registry::open().and_then(|registry| {
+            registry.list().map(|entries| {
+                entries
+                    .into_iter()
+                    .map(|entry| {
+                        let modelfile = registry.get(&entry.name).map_err(|err| err.to_string());
+                        (entry.name.to_string(), modelfile)
+                    })
+                    .collect::<Vec<_>>()
+            })
+        }) {
+            Ok(entries) => targets = entries,
+            Err(err) => checks.push(health::Check::new(
+                "registry",
+                health::Status::Fail,
+                err.to_string(),
+            )),
+        }
+    }
+    checks.extend(targets.into_iter().map(|(name, modelfile)| {
+        match modelfile.and_then(|modelfile| modelfile.model_ref()) {
+            Ok(model_ref) => {
+                health::Check::from_model(&name, &health::check_model(&model_ref, sha256))
+            }
+            Err(err) => health::Check::new(&name, health::Status::Fail, err),
+        }
+    }));
+    checks
 }
 
-pub fn start_server() {
-    let _ = mlx::start_server_daemon();
+fn print_check(check: &health::Check) {
+    let mark = match check.status {
+        health::Status::Pass => "✅",
+        health::Status::Warn => "⚠️",
+        health::Status::Fail => "❌",
+    };
+    println!("{}: {} {}", check.name, mark, check.detail);
+    for issue in &check.issues {
+        match &issue.file {
+            Some(file) => println!("  {}: {}", file, issue.problem),
+            None => println!("  {}", issue.problem),
+        }
+    }
+    if let Some(hint) = &check.hint {
+        println!("  hint: {}", hint);
+    }
+}
+
+pub fn setup(force: bool) {
+    let result = ServerEnv::open().and_then(|server| {
+        println!("Setting up the server in {}", server.dir().display());
+        server.setup(force)
+    });
+    match result {
+        Ok(outcome) => {
+            for path in &outcome.written {
+                println!("  installed {}", path);
+            }
+            if outcome.synced {
+                println!("✅ Server {} is ready", outcome.version);
+            } else {
+                println!("✅ Server {} is already set up", outcome.version);
+            }
+        }
+        Err(err) => {
+            println!("❌ Setup failed: {:#}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub async fn serve(
+    references: &[String],
+    dir: Option<&Path>,
+    api: Api,
+    addr: &str,
+    mlx_url: Option<String>,
+) {
+    let catalog = match Catalog::load(references, dir) {
+        Ok(catalog) => catalog,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let models = catalog.list();
+    // The Ollama API can create models later
+    if models.is_empty() && api == Api::OpenAi {
+        eprintln!("No models to serve, pass Modelfiles or register some with `tiles create`");
+        std::process::exit(1);
+    }
+    let registry = match Registry::open() {
+        Ok(registry) => registry,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let listener = match gateway::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+    };
+    let needs_mlx = models.iter().any(|model| model.backend == Backend::MlxChat);
+    let backends = match Backends::start(mlx_url, needs_mlx) {
+        Ok(backends) => backends,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    println!("Serving on http://{}", addr);
+    for model in &models {
+        println!("  {} ({})", model.name, model.model);
+    }
+    if backends.owns_mlx_server() {
+        println!("Started mlx_lm.server on {}", backends.mlx_url());
+    }
+    let gateway = Gateway {
+        catalog,
+        backends,
+        registry,
+    };
+    // The gateway, and the mlx_lm.server it started, are dropped before exiting
+    if let Err(err) = gateway::serve(gateway, api, listener).await {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+}
+
+pub async fn start_server(tcp: bool, supervise: bool, foreground: bool, log_format: LogFormat) {
+    if !foreground {
+        if let Err(err) = mlx::start_server_daemon(tcp, supervise, log_format) {
+            eprintln!("❌ Error: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    // A service manager restarts the server when it exits with an error
+    if let Err(err) = mlx::run_server_foreground(tcp, supervise).await {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+}
+
+pub async fn run_proxy(tcp: bool, supervise: bool) {
+    let transport = if tcp {
+        Ok(Transport::tcp())
+    } else {
+        Transport::unix()
+    };
+    if let Err(err) = async { proxy::run(transport?, supervise).await }.await {
+        eprintln!("{:#}", err);
+    }
 }
 
 pub fn stop_server() {
     let _ = mlx::stop_server_daemon();
 }
+
+// The server a command about loaded models talks to, once it is known to
+// keep models loaded
+async fn keep_alive_server() -> Result<(Transport, reqwest::Client), String> {
+    let transport = Transport::current().map_err(|err| format!("{:#}", err))?;
+    let handshake = server::handshake(&transport)
+        .await
+        .map_err(|err| format!("{:#}", err))?;
+    if !handshake.supports("keep_alive") {
+        return Err(
+            "The tiles server predates loading models ahead of time, run `tiles setup` and restart it"
+                .to_owned(),
+        );
+    }
+    let client = transport.client().map_err(|err| err.to_string())?;
+    Ok((transport, client))
+}
+
+// The model the server loads for a Modelfile, only Modelfiles it runs qualify
+fn server_model(reference: &str) -> Result<(Modelfile, String), String> {
+    let modelfile = load(reference, &ResolveOptions::default(), &Overrides::default())?;
+    let model_ref = modelfile.model_ref()?;
+    let from = modelfile.from.clone().unwrap_or_default();
+    if Backend::for_model(&from) != Backend::Server {
+        return Err(format!(
+            "{} runs with {}, only models run by the tiles server stay loaded",
+            reference,
+            Backend::for_model(&from).name()
+        ));
+    }
+    let model = Backend::Server.model_argument(&model_ref)?;
+    Ok((modelfile, model))
+}
+
+pub async fn load_on_server(reference: &str, keep_alive: Option<String>) {
+    let loaded = async {
+        let (modelfile, model) = server_model(reference)?;
+        let keep_alive = match keep_alive {
+            Some(keep_alive) => Some(modelfile::parse_keep_alive(&keep_alive)?),
+            None => modelfile.keep_alive(),
+        };
+        let name = registry::name_for(reference)
+            .map(|name| name.to_string())
+            .unwrap_or_else(|_| reference.to_owned());
+        let (transport, client) = keep_alive_server().await?;
+        let request = LoadRequest { model, keep_alive };
+        server::load_model(&transport, &client, &request, &name)
+            .await
+            .map_err(|err| format!("{:#}", err))
+    };
+    match loaded.await {
+        Ok(loaded) => println!(
+            "Loaded {} ({}), it {}",
+            loaded.model,
+            format_size(loaded.size),
+            format_keep_alive(loaded.keep_alive)
+        ),
+        Err(err) => println!("{}", err),
+    }
+}
+
+pub async fn unload_on_server(reference: Option<&str>) {
+    let unloaded = async {
+        let model = match reference {
+            Some(reference) => Some(server_model(reference)?.1),
+            None => None,
+        };
+        let (transport, client) = keep_alive_server().await?;
+        server::unload_models(&transport, &client, model.as_deref())
+            .await
+            .map_err(|err| format!("{:#}", err))
+    };
+    match unloaded.await {
+        Ok(unloaded) if unloaded.is_empty() => println!("No models were loaded"),
+        Ok(unloaded) => {
+            for model in unloaded {
+                println!("Unloaded {}", model);
+            }
+        }
+        Err(err) => println!("{}", err),
+    }
+}
+
+pub async fn list_loaded() {
+    let loaded = async {
+        let (transport, client) = keep_alive_server().await?;
+        server::loaded_models(&transport, &client)
+            .await
+            .map_err(|err| format!("{:#}", err))
+    };
+    let loaded = match loaded.await {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    if loaded.is_empty() {
+        println!("No models loaded");
+        return;
+    }
+    println!(
+        "{:<20}{:<40}{:<12}{:<16}UNLOADS",
+        "NAME", "MODEL", "SIZE", "LAST USED"
+    );
+    for model in loaded.iter().rev() {
+        println!(
+            "{:<20}{:<40}{:<12}{:<16}{}",
+            model.modelfile.as_deref().unwrap_or("-"),
+            model.model,
+            format_size(model.size),
+            format_age(model.last_used),
+            format_expiry(model.expires_at)
+        );
+    }
+}
+
+// How long an idle model stays loaded
+fn format_keep_alive(seconds: i64) -> String {
+    match seconds {
+        ..0 => "stays loaded until `tiles server unload`".to_owned(),
+        0..120 => format!("unloads after {} seconds idle", seconds),
+        120..7200 => format!("unloads after {} minutes idle", seconds / 60),
+        _ => format!("unloads after {} hours idle", seconds / 3600),
+    }
+}
+
+// When an idle model is unloaded, unless it is used again first
+fn format_expiry(expires_at: Option<u64>) -> String {
+    let Some(expires_at) = expires_at else {
+        return "never".to_owned();
+    };
+    match expires_at.saturating_sub(protocol::now()) {
+        0..60 => "within a minute".to_owned(),
+        seconds @ 60..7200 => format!("in {} minutes", seconds / 60),
+        seconds => format!("in {} hours", seconds / 3600),
+    }
+}
+
+pub fn install_service(system: bool, log_format: LogFormat) {
+    let scope = if system { Scope::System } else { Scope::User };
+    let unit = match Unit::current(scope, log_format) {
+        Ok(unit) => unit,
+        Err(err) => {
+            println!("{:#}", err);
+            return;
+        }
+    };
+    // The service would only find the daemon running and give up
+    let running = health::daemon_pid().is_some();
+    match service::install(scope, &unit, !running) {
+        Ok(path) => {
+            println!("Wrote {}", path.display());
+            let systemctl = match scope {
+                Scope::User => "systemctl --user",
+                Scope::System => "systemctl",
+            };
+            if running {
+                println!(
+                    "The service starts next time, or now with `tiles server stop && {} start {}`",
+                    systemctl,
+                    service::UNIT_NAME
+                );
+            } else {
+                println!(
+                    "Service started, follow it with `{} status {}`",
+                    systemctl,
+                    service::UNIT_NAME
+                );
+            }
+        }
+        Err(err) => println!("{:#}", err),
+    }
+}
+
+pub fn uninstall_service(system: bool) {
+    let scope = if system { Scope::System } else { Scope::User };
+    match service::uninstall(scope) {
+        Ok(Some(path)) => println!("Removed {}", path.display()),
+        Ok(None) => println!("No tiles service is installed"),
+        Err(err) => println!("{:#}", err),
+    }
+}
+
+pub async fn server_status() {
+    let transport = match Transport::current() {
+        Ok(transport) => transport,
+        Err(err) => {
+            println!("{:#}", err);
+            return;
+        }
+    };
+    let status = match server::server_status(&transport).await {
+        Ok(status) => status,
+        Err(err) => {
+            match mlx::clean_stale_daemon() {
+                Ok(true) => println!(
+                    "Server is not running, removed the pid file and socket it left behind"
+                ),
+                Ok(false) if health::daemon_pid().is_some() => {
+                    println!(
+                        "Server is running on {} but not answering: {:#}",
+                        transport, err
+                    )
+                }
+                Ok(false) => println!("Server is not running"),
+                Err(err) => println!("{:#}", err),
+            }
+            return;
+        }
+    };
+    println!(
+        "Proxy:    PID {} on {}{}",
+        status.proxy_pid,
+        transport,
+        if status.supervised {
+            ", supervised"
+        } else {
+            ""
+        }
+    );
+    match status.server {
+        ServerState::Running { pid, since } => {
+            println!(
+                "Server:   running, PID {}, started {}",
+                pid,
+                format_age(since)
+            )
+        }
+        ServerState::Restarting { at } => println!(
+            "Server:   restarting in {}s",
+            at.saturating_sub(protocol::now())
+        ),
+        ServerState::Exited => println!("Server:   exited"),
+    }
+    println!("Restarts: {}", status.restarts);
+    if status.crashes.is_empty() {
+        return;
+    }
+    println!("\nRecent crashes:");
+    for crash in status.crashes.iter().rev() {
+        println!("  {:<16}{}", format_age(crash.at), crash.status);
+        if let Some(reason) = &crash.reason {
+            println!("  {:<16}{}", "", reason);
+        }
+    }
+}
+
+pub async fn list_sessions() {
+    let sessions = match Transport::current() {
+        Ok(transport) => server::list_sessions(&transport).await,
+        Err(err) => Err(err),
+    };
+    let sessions = match sessions {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            println!("{:#}", err);
+            return;
+        }
+    };
+    if sessions.is_empty() {
+        println!("No active sessions");
+        return;
+    }
+    println!(
+        "{:<24}{:<32}{:<10}{:<16}LAST ACTIVE",
+        "ID", "MODEL", "MESSAGES", "STARTED"
+    );
+    for session in sessions {
+        println!(
+            "{:<24}{:<32}{:<10}{:<16}{}",
+            session.id,
+            session.model,
+            session.messages,
+            format_age(session.created),
+            format_age(session.last_active)
+        );
+    }
+}
+
+pub fn show_token() {
+    match TokenStore::open().and_then(|store| store.read()) {
+        Ok(Some(token)) => println!("{}", token),
+        Ok(None) => println!("No token yet, `tiles server start` creates one"),
+        Err(err) => println!("{:#}", err),
+    }
+}
+
+pub fn rotate_token() {
+    let rotated = TokenStore::open().and_then(|store| {
+        let token = store.rotate()?;
+        Ok((token, store))
+    });
+    match rotated {
+        Ok((token, store)) => {
+            println!("{}", token);
+            eprintln!("Wrote a new token to {}", store.path().display());
+        }
+        Err(err) => println!("{:#}", err),
+    }
+}
+
+pub fn convert(input: &str, from: Option<Format>, to: Format) {
+    match convert::read(input, from).and_then(|modelfile| convert::render(&modelfile, to)) {
+        Ok(output) => println!("{}", output),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub fn print_schema() {
+    println!("{:#}", modelfile::json_schema());
+}
+
+pub fn resolve(modelfile: &str, options: ResolveOptions) {
+    match load(modelfile, &options, &Overrides::default()) {
+        Ok(modelfile) => println!("{}", modelfile),
+        Err(err) => println!("{}", err),
+    }
+}
+
+/// Which part of the Modelfile `tiles show` prints
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ShowSection {
+    #[default]
+    Summary,
+    Parameters,
+    Template,
+    System,
+    License,
+    Json,
+}
+
+pub fn show(modelfile: &str, options: ResolveOptions, overrides: Overrides, section: ShowSection) {
+    let modelfile = match load(modelfile, &options, &overrides) {
+        Ok(modelfile) => modelfile,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let info = inspect::inspect(&modelfile, &overrides);
+    if matches!(section, ShowSection::Summary | ShowSection::Parameters) {
+        for warning in &info.warnings {
+            eprintln!("⚠️ {}", warning);
+        }
+    }
+    match section {
+        ShowSection::Summary => print_summary(&info),
+        ShowSection::Parameters => {
+            for parameter in &info.parameters {
+                println!("{:<16}{}", parameter.name, parameter.value);
+            }
+        }
+        ShowSection::Template => println!("{}", info.template.unwrap_or_default()),
+        ShowSection::System => println!("{}", info.system.unwrap_or_default()),
+        ShowSection::License => println!("{}", modelfile.license.unwrap_or_default()),
+        ShowSection::Json => match serde_json::to_string_pretty(&info) {
+            Ok(json) => println!("{}", json),
+            Err(err) => println!("{}", err),
+        },
+    }
+}
+
+fn print_summary(info: &ModelfileInfo) {
+    println!("  Model");
+    println!("    {:<16}{}", "from", info.model);
+    if let Some(reference) = &info.reference {
+        println!("    {:<16}{}", "reference", reference);
+    }
+    if let Some(resolved) = info.resolved.as_ref().filter(|r| **r != info.model) {
+        println!("    {:<16}{}", "resolved", resolved);
+    }
+    println!("    {:<16}{}", "backend", info.backend.name());
+    if let Some(adapter) = &info.adapter {
+        println!("    {:<16}{}", "adapter", adapter);
+    }
+
+    println!("\n  Parameters");
+    for parameter in &info.parameters {
+        let source = serde_json::to_value(parameter.source).unwrap_or_default();
+        println!(
+            "    {:<16}{:<24}{}",
+            parameter.name,
+            parameter.value,
+            source.as_str().unwrap_or_default()
+        );
+    }
+
+    if let Some(system) = &info.system {
+        println!("\n  System");
+        for line in system.lines() {
+            println!("    {}", line);
+        }
+    }
+
+    if !info.template_variables.is_empty() {
+        println!("\n  Template");
+        println!(
+            "    {:<16}{}",
+            "variables",
+            info.template_variables.join(", ")
+        );
+    }
+
+    println!("\n  Messages");
+    println!("    {:<16}{}", "count", info.message_count);
+
+    if let Some(license) = &info.license {
+        println!("\n  License");
+        println!("    {} ({} lines)", license.title, license.lines);
+    }
+}
+
+pub fn create(name: &str, modelfile_path: &str, options: ResolveOptions) {
+    let result = name.parse::<ModelName>().and_then(|name| {
+        let modelfile = modelfile::resolve_from_file(modelfile_path, &options)?;
+        Registry::open()
+            .and_then(|registry| registry.create(&name, &modelfile))
+            .map_err(|err| err.to_string())
+    });
+    match result {
+        Ok(entry) => println!(
+            "Created {} ({})",
+            entry.name,
+            short_digest(&entry.manifest.digest)
+        ),
+        Err(err) => println!("{}", err),
+    }
+}
+
+pub fn list_models() {
+    let entries = match Registry::open().and_then(|registry| registry.list()) {
+        Ok(entries) => entries,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    println!("{:<32}{:<16}{:<16}FROM", "NAME", "ID", "CREATED");
+    for entry in entries {
+        println!(
+            "{:<32}{:<16}{:<16}{}",
+            entry.name.to_string(),
+            short_digest(&entry.manifest.digest),
+            format_age(entry.manifest.created),
+            entry.manifest.from
+        );
+    }
+}
+
+pub fn remove_models(names: &[String]) {
+    for name in names {
+        let result = name.parse::<ModelName>().and_then(|name| {
+            Registry::open()
+                .and_then(|registry| registry.remove(&name))
+                .map_err(|err| err.to_string())
+        });
+        match result {
+            Ok(()) => println!("Deleted {}", name),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+pub fn copy_model(source: &str, destination: &str) {
+    let result = source.parse::<ModelName>().and_then(|source| {
+        let destination = destination.parse::<ModelName>()?;
+        Registry::open()
+            .and_then(|registry| registry.copy(&source, &destination))
+            .map_err(|err| err.to_string())
+    });
+    match result {
+        Ok(()) => println!("Copied {} to {}", source, destination),
+        Err(err) => println!("{}", err),
+    }
+}
+
+pub fn import_ollama(name: &str, target: Option<&str>) {
+    let result = name.parse::<OllamaName>().and_then(|source| {
+        let target: ModelName = match target {
+            Some(target) => target.parse()?,
+            None => source.registry_name().parse()?,
+        };
+        let imported = OllamaStore::open()
+            .and_then(|store| store.import(&source, &AliasTable::load()?))
+            .map_err(|err| err.to_string())?;
+        let entry = Registry::open()
+            .and_then(|registry| registry.create(&target, &imported.modelfile))
+            .map_err(|err| err.to_string())?;
+        Ok((source, entry, imported.skipped))
+    });
+    match result {
+        Ok((source, entry, skipped)) => {
+            if !skipped.is_empty() {
+                println!("Skipped unsupported parameters: {}", skipped.join(", "));
+            }
+            println!(
+                "Imported {} as {} ({})",
+                source,
+                entry.name,
+                short_digest(&entry.manifest.digest)
+            );
+        }
+        Err(err) => println!("{}", err),
+    }
+}
+
+pub fn export_ollama(modelfile_path: &str, to: &Path, name: Option<&str>) {
+    let result = name
+        .map(str::to_owned)
+        .or_else(|| {
+            Path::new(modelfile_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_lowercase())
+        })
+        .unwrap_or_default()
+        .parse::<OllamaName>()
+        .and_then(|name| {
+            let modelfile = load(
+                modelfile_path,
+                &ResolveOptions::default(),
+                &Overrides::default(),
+            )?;
+            OllamaStore::at(to)
+                .export(&name, &modelfile)
+                .map(|manifest| (name, manifest))
+                .map_err(|err| err.to_string())
+        });
+    match result {
+        Ok((name, manifest)) => {
+            let size: u64 = manifest.layers.iter().map(|layer| layer.size).sum();
+            println!(
+                "Exported {} to {} ({} layers, {} bytes)",
+                name,
+                to.display(),
+                manifest.layers.len(),
+                size
+            );
+        }
+        Err(err) => println!("{}", err),
+    }
+}
+
+pub fn list_cached_models(framework: Option<Framework>, json: bool) {
+    let models = match HfCache::open().and_then(|cache| cache.list()) {
+        Ok(models) => models,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let models: Vec<CachedModel> = models
+        .into_iter()
+        .filter(|model| framework.is_none_or(|framework| model.framework == framework))
+        .collect();
+    if json {
+        match serde_json::to_string_pretty(&models) {
+            Ok(json) => println!("{}", json),
+            Err(err) => println!("{}", err),
+        }
+        return;
+    }
+    println!(
+        "{:<56}{:<10}{:<10}{:<12}{:<24}MODIFIED",
+        "NAME", "ID", "SIZE", "FRAMEWORK", "QUANTIZATION"
+    );
+    for model in models {
+        let commit = model
+            .snapshot()
+            .and_then(|snapshot| {
+                snapshot
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+            })
+            .unwrap_or_default();
+        println!(
+            "{:<56}{:<10}{:<10}{:<12}{:<24}{}",
+            model.repo,
+            &commit[..commit.len().min(8)],
+            format_size(model.size),
+            model.framework.to_string(),
+            model.quantization.as_deref().unwrap_or("-"),
+            format_age(model.modified)
+        );
+    }
+}
+
+pub fn show_cached_model(repo: &str, json: bool) {
+    let model = match HfCache::open().and_then(|cache| cache.find(repo)) {
+        Ok(model) => model,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    if json {
+        match serde_json::to_string_pretty(&model) {
+            Ok(json) => println!("{}", json),
+            Err(err) => println!("{}", err),
+        }
+        return;
+    }
+    println!("  Model");
+    println!("    {:<16}{}", "repo", model.repo);
+    println!("    {:<16}{}", "path", model.path.display());
+    println!("    {:<16}{}", "size", format_size(model.size));
+    println!("    {:<16}{}", "framework", model.framework);
+    if let Some(quantization) = &model.quantization {
+        println!("    {:<16}{}", "quantization", quantization);
+    }
+    println!("    {:<16}{}", "modified", format_age(model.modified));
+
+    if !model.refs.is_empty() {
+        println!("\n  Refs");
+        for (name, commit) in &model.refs {
+            println!("    {:<16}{}", name, commit);
+        }
+    }
+
+    println!("\n  Snapshots");
+    for snapshot in &model.snapshots {
+        println!("    {}", snapshot);
+    }
+}
+
+pub fn remove_cached_model(repo: &str, force: bool) {
+    let result = HfCache::open().and_then(|cache| {
+        let model = cache.get(repo)?;
+        if !force {
+            print!(
+                "Delete {} ({})? [y/N] ",
+                model.repo,
+                format_size(model.size)
+            );
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                return Ok(None);
+            }
+        }
+        cache.remove(&model).map(|freed| Some((model.repo, freed)))
+    });
+    match result {
+        Ok(Some((repo, freed))) => println!("Deleted {}, freed {}", repo, format_size(freed)),
+        Ok(None) => println!("Aborted"),
+        Err(err) => println!("{}", err),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1_000_000 => format!("{:.1} KB", bytes as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.1} GB", bytes as f64 / 1e9),
+    }
+}
+
+fn short_digest(digest: &str) -> &str {
+    let hex = digest.trim_start_matches("sha256:");
+    &hex[..hex.len().min(12)]
+}
+
+fn format_age(timestamp: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let seconds = now.saturating_sub(timestamp);
+    match seconds {
+        0..60 => "just now".to_owned(),
+        60..3600 => format!("{} minutes ago", seconds / 60),
+        3600..86400 => format!("{} hours ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+pub fn list_aliases() {
+    let table = match AliasTable::load() {
+        Ok(table) => table,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    println!("{:<24}{:<48}{:<40}SOURCE", "NAME", "MLX", "GGUF");
+    for (name, alias, source) in table.entries() {
+        let source = match source {
+            AliasSource::BuiltIn => "built-in",
+            AliasSource::User => "user",
+        };
+        println!(
+            "{:<24}{:<48}{:<40}{}",
+            name,
+            alias.mlx.unwrap_or_else(|| "-".to_owned()),
+            alias.gguf.unwrap_or_else(|| "-".to_owned()),
+            source
+        );
+    }
+}
+
+pub fn add_alias(name: &str, alias: Alias) {
+    match AliasTable::load().and_then(|mut table| table.add(name, alias)) {
+        Ok(()) => println!("Added alias {}", name),
+        Err(err) => println!("{}", err),
+    }
+}
+
+pub fn remove_alias(name: &str) {
+    match AliasTable::load().and_then(|mut table| table.remove(name)) {
+        Ok(()) => println!("Removed alias {}", name),
+        Err(err) => println!("{}", err),
+    }
+}
@@ -0,0 +1,211 @@
+// Maps Ollama style model names to the artifacts tiles can use for them: MLX
+// weights to run, and GGUF weights for `tiles export ollama`
+//
+// Built-in aliases can be overridden or extended in `aliases.toml` under the
+// tiles config dir:
+//
+// ["llama3.2:latest"]
+// mlx = "mlx-community/Llama-3.2-3B-Instruct-4bit"
+// gguf = "/models/llama-3.2-3b-instruct-q4_k_m.gguf"
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use crate::core::config::get_config_dir;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Alias {
+    /// Hugging Face repo or local directory with MLX weights
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mlx: Option<String>,
+    /// Path to a GGUF file, only used by `tiles export ollama` since every
+    /// backend runs MLX weights
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gguf: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AliasSource {
+    BuiltIn,
+    User,
+}
+
+const BUILT_IN: &[(&str, &str)] = &[
+    (
+        "llama3.2:latest",
+        "mlx-community/Llama-3.2-3B-Instruct-4bit",
+    ),
+    ("llama3.2:3b", "mlx-community/Llama-3.2-3B-Instruct-4bit"),
+    ("llama3.2:1b", "mlx-community/Llama-3.2-1B-Instruct-4bit"),
+    (
+        "llama3.1:latest",
+        "mlx-community/Meta-Llama-3.1-8B-Instruct-4bit",
+    ),
+    (
+        "llama3.1:8b",
+        "mlx-community/Meta-Llama-3.1-8B-Instruct-4bit",
+    ),
+    (
+        "mistral:latest",
+        "mlx-community/Mistral-7B-Instruct-v0.3-4bit",
+    ),
+    ("mistral:7b", "mlx-community/Mistral-7B-Instruct-v0.3-4bit"),
+    ("qwen2.5:latest", "mlx-community/Qwen2.5-7B-Instruct-4bit"),
+    ("qwen2.5:7b", "mlx-community/Qwen2.5-7B-Instruct-4bit"),
+    ("gemma2:2b", "mlx-community/gemma-2-2b-it-4bit"),
+    ("phi3:latest", "mlx-community/Phi-3-mini-4k-instruct-4bit"),
+    ("phi3:mini", "mlx-community/Phi-3-mini-4k-instruct-4bit"),
+];
+
+pub struct AliasTable {
+    path: PathBuf,
+    user: BTreeMap<String, Alias>,
+}
+
+impl AliasTable {
+    /// Loads the user aliases from the tiles config dir
+    pub fn load() -> Result<AliasTable> {
+        AliasTable::load_from(get_config_dir()?.join("aliases.toml"))
+    }
+
+    pub fn load_from(path: impl Into<PathBuf>) -> Result<AliasTable> {
+        let path = path.into();
+        let user = if path.exists() {
+            let content = fs::read_to_string(&path).context("Failed to read aliases.toml")?;
+            let user: BTreeMap<String, Alias> = toml::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            // Hand written entries may not be normalized
+            user.into_iter()
+                .map(|(name, alias)| (normalize(&name), alias))
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+        Ok(AliasTable { path, user })
+    }
+
+    /// Looks up `name[:tag]`, user aliases take precedence over built-in ones
+    pub fn lookup(&self, name: &str) -> Option<Alias> {
+        let name = normalize(name);
+        self.user.get(&name).cloned().or_else(|| {
+            BUILT_IN
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, mlx)| Alias {
+                    mlx: Some(mlx.to_string()),
+                    gguf: None,
+                })
+        })
+    }
+
+    /// Every alias with where it comes from, sorted by name
+    pub fn entries(&self) -> Vec<(String, Alias, AliasSource)> {
+        let mut entries: Vec<(String, Alias, AliasSource)> = BUILT_IN
+            .iter()
+            .filter(|(name, _)| !self.user.contains_key(*name))
+            .map(|(name, mlx)| {
+                let alias = Alias {
+                    mlx: Some(mlx.to_string()),
+                    gguf: None,
+                };
+                (name.to_string(), alias, AliasSource::BuiltIn)
+            })
+            .collect();
+        entries.extend(
+            self.user
+                .iter()
+                .map(|(name, alias)| (name.clone(), alias.clone(), AliasSource::User)),
+        );
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    pub fn add(&mut self, name: &str, alias: Alias) -> Result<()> {
+        if alias.mlx.is_none() && alias.gguf.is_none() {
+            bail!("An alias needs at least one of --mlx or --gguf");
+        }
+        self.user.insert(normalize(name), alias);
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        let name = normalize(name);
+        if self.user.remove(&name).is_none() {
+            if BUILT_IN.iter().any(|(alias, _)| *alias == name) {
+                bail!(
+                    "{} is a built-in alias, override it with `tiles alias add`",
+                    name
+                );
+            }
+            bail!("No alias named {}", name);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create tiles config directory")?;
+        }
+        fs::write(&self.path, toml::to_string(&self.user)?).context("Failed to write aliases.toml")
+    }
+}
+
+// Ollama names are case insensitive and default to the `latest` tag
+fn normalize(name: &str) -> String {
+    let name = name.to_lowercase();
+    if name.contains(':') {
+        name
+    } else {
+        format!("{}:latest", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_lookup() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let table = AliasTable::load_from(dir.path().join("aliases.toml"))?;
+        let alias = table.lookup("llama3.2").unwrap();
+        assert_eq!(
+            alias.mlx.as_deref(),
+            Some("mlx-community/Llama-3.2-3B-Instruct-4bit")
+        );
+        assert!(table.lookup("unknown:latest").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_aliases_override_and_persist() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("aliases.toml");
+        let mut table = AliasTable::load_from(&path)?;
+        let alias = Alias {
+            mlx: Some("/models/llama-mlx".to_owned()),
+            gguf: Some("/models/llama.gguf".to_owned()),
+        };
+        table.add("llama3.2:latest", alias.clone())?;
+
+        let table = AliasTable::load_from(&path)?;
+        assert_eq!(table.lookup("llama3.2"), Some(alias.clone()));
+        assert_eq!(table.lookup("Llama3.2:LATEST"), Some(alias));
+        let (_, _, source) = table
+            .entries()
+            .into_iter()
+            .find(|(name, ..)| name == "llama3.2:latest")
+            .unwrap();
+        assert_eq!(source, AliasSource::User);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtin_aliases_cannot_be_removed() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut table = AliasTable::load_from(dir.path().join("aliases.toml"))?;
+        assert!(table.remove("llama3.2").is_err());
+        Ok(())
+    }
+}
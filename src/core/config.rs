@@ -0,0 +1,98 @@
+// Locations of the tiles config, data and server directories
+
+use anyhow::{Context, Result};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Port the Python server listens on
+pub const SERVER_PORT: u16 = 6969;
+
+/// `$TILES_SERVER_DIR` when set, as in the systemd unit tiles writes
+pub fn get_server_dir() -> Result<PathBuf> {
+    get_server_dir_in(&env::home_dir().context("Failed to fetch $HOME")?)
+}
+
+/// The server dir of the user whose home is `home_dir`
+pub fn get_server_dir_in(home_dir: &Path) -> Result<PathBuf> {
+    if let Ok(val) = env::var("TILES_SERVER_DIR") {
+        return Ok(PathBuf::from(val));
+    }
+    if cfg!(debug_assertions) {
+        let base_dir = env::current_dir().context("Failed to fetch CURRENT_DIR")?;
+        Ok(base_dir.join("server"))
+    } else {
+        let data_dir = match env::var("XDG_DATA_HOME") {
+            Ok(val) => PathBuf::from(val),
+            Err(_err) => home_dir.join(".local/share"),
+        };
+        Ok(data_dir.join("tiles/server"))
+    }
+}
+
+pub fn get_config_dir() -> Result<PathBuf> {
+    get_config_dir_in(&env::home_dir().context("Failed to fetch $HOME")?)
+}
+
+/// The config dir of the user whose home is `home_dir`
+pub fn get_config_dir_in(home_dir: &Path) -> Result<PathBuf> {
+    if cfg!(debug_assertions) {
+        let base_dir = env::current_dir().context("Failed to fetch CURRENT_DIR")?;
+        Ok(base_dir.join(".tiles_dev/tiles"))
+    } else {
+        let config_dir = match env::var("XDG_CONFIG_HOME") {
+            Ok(val) => PathBuf::from(val),
+            Err(_err) => home_dir.join(".config"),
+        };
+        Ok(config_dir.join("tiles"))
+    }
+}
+
+pub fn get_data_dir() -> Result<PathBuf> {
+    if cfg!(debug_assertions) {
+        let base_dir = env::current_dir().context("Failed to fetch CURRENT_DIR")?;
+        Ok(base_dir.join(".tiles_dev/tiles"))
+    } else {
+        let home_dir = env::home_dir().context("Failed to fetch $HOME")?;
+        let data_dir = match env::var("XDG_DATA_HOME") {
+            Ok(val) => PathBuf::from(val),
+            Err(_err) => home_dir.join(".local/share"),
+        };
+        Ok(data_dir.join("tiles"))
+    }
+}
+
+/// Where the daemon's socket lives, `$XDG_RUNTIME_DIR/tiles` or the config dir
+pub fn get_runtime_dir() -> Result<PathBuf> {
+    if cfg!(debug_assertions) {
+        return get_config_dir();
+    }
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(val) if !val.is_empty() => Ok(PathBuf::from(val).join("tiles")),
+        _ => get_config_dir(),
+    }
+}
+
+/// Models dir of a local Ollama installation, `$OLLAMA_MODELS` or ~/.ollama/models
+pub fn get_ollama_models_dir() -> Result<PathBuf> {
+    match env::var("OLLAMA_MODELS") {
+        Ok(val) => Ok(PathBuf::from(val)),
+        Err(_err) => {
+            let home_dir = env::home_dir().context("Failed to fetch $HOME")?;
+            Ok(home_dir.join(".ollama/models"))
+        }
+    }
+}
+
+/// Hugging Face hub cache, honoring `$HF_HUB_CACHE` and `$HF_HOME`
+pub fn get_hf_hub_dir() -> Result<PathBuf> {
+    if let Ok(val) = env::var("HF_HUB_CACHE") {
+        return Ok(PathBuf::from(val));
+    }
+    match env::var("HF_HOME") {
+        Ok(val) => Ok(PathBuf::from(val).join("hub")),
+        Err(_err) => {
+            let home_dir = env::home_dir().context("Failed to fetch $HOME")?;
+            Ok(home_dir.join(".cache/huggingface/hub"))
+        }
+    }
+}
@@ -0,0 +1,123 @@
+// Converts Modelfiles to and from structured formats (JSON, YAML, TOML)
+
+use std::{fmt::Display, fs, path::Path, str::FromStr};
+
+use crate::core::modelfile::{self, Modelfile};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Modelfile,
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "modelfile" => Ok(Format::Modelfile),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            other => Err(format!(
+                "Unknown format `{}`, expected one of json, yaml, toml, modelfile",
+                other
+            )),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Modelfile => write!(f, "modelfile"),
+            Format::Json => write!(f, "json"),
+            Format::Yaml => write!(f, "yaml"),
+            Format::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+impl Format {
+    /// Guesses the format from the file extension, anything unknown is a Modelfile
+    pub fn from_path(path: &str) -> Format {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| ext.parse().ok())
+            .unwrap_or(Format::Modelfile)
+    }
+}
+
+pub fn read(path: &str, format: Option<Format>) -> Result<Modelfile, String> {
+    let format = format.unwrap_or_else(|| Format::from_path(path));
+    match fs::read_to_string(path) {
+        Ok(content) => parse_as(&content, format),
+        Err(err) => Err(format!("Reading {} failed due to {}", path, err)),
+    }
+}
+
+pub fn parse_as(input: &str, format: Format) -> Result<Modelfile, String> {
+    match format {
+        Format::Modelfile => modelfile::parse(input),
+        Format::Json => serde_json::from_str(input).map_err(|err| err.to_string()),
+        Format::Yaml => serde_yaml::from_str(input).map_err(|err| err.to_string()),
+        Format::Toml => toml::from_str(input).map_err(|err| err.to_string()),
+    }
+}
+
+pub fn render(modelfile: &Modelfile, format: Format) -> Result<String, String> {
+    match format {
+        Format::Modelfile => Ok(modelfile.to_string()),
+        Format::Json => serde_json::to_string_pretty(modelfile).map_err(|err| err.to_string()),
+        Format::Yaml => serde_yaml::to_string(modelfile).map_err(|err| err.to_string()),
+        Format::Toml => toml::to_string(modelfile).map_err(|err| err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [Format; 4] = [Format::Modelfile, Format::Json, Format::Yaml, Format::Toml];
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("a.json"), Format::Json);
+        assert_eq!(Format::from_path("a.YML"), Format::Yaml);
+        assert_eq!(Format::from_path("a.toml"), Format::Toml);
+        assert_eq!(Format::from_path("fixtures/a.modelfile"), Format::Modelfile);
+        assert_eq!(Format::from_path("Modelfile"), Format::Modelfile);
+    }
+
+    #[test]
+    fn test_round_trip_all_formats() -> Result<(), String> {
+        for path in ["fixtures/a.modelfile", "fixtures/mistral.modelfile"] {
+            let original = read(path, None)?;
+            let expected = render(&original, Format::Json)?;
+            for format in FORMATS {
+                let rendered = render(&original, format)?;
+                let parsed = parse_as(&rendered, format)?;
+                assert_eq!(render(&parsed, Format::Json)?, expected, "{}", format);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_structured_input_is_validated() {
+        let json = r#"{"from": "llama3.2", "parameters": [{"name": "num_ctx", "value": 0.5}]}"#;
+        assert!(parse_as(json, Format::Json).is_err());
+        let toml = "parameters = []";
+        assert!(parse_as(toml, Format::Toml).is_err());
+    }
+
+    #[test]
+    fn test_float_parameters_stay_exact() -> Result<(), String> {
+        let modelfile = modelfile::parse("FROM llama3.2\nPARAMETER temperature 0.7")?;
+        let yaml = render(&modelfile, Format::Yaml)?;
+        assert!(yaml.contains("value: 0.7\n"));
+        Ok(())
+    }
+}
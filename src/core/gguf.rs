@@ -0,0 +1,265 @@
+// Reads the metadata at the start of a GGUF file
+//
+// Layout (little endian, v2 and later):
+// magic "GGUF", version u32, tensor count u64, metadata count u64
+// metadata: key string, value type u32, value
+// tensor infos: name string, dimension count u32, dimensions u64..., type u32, offset u64
+// Strings are a u64 length followed by UTF-8 bytes
+
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"GGUF";
+// Value types, in the order GGUF numbers them
+const TYPE_SIZES: [u64; 13] = [1, 1, 2, 2, 4, 4, 4, 1, 0, 0, 8, 8, 8];
+const TYPE_U32: u32 = 4;
+const TYPE_STRING: u32 = 8;
+const TYPE_ARRAY: u32 = 9;
+const TYPE_U64: u32 = 10;
+
+/// What Ollama lists for a model in `ollama show` and `/api/tags`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GgufInfo {
+    /// `general.architecture`, e.g. "llama"
+    pub architecture: String,
+    /// The parameter count, e.g. "1.1B"
+    pub size: String,
+    /// The quantization of most tensors, e.g. "Q4_K_M"
+    pub file_type: String,
+}
+
+/// Reads the architecture, size and quantization of the GGUF file at `path`,
+/// without reading its tensors
+pub fn read_info(path: &Path) -> Result<GgufInfo> {
+    let file = File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    read_header(&mut BufReader::new(file))
+        .with_context(|| format!("{} is not a GGUF file", path.display()))
+}
+
+fn read_header(reader: &mut impl Read) -> Result<GgufInfo> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        bail!("Missing GGUF magic");
+    }
+    let version = read_u32(reader)?;
+    if version < 2 {
+        bail!("GGUF version {} is not supported", version);
+    }
+    let tensor_count = read_u64(reader)?;
+    let metadata_count = read_u64(reader)?;
+
+    let mut architecture = None;
+    let mut parameter_count = None;
+    let mut file_type = None;
+    for _ in 0..metadata_count {
+        let key = read_string(reader)?;
+        let value_type = read_u32(reader)?;
+        match (key.as_str(), value_type) {
+            ("general.architecture", TYPE_STRING) => architecture = Some(read_string(reader)?),
+            ("general.parameter_count", TYPE_U64) => parameter_count = Some(read_u64(reader)?),
+            ("general.file_type", TYPE_U32) => file_type = Some(read_u32(reader)?),
+            _ => skip_value(reader, value_type)?,
+        }
+    }
+    let architecture = architecture.context("Missing general.architecture")?;
+
+    // Older converters don't write the parameter count, so it is summed up
+    // from the tensor shapes
+    let parameter_count = match parameter_count {
+        Some(count) => count,
+        None => {
+            let mut count = 0u64;
+            for _ in 0..tensor_count {
+                read_string(reader)?;
+                let mut elements = 1u64;
+                for _ in 0..read_u32(reader)? {
+                    elements = elements.saturating_mul(read_u64(reader)?);
+                }
+                read_u32(reader)?;
+                read_u64(reader)?;
+                count = count.saturating_add(elements);
+            }
+            count
+        }
+    };
+
+    Ok(GgufInfo {
+        architecture,
+        size: human_number(parameter_count),
+        file_type: file_type.map_or("unknown", file_type_name).to_owned(),
+    })
+}
+
+// Names as llama.cpp and Ollama print them
+fn file_type_name(file_type: u32) -> &'static str {
+    match file_type {
+        0 => "F32",
+        1 => "F16",
+        2 => "Q4_0",
+        3 => "Q4_1",
+        7 => "Q8_0",
+        8 => "Q5_0",
+        9 => "Q5_1",
+        10 => "Q2_K",
+        11 => "Q3_K_S",
+        12 => "Q3_K_M",
+        13 => "Q3_K_L",
+        14 => "Q4_K_S",
+        15 => "Q4_K_M",
+        16 => "Q5_K_S",
+        17 => "Q5_K_M",
+        18 => "Q6_K",
+        32 => "BF16",
+        _ => "unknown",
+    }
+}
+
+// 1100048384 -> "1.1B", 494032768 -> "494.03M"
+fn human_number(count: u64) -> String {
+    let (value, unit) = match count {
+        1_000_000_000.. => (count as f64 / 1e9, "B"),
+        1_000_000.. => (count as f64 / 1e6, "M"),
+        1_000.. => (count as f64 / 1e3, "K"),
+        _ => return count.to_string(),
+    };
+    let digits = format!("{:.2}", value);
+    format!(
+        "{}{}",
+        digits.trim_end_matches('0').trim_end_matches('.'),
+        unit
+    )
+}
+
+fn skip_value(reader: &mut impl Read, value_type: u32) -> Result<()> {
+    match value_type {
+        TYPE_STRING => {
+            let len = read_u64(reader)?;
+            skip(reader, len)
+        }
+        TYPE_ARRAY => {
+            let item_type = read_u32(reader)?;
+            let len = read_u64(reader)?;
+            if item_type == TYPE_STRING || item_type == TYPE_ARRAY {
+                for _ in 0..len {
+                    skip_value(reader, item_type)?;
+                }
+                Ok(())
+            } else {
+                let size = type_size(item_type)?;
+                skip(reader, len.saturating_mul(size))
+            }
+        }
+        _ => {
+            let size = type_size(value_type)?;
+            skip(reader, size)
+        }
+    }
+}
+
+fn type_size(value_type: u32) -> Result<u64> {
+    match TYPE_SIZES.get(value_type as usize) {
+        Some(&size) if size > 0 => Ok(size),
+        _ => bail!("Unknown GGUF value type {}", value_type),
+    }
+}
+
+fn skip(reader: &mut impl Read, len: u64) -> Result<()> {
+    let skipped = std::io::copy(&mut reader.take(len), &mut std::io::sink())?;
+    if skipped != len {
+        bail!("Unexpected end of file");
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = read_u64(reader)?;
+    let mut bytes = vec![];
+    reader.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        bail!("Unexpected end of file");
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// A GGUF header with one tensor of `shape` and no tensor data
+#[cfg(test)]
+pub fn test_header(architecture: &str, file_type: u32, shape: &[u64]) -> Vec<u8> {
+    fn string(bytes: &mut Vec<u8>, value: &str) {
+        bytes.extend((value.len() as u64).to_le_bytes());
+        bytes.extend(value.as_bytes());
+    }
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(3u32.to_le_bytes());
+    bytes.extend(1u64.to_le_bytes());
+    bytes.extend(3u64.to_le_bytes());
+    string(&mut bytes, "general.architecture");
+    bytes.extend(TYPE_STRING.to_le_bytes());
+    string(&mut bytes, architecture);
+    string(&mut bytes, "tokenizer.ggml.tokens");
+    bytes.extend(TYPE_ARRAY.to_le_bytes());
+    bytes.extend(TYPE_STRING.to_le_bytes());
+    bytes.extend(2u64.to_le_bytes());
+    string(&mut bytes, "<s>");
+    string(&mut bytes, "</s>");
+    string(&mut bytes, "general.file_type");
+    bytes.extend(TYPE_U32.to_le_bytes());
+    bytes.extend(file_type.to_le_bytes());
+    string(&mut bytes, "token_embd.weight");
+    bytes.extend((shape.len() as u32).to_le_bytes());
+    for dim in shape {
+        bytes.extend(dim.to_le_bytes());
+    }
+    bytes.extend(0u32.to_le_bytes());
+    bytes.extend(0u64.to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_info() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tiny.gguf");
+        std::fs::write(&path, test_header("llama", 15, &[32000, 34376]))?;
+        let info = read_info(&path)?;
+        assert_eq!(info.architecture, "llama");
+        assert_eq!(info.size, "1.1B");
+        assert_eq!(info.file_type, "Q4_K_M");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        std::fs::write(&path, b"{\"__metadata__\":{}}").unwrap();
+        let err = read_info(&path).err().unwrap();
+        assert!(err.to_string().contains("is not a GGUF file"));
+    }
+
+    #[test]
+    fn test_human_number() {
+        assert_eq!(human_number(494_032_768), "494.03M");
+        assert_eq!(human_number(7_000_000_000), "7B");
+        assert_eq!(human_number(999), "999");
+    }
+}
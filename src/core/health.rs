@@ -1,34 +1,1007 @@
 // Contains functions for health checking various dependencies
 
-use std::{env, process::Command};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeSet,
+    env,
+    fmt::Display,
+    fs::{self, File},
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, SystemTime},
+};
 
-//TODO: In future we can install the dependencies if not there..
-pub fn check_health() {
-    let os = env::consts::OS;
-    println!("Running diagnosis...");
-    check_python3();
-    if os == "macos" {
-        check_mlx_lm()
+use crate::core::{
+    alias::AliasTable,
+    config::{get_config_dir, get_data_dir, get_server_dir},
+    hf_cache::HfCache,
+    model_ref::{ModelRef, WeightsFormat, file_digest},
+    setup::ServerEnv,
+    token::TokenStore,
+};
+use crate::runner::{
+    mlx,
+    protocol::ServerInfo,
+    server::{self, Transport},
+};
+
+/// How a check came out, failures make `tiles health` exit non-zero
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// A remediation `tiles health --fix` can perform without asking
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fix {
+    /// `tiles setup`, which runs `uv sync` in the server project
+    SyncServer(PathBuf),
+    CreateDir(PathBuf),
+    RemoveFile(PathBuf),
+}
+
+impl Fix {
+    pub fn apply(&self) -> Result<()> {
+        match self {
+            Fix::SyncServer(server_dir) => {
+                ServerEnv::at(server_dir.clone()).setup(false).map(|_| ())
+            }
+            Fix::CreateDir(dir) => fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display())),
+            Fix::RemoveFile(path) => fs::remove_file(path)
+                .with_context(|| format!("Failed to remove {}", path.display())),
+        }
+    }
+}
+
+impl Display for Fix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fix::SyncServer(_) => write!(f, "tiles setup"),
+            Fix::CreateDir(dir) => write!(f, "mkdir -p {}", dir.display()),
+            Fix::RemoveFile(path) => write!(f, "rm {}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<Issue>,
+    #[serde(skip)]
+    pub fix: Option<Fix>,
+}
+
+impl Check {
+    pub fn new(name: &str, status: Status, detail: impl Into<String>) -> Check {
+        Check {
+            name: name.to_owned(),
+            status,
+            detail: detail.into(),
+            hint: None,
+            issues: vec![],
+            fix: None,
+        }
+    }
+
+    pub fn hint(mut self, hint: impl Into<String>) -> Check {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    fn fix(mut self, fix: Fix) -> Check {
+        self.fix = Some(fix);
+        self
+    }
+
+    /// Turns the result of [`check_model`] into a report entry
+    pub fn from_model(name: &str, check: &ModelCheck) -> Check {
+        if check.is_healthy() {
+            let location = match &check.path {
+                Some(path) => path.display().to_string(),
+                None => "not downloaded yet".to_owned(),
+            };
+            return Check::new(
+                name,
+                Status::Pass,
+                format!("{} ({})", check.model, location),
+            );
+        }
+        let mut fixes: Vec<&str> = vec![];
+        for issue in &check.issues {
+            if !fixes.contains(&issue.fix.as_str()) {
+                fixes.push(&issue.fix);
+            }
+        }
+        Check {
+            issues: check.issues.clone(),
+            ..Check::new(name, Status::Fail, check.model.clone()).hint(fixes.join(", "))
+        }
     }
 }
 
-fn check_python3() {
-    let output = Command::new("python3")
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub checks: Vec<Check>,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<Check>) -> HealthReport {
+        let healthy = checks.iter().all(|check| check.status != Status::Fail);
+        HealthReport { healthy, checks }
+    }
+
+    pub fn push(&mut self, check: Check) {
+        self.healthy &= check.status != Status::Fail;
+        self.checks.push(check);
+    }
+}
+
+const MIN_PYTHON: (u32, u32) = (3, 10);
+// A 4-bit 8B model is around 5GB
+const MIN_FREE_DISK: u64 = 1_000_000_000;
+const LOW_FREE_DISK: u64 = 10_000_000_000;
+
+/// Checks everything tiles needs on this machine
+pub fn check_health() -> HealthReport {
+    let mut checks = vec![check_python(), check_uv()];
+    checks.extend(check_server());
+    checks.push(check_transport());
+    checks.push(check_token());
+    checks.push(check_memory_dir());
+    checks.push(check_disk_space());
+    checks.push(check_backend());
+    HealthReport::new(checks)
+}
+
+fn check_python() -> Check {
+    let version = Command::new("python3")
         .arg("--version")
         .output()
         .ok()
         .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string());
+    let hint = "install Python 3.10 or newer for your OS";
+    match version {
+        None => Check::new("python", Status::Fail, "python3 not found").hint(hint),
+        Some(version) => match parse_python_version(&version) {
+            Some(parsed) if parsed >= MIN_PYTHON => Check::new("python", Status::Pass, version),
+            _ => Check::new(
+                "python",
+                Status::Fail,
+                format!("{} is older than 3.10", version),
+            )
+            .hint(hint),
+        },
+    }
+}
+
+fn parse_python_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.strip_prefix("Python ")?.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn check_uv() -> Check {
+    match Command::new("uv").arg("--version").output() {
+        Ok(output) if output.status.success() => Check::new(
+            "uv",
+            Status::Pass,
+            String::from_utf8_lossy(&output.stdout).trim(),
+        ),
+        _ => Check::new("uv", Status::Fail, "uv not found")
+            .hint("install it with `curl -LsSf https://astral.sh/uv/install.sh | sh`"),
+    }
+}
 
-    if let Some(version) = output {
-        println!("Python3: ✅ {}", version)
+// The server project and whether its venv matches uv.lock
+fn check_server() -> Vec<Check> {
+    let server_dir = match get_server_dir() {
+        Ok(server_dir) => server_dir,
+        Err(err) => return vec![Check::new("server", Status::Fail, err.to_string())],
+    };
+    if !server_dir.join("pyproject.toml").is_file() {
+        return vec![
+            Check::new(
+                "server",
+                Status::Fail,
+                format!("no server project in {}", server_dir.display()),
+            )
+            .hint("run `tiles setup`"),
+        ];
+    }
+    let installed = match ServerEnv::at(server_dir.clone()).stamp() {
+        Some(stamp) => format!("server {} in {}", stamp.version, server_dir.display()),
+        None => server_dir.display().to_string(),
+    };
+    let server = Check::new("server", Status::Pass, installed);
+
+    let venv = server_dir.join(".venv");
+    let lock_modified = modified(&server_dir.join("uv.lock"));
+    let venv_modified = modified(&venv.join("pyvenv.cfg"));
+    let venv_check = match (venv_modified, lock_modified) {
+        (None, _) => Check::new("venv", Status::Fail, "server venv is missing"),
+        (Some(venv), Some(lock)) if venv < lock => {
+            Check::new("venv", Status::Fail, "server venv is older than uv.lock")
+        }
+        _ if !ServerEnv::at(server_dir.clone()).is_ready() => Check::new(
+            "venv",
+            Status::Fail,
+            "server venv was not set up by `tiles setup`",
+        ),
+        _ => Check::new("venv", Status::Pass, venv.display().to_string()),
+    };
+    let venv_check = if venv_check.status == Status::Fail {
+        let fix = Fix::SyncServer(server_dir);
+        venv_check.hint(format!("run `{}`", fix)).fix(fix)
     } else {
-        println!("Python3: ❌ hint: Install Python3 for your OS")
+        venv_check
+    };
+    vec![server, venv_check]
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Other users must not be able to read the daemon's token
+fn check_token() -> Check {
+    let name = "token";
+    let store = match TokenStore::open() {
+        Ok(store) => store,
+        Err(err) => return Check::new(name, Status::Fail, err.to_string()),
+    };
+    match store.read() {
+        Ok(None) => Check::new(name, Status::Pass, "created by `tiles server start`"),
+        Ok(Some(_)) => match store.check_permissions() {
+            Ok(()) => Check::new(name, Status::Pass, store.path().display().to_string()),
+            Err(err) => Check::new(name, Status::Fail, err.to_string()),
+        },
+        Err(err) => Check::new(name, Status::Fail, format!("{:#}", err)),
+    }
+}
+
+/// The PID in server.pid, if that process is still alive
+pub fn daemon_pid() -> Option<String> {
+    get_config_dir()
+        .ok()
+        .and_then(|dir| fs::read_to_string(dir.join("server.pid")).ok())
+        .map(|pid| pid.trim().to_owned())
+        .filter(|pid| {
+            Command::new("kill")
+                .args(["-0", pid])
+                .output()
+                .is_ok_and(|output| output.status.success())
+        })
+}
+
+fn check_transport() -> Check {
+    match Transport::current() {
+        Ok(Transport::Unix(path)) => check_socket(&path),
+        Ok(Transport::Tcp(addr)) => check_port(&addr),
+        Err(err) => Check::new("transport", Status::Warn, err.to_string()),
+    }
+}
+
+// A socket left behind by a daemon that died would make the next start fail
+fn check_socket(path: &Path) -> Check {
+    let name = "socket";
+    if !path.exists() {
+        return Check::new(name, Status::Pass, format!("{} is free", path.display()));
+    }
+    match daemon_pid() {
+        Some(pid) => Check::new(
+            name,
+            Status::Pass,
+            format!(
+                "{} is used by the tiles server (PID {})",
+                path.display(),
+                pid
+            ),
+        ),
+        None => Check::new(
+            name,
+            Status::Warn,
+            format!("{} is left over from a stopped server", path.display()),
+        )
+        .fix(Fix::RemoveFile(path.to_path_buf())),
+    }
+}
+
+// The port is fine when free or held by the tiles server, which is told
+// apart from other processes by its answer on /version
+fn check_port(addr: &str) -> Check {
+    let name = "port";
+    if TcpListener::bind(addr).is_ok() {
+        return Check::new(name, Status::Pass, format!("{} is free", addr));
+    }
+    if let Some(info) = probe_version(addr) {
+        let pid = daemon_pid()
+            .map(|pid| format!(" (PID {})", pid))
+            .unwrap_or_default();
+        return Check::new(
+            name,
+            Status::Pass,
+            format!("{} is used by tiles server {}{}", addr, info.version, pid),
+        );
+    }
+    match daemon_pid() {
+        Some(pid) => Check::new(
+            name,
+            Status::Warn,
+            format!(
+                "{} is in use, but doesn't answer /version like the tiles server (PID {})",
+                addr, pid
+            ),
+        )
+        .hint("restart it with `tiles server stop` and `tiles server start`"),
+        None => Check::new(
+            name,
+            Status::Fail,
+            format!("{} is used by another process", addr),
+        )
+        .hint(format!(
+            "find it with `lsof -i :{}` and stop it",
+            addr.rsplit(':').next().unwrap_or(addr)
+        )),
+    }
+}
+
+// A plain HTTP/1.0 request, since the checks run outside the async runtime
+fn probe_version(addr: &str) -> Option<ServerInfo> {
+    let timeout = Duration::from_secs(2);
+    let socket = addr.to_socket_addrs().ok()?.next()?;
+    let mut stream = TcpStream::connect_timeout(&socket, timeout).ok()?;
+    stream.set_read_timeout(Some(timeout)).ok()?;
+    stream.set_write_timeout(Some(timeout)).ok()?;
+    let auth = TokenStore::open()
+        .and_then(|store| store.read())
+        .ok()
+        .flatten()
+        .map(|token| format!("Authorization: Bearer {}\r\n", token))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "GET /version HTTP/1.0\r\nHost: {}\r\n{}\r\n",
+        addr, auth
+    )
+    .ok()?;
+    let mut response = String::new();
+    stream.take(64 * 1024).read_to_string(&mut response).ok()?;
+    let (head, body) = response.split_once("\r\n\r\n")?;
+    if head.split_whitespace().nth(1) != Some("200") {
+        return None;
+    }
+    serde_json::from_str(body).ok()
+}
+
+/// Asks a running server for its version and checks this CLI can talk to it
+pub async fn check_server_version() -> Check {
+    let name = "server api";
+    let transport = match Transport::current() {
+        Ok(transport) => transport,
+        Err(err) => return Check::new(name, Status::Warn, err.to_string()),
+    };
+    let client = match transport
+        .client_builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => return Check::new(name, Status::Warn, err.to_string()),
+    };
+    match server::server_info(&transport, &client).await {
+        Err(err) if err.is_connect() => Check::new(name, Status::Pass, "server is not running"),
+        Err(err) if err.status() == Some(reqwest::StatusCode::UNAUTHORIZED) => {
+            Check::new(name, Status::Fail, "server rejected the token")
+                .hint("restart it with `tiles server stop` and `tiles server start`")
+        }
+        Err(err) => Check::new(
+            name,
+            Status::Warn,
+            format!("no answer on /version: {}", err),
+        ),
+        Ok(None) => Check::new(name, Status::Warn, "server predates version checks")
+            .hint("run `tiles setup` and restart the server"),
+        Ok(Some(info)) => match server::check_compatibility(&info) {
+            Ok(()) => Check::new(
+                name,
+                Status::Pass,
+                format!("server {} speaks API {}", info.version, info.api_version),
+            ),
+            Err(err) => Check::new(name, Status::Fail, err.to_string()),
+        },
+    }
+}
+
+fn check_memory_dir() -> Check {
+    let name = "memory";
+    let memory_dir = match mlx::configured_memory_path()
+        .transpose()
+        .unwrap_or_else(mlx::default_memory_path)
+    {
+        Ok(memory_dir) => memory_dir,
+        Err(err) => return Check::new(name, Status::Fail, err.to_string()),
+    };
+    if !memory_dir.is_dir() {
+        let fix = Fix::CreateDir(memory_dir.clone());
+        return Check::new(
+            name,
+            Status::Warn,
+            format!("{} does not exist yet", memory_dir.display()),
+        )
+        .hint(format!("run `{}`", fix))
+        .fix(fix);
+    }
+    let probe = memory_dir.join(".tiles_health");
+    match fs::write(&probe, b"").and_then(|_| fs::remove_file(&probe)) {
+        Ok(()) => Check::new(name, Status::Pass, memory_dir.display().to_string()),
+        Err(err) => Check::new(
+            name,
+            Status::Fail,
+            format!("{} is not writable: {}", memory_dir.display(), err),
+        )
+        .hint("fix its permissions or point .memory_path in the tiles config dir elsewhere"),
+    }
+}
+
+fn check_disk_space() -> Check {
+    let name = "disk";
+    let Ok(data_dir) = get_data_dir() else {
+        return Check::new(name, Status::Warn, "data dir unknown");
+    };
+    // df needs an existing path
+    let existing = data_dir.ancestors().find(|dir| dir.exists());
+    let available = existing.and_then(|dir| {
+        let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+        parse_df(&String::from_utf8_lossy(&output.stdout))
+    });
+    let Some(available) = available else {
+        return Check::new(name, Status::Warn, "could not determine free disk space");
+    };
+    let detail = format!("{:.1} GB free", available as f64 / 1e9);
+    let hint = "free up space, models need several GB each";
+    match available {
+        0..MIN_FREE_DISK => Check::new(name, Status::Fail, detail).hint(hint),
+        MIN_FREE_DISK..LOW_FREE_DISK => Check::new(name, Status::Warn, detail).hint(hint),
+        _ => Check::new(name, Status::Pass, detail),
     }
 }
 
-fn check_mlx_lm() {
+// Available bytes from the POSIX output of `df -Pk`
+fn parse_df(output: &str) -> Option<u64> {
+    let kilobytes: u64 = output
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+// mlx_lm only runs on Apple Silicon
+fn check_backend() -> Check {
+    let name = "mlx_lm";
+    if env::consts::OS != "macos" || env::consts::ARCH != "aarch64" {
+        return Check::new(
+            name,
+            Status::Warn,
+            format!(
+                "MLX needs macOS on Apple Silicon, not {}/{}",
+                env::consts::OS,
+                env::consts::ARCH
+            ),
+        );
+    }
     match Command::new("mlx_lm.chat").arg("--help").output() {
-        Ok(_) => println!("mlx_lm: ✅"),
-        _ => println!("mlx_lm: ❌ hint: run `pip install mlx-lm`"),
+        Ok(_) => Check::new(name, Status::Pass, "mlx_lm.chat found"),
+        _ => {
+            Check::new(name, Status::Fail, "mlx_lm.chat not found").hint("run `pip install mlx-lm`")
+        }
+    }
+}
+
+/// Something wrong with a model's files and how to fix it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Issue {
+    /// Path relative to the model directory
+    pub file: Option<String>,
+    pub problem: String,
+    pub fix: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelCheck {
+    pub model: String,
+    /// Where the files were checked, none when they are yet to be downloaded
+    pub path: Option<PathBuf>,
+    pub issues: Vec<Issue>,
+}
+
+impl ModelCheck {
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+const LFS_POINTER_HEADER: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+// Pointer files are around 130 bytes, anything this big holds real content
+const LFS_POINTER_MAX_SIZE: u64 = 200;
+
+/// Checks that the files behind a FROM reference are complete, with
+/// `sha256` also hashing weights against the digests they were stored under
+pub fn check_model(model_ref: &ModelRef, sha256: bool) -> ModelCheck {
+    let mut check = ModelCheck {
+        model: model_ref.to_string(),
+        path: None,
+        issues: vec![],
+    };
+    let re_download = |repo: &str| {
+        format!(
+            "delete it with `tiles models rm {} --force` and run again to re-download",
+            repo
+        )
+    };
+    match model_ref {
+        ModelRef::HuggingFace { repo, revision } => {
+            let snapshot = HfCache::open()
+                .ok()
+                .and_then(|cache| cache.snapshot_path(repo, revision.as_deref().unwrap_or("main")));
+            if let Some(snapshot) = snapshot {
+                check.issues = check_model_dir(&snapshot, sha256, &re_download(repo));
+                check.path = Some(snapshot);
+            }
+        }
+        ModelRef::OllamaTag { name, tag } => {
+            let name = format!("{}:{}", name, tag);
+            let target = AliasTable::load()
+                .ok()
+                .and_then(|aliases| aliases.lookup(&name))
+                .and_then(|alias| alias.mlx)
+                .and_then(|mlx| mlx.parse::<ModelRef>().ok())
+                .filter(|target| !matches!(target, ModelRef::OllamaTag { .. }));
+            match target {
+                Some(target) => {
+                    let target_check = check_model(&target, sha256);
+                    check.path = target_check.path;
+                    check.issues = target_check.issues;
+                }
+                None => check.issues.push(Issue {
+                    file: None,
+                    problem: format!("No MLX weights are known for {}", name),
+                    fix: format!("map it with `tiles alias add {} --mlx <repo>`", name),
+                }),
+            }
+        }
+        ModelRef::LocalDir { path } => {
+            check.path = Some(path.clone());
+            check.issues = if path.is_dir() {
+                check_model_dir(
+                    path,
+                    sha256,
+                    &format!(
+                        "run `git lfs pull` in {} or download it again",
+                        path.display()
+                    ),
+                )
+            } else {
+                vec![Issue {
+                    file: None,
+                    problem: format!("{} does not exist", path.display()),
+                    fix: "fix the path in FROM".to_owned(),
+                }]
+            };
+        }
+        ModelRef::LocalFile { path, format } => {
+            check.path = Some(path.clone());
+            let fix = "download the weights file again";
+            if !path.is_file() {
+                check.issues.push(Issue {
+                    file: None,
+                    problem: format!("{} does not exist", path.display()),
+                    fix: "fix the path in FROM".to_owned(),
+                });
+            } else if let Some(problem) = weights_problem(path) {
+                check.issues.push(Issue {
+                    file: None,
+                    problem,
+                    fix: fix.to_owned(),
+                });
+            } else if *format == WeightsFormat::Gguf && !has_magic(path, b"GGUF") {
+                check.issues.push(Issue {
+                    file: None,
+                    problem: "not a GGUF file".to_owned(),
+                    fix: fix.to_owned(),
+                });
+            }
+        }
+        ModelRef::OllamaBlob { path, .. } => {
+            check.path = Some(path.clone());
+            let result = if sha256 {
+                model_ref.validate()
+            } else if path.is_file() {
+                Ok(())
+            } else {
+                Err(format!("Ollama blob {} does not exist", path.display()))
+            };
+            if let Err(problem) = result {
+                check.issues.push(Issue {
+                    file: None,
+                    problem,
+                    fix: "pull the model again with `ollama pull`".to_owned(),
+                });
+            }
+        }
+    }
+    check
+}
+
+/// Checks a directory in the Hugging Face layout: config, tokenizer, weights
+/// and the shards listed in a safetensors index
+pub fn check_model_dir(dir: &Path, sha256: bool, fix: &str) -> Vec<Issue> {
+    let mut issues = vec![];
+    let mut issue = |file: Option<&str>, problem: String| {
+        issues.push(Issue {
+            file: file.map(str::to_owned),
+            problem,
+            fix: fix.to_owned(),
+        })
+    };
+    let files = list_files(dir);
+
+    match fs::read_to_string(dir.join("config.json"))
+        .ok()
+        .and_then(|config| serde_json::from_str::<Value>(&config).ok())
+    {
+        Some(Value::Object(config)) if !config.is_empty() => {}
+        Some(_) => issue(
+            Some("config.json"),
+            "config.json is empty or invalid".to_owned(),
+        ),
+        None => issue(Some("config.json"), "config.json is missing".to_owned()),
+    }
+    let has_tokenizer = ["tokenizer.json", "tokenizer.model", "tokenizer_config.json"]
+        .iter()
+        .any(|name| dir.join(name).is_file());
+    if !has_tokenizer {
+        issue(None, "no tokenizer files".to_owned());
+    }
+
+    for file in &files {
+        let name = file.to_lowercase();
+        if name.ends_with(".partial") || name.ends_with(".incomplete") || name.ends_with(".tmp") {
+            issue(Some(file), "download did not finish".to_owned());
+        } else if is_lfs_pointer(&dir.join(file)) {
+            issue(
+                Some(file),
+                "Git LFS pointer instead of the real file".to_owned(),
+            );
+        }
+    }
+
+    let index = dir.join("model.safetensors.index.json");
+    let is_shard = |file: &str| {
+        let name = file.rsplit('/').next().unwrap_or(file);
+        name.starts_with("model-") && name.contains("-of-") && name.ends_with(".safetensors")
+    };
+    if index.is_file() {
+        let index = fs::read_to_string(&index)
+            .ok()
+            .and_then(|index| serde_json::from_str::<Value>(&index).ok());
+        let shards: BTreeSet<&str> = index
+            .as_ref()
+            .and_then(|index| index.get("weight_map"))
+            .and_then(Value::as_object)
+            .map(|weight_map| weight_map.values().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        if shards.is_empty() {
+            issue(
+                Some("model.safetensors.index.json"),
+                "index lists no shards".to_owned(),
+            );
+        }
+        let mut shards_size = 0;
+        for shard in &shards {
+            match fs::metadata(dir.join(shard)) {
+                Ok(metadata) if metadata.len() > 0 => shards_size += metadata.len(),
+                Ok(_) => issue(Some(shard), "shard is empty".to_owned()),
+                Err(_) => issue(
+                    Some(shard),
+                    "shard listed in the index is missing".to_owned(),
+                ),
+            }
+        }
+        // Shards hold the tensors plus a header, so they can't be smaller
+        let total_size = index
+            .as_ref()
+            .and_then(|index| index.pointer("/metadata/total_size"))
+            .and_then(Value::as_u64);
+        if let Some(total_size) = total_size
+            && shards_size > 0
+            && shards_size < total_size
+        {
+            issue(
+                Some("model.safetensors.index.json"),
+                format!(
+                    "shards hold {} bytes but the index expects at least {}",
+                    shards_size, total_size
+                ),
+            );
+        }
+    } else if files.iter().any(|file| is_shard(file)) {
+        issue(
+            Some("model.safetensors.index.json"),
+            "sharded weights without an index".to_owned(),
+        );
+    } else if !files.iter().any(|file| {
+        file.ends_with(".safetensors") || file.ends_with(".bin") || file.ends_with(".gguf")
+    }) {
+        issue(None, "no weights files".to_owned());
+    }
+
+    if sha256 {
+        for file in &files {
+            if let Some(problem) = hash_problem(&dir.join(file)) {
+                issue(Some(file), problem);
+            }
+        }
+    }
+    issues
+}
+
+// The Hugging Face cache names blobs of LFS files after their sha256
+fn hash_problem(path: &Path) -> Option<String> {
+    let target = fs::canonicalize(path).ok()?;
+    let expected = target.file_name()?.to_string_lossy().to_string();
+    if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    match file_digest(&target) {
+        Ok(actual) if actual.trim_start_matches("sha256:") == expected => None,
+        Ok(actual) => Some(format!("sha256 is {} but should be {}", actual, expected)),
+        Err(err) => Some(format!("can't be read: {}", err)),
+    }
+}
+
+fn weights_problem(path: &Path) -> Option<String> {
+    let size = fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    if size == 0 {
+        Some("weights file is empty".to_owned())
+    } else if is_lfs_pointer(path) {
+        Some("Git LFS pointer instead of the real file".to_owned())
+    } else {
+        None
+    }
+}
+
+fn is_lfs_pointer(path: &Path) -> bool {
+    let size = fs::metadata(path).map(|metadata| metadata.len());
+    if !size.is_ok_and(|size| size < LFS_POINTER_MAX_SIZE) {
+        return false;
+    }
+    fs::read(path).is_ok_and(|content| {
+        content
+            .windows(LFS_POINTER_HEADER.len())
+            .any(|window| window == LFS_POINTER_HEADER)
+    })
+}
+
+fn has_magic(path: &Path, magic: &[u8]) -> bool {
+    let mut header = vec![0; magic.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| header == magic)
+}
+
+// File names relative to `dir`, following the symlinks snapshots are made of
+fn list_files(dir: &Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(&current).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(dir) {
+                files.push(relative.to_string_lossy().to_string());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn model_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let index = r#"{
+            "metadata": {"total_size": 8},
+            "weight_map": {"a": "model-00001-of-00002.safetensors", "b": "model-00002-of-00002.safetensors"}
+        }"#;
+        fs::write(dir.path().join("config.json"), r#"{"model_type": "llama"}"#).unwrap();
+        fs::write(dir.path().join("tokenizer.json"), "{}").unwrap();
+        fs::write(dir.path().join("model.safetensors.index.json"), index).unwrap();
+        fs::write(
+            dir.path().join("model-00001-of-00002.safetensors"),
+            b"tensors1",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("model-00002-of-00002.safetensors"),
+            b"tensors2",
+        )
+        .unwrap();
+        dir
+    }
+
+    fn problems(dir: &Path) -> Vec<(Option<String>, String)> {
+        check_model_dir(dir, false, "fix")
+            .into_iter()
+            .map(|issue| (issue.file, issue.problem))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_python_version() {
+        assert_eq!(parse_python_version("Python 3.11.7"), Some((3, 11)));
+        assert!(parse_python_version("Python 3.9.6").unwrap() < MIN_PYTHON);
+        assert_eq!(parse_python_version("command not found"), None);
+    }
+
+    #[test]
+    fn test_parse_df() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n/dev/disk3s5    482797652 350131180 114467512    76% /System/Volumes/Data\n";
+        assert_eq!(parse_df(output), Some(114467512 * 1024));
+        assert_eq!(parse_df(""), None);
+    }
+
+    // Answers one connection with `response` and returns the address
+    fn serve_once(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_port_held_by_the_server_passes() {
+        let addr = serve_once(
+            "HTTP/1.0 200 OK\r\ncontent-type: application/json\r\n\r\n{\"version\":\"0.4.0\",\"api_version\":2}",
+        );
+        let check = check_port(&addr);
+        assert_eq!(check.status, Status::Pass);
+        assert!(check.detail.contains("tiles server 0.4.0"));
+    }
+
+    #[test]
+    fn test_port_held_by_another_process_fails() {
+        let addr = serve_once("HTTP/1.0 200 OK\r\n\r\n<html>dev server</html>");
+        let check = check_port(&addr);
+        assert_ne!(check.status, Status::Pass);
+    }
+
+    #[test]
+    fn test_report_fails_on_any_failed_check() {
+        let mut report = HealthReport::new(vec![
+            Check::new("python", Status::Pass, "Python 3.12.1"),
+            Check::new("disk", Status::Warn, "5.0 GB free"),
+        ]);
+        assert!(report.healthy);
+        let model = ModelCheck {
+            model: "local directory /models/phi".to_owned(),
+            path: Some(PathBuf::from("/models/phi")),
+            issues: vec![Issue {
+                file: None,
+                problem: "no weights files".to_owned(),
+                fix: "download it again".to_owned(),
+            }],
+        };
+        report.push(Check::from_model("phi", &model));
+        assert!(!report.healthy);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["checks"][2]["status"], "fail");
+        assert_eq!(
+            json["checks"][2]["issues"][0]["problem"],
+            "no weights files"
+        );
+    }
+
+    #[test]
+    fn test_complete_model_is_healthy() {
+        let dir = model_dir();
+        assert!(problems(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_detects_lfs_pointers_and_missing_shards() {
+        let dir = model_dir();
+        let pointer = "version https://git-lfs.github.com/spec/v1\noid sha256:abc\nsize 123\n";
+        fs::write(dir.path().join("model-00001-of-00002.safetensors"), pointer).unwrap();
+        fs::remove_file(dir.path().join("model-00002-of-00002.safetensors")).unwrap();
+        let shard = |n: &str| Some(format!("model-0000{}-of-00002.safetensors", n));
+        assert_eq!(
+            problems(dir.path()),
+            vec![
+                (
+                    shard("1"),
+                    "Git LFS pointer instead of the real file".to_owned()
+                ),
+                (
+                    shard("2"),
+                    "shard listed in the index is missing".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detects_missing_config_tokenizer_and_index() {
+        let dir = model_dir();
+        fs::remove_file(dir.path().join("config.json")).unwrap();
+        fs::remove_file(dir.path().join("tokenizer.json")).unwrap();
+        fs::remove_file(dir.path().join("model.safetensors.index.json")).unwrap();
+        let problems: Vec<String> = problems(dir.path()).into_iter().map(|(_, p)| p).collect();
+        assert_eq!(
+            problems,
+            vec![
+                "config.json is missing",
+                "no tokenizer files",
+                "sharded weights without an index"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sha256_verification_of_cache_blobs() {
+        let dir = model_dir();
+        let content = b"tensors1";
+        let blob = dir.path().join(format!("{:x}", Sha256::digest(b"other")));
+        fs::write(&blob, content).unwrap();
+        let shard = dir.path().join("model-00001-of-00002.safetensors");
+        fs::remove_file(&shard).unwrap();
+        std::os::unix::fs::symlink(&blob, &shard).unwrap();
+        let issues = check_model_dir(dir.path(), true, "fix");
+        assert!(
+            issues
+                .iter()
+                .any(|issue| issue.problem.starts_with("sha256 is"))
+        );
+    }
+
+    #[test]
+    fn test_gguf_file_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        fs::write(&path, b"not gguf").unwrap();
+        let model_ref: ModelRef = path.to_string_lossy().parse().unwrap();
+        assert!(!check_model(&model_ref, false).is_healthy());
+        fs::write(&path, b"GGUF weights").unwrap();
+        assert!(check_model(&model_ref, false).is_healthy());
     }
 }
@@ -0,0 +1,541 @@
+// Browses the Hugging Face hub cache that mlx_lm and huggingface_hub download into
+//
+// Layout:
+// models--<org>--<name>/blobs/<etag>                 file content
+// models--<org>--<name>/snapshots/<commit>/<file>    symlinks into blobs
+// models--<org>--<name>/refs/<branch>                commit a branch points at
+// .locks/models--<org>--<name>/                      download locks
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
+
+use crate::core::config::get_hf_hub_dir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Framework {
+    #[serde(rename = "MLX")]
+    Mlx,
+    #[serde(rename = "GGUF")]
+    Gguf,
+    PyTorch,
+    Tokenizer,
+    Unknown,
+}
+
+impl FromStr for Framework {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mlx" => Ok(Framework::Mlx),
+            "gguf" => Ok(Framework::Gguf),
+            "pytorch" => Ok(Framework::PyTorch),
+            "tokenizer" => Ok(Framework::Tokenizer),
+            "unknown" => Ok(Framework::Unknown),
+            _ => Err(format!(
+                "Unknown framework `{}`, expected mlx, gguf, pytorch, tokenizer or unknown",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Framework {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Framework::Mlx => "MLX",
+            Framework::Gguf => "GGUF",
+            Framework::PyTorch => "PyTorch",
+            Framework::Tokenizer => "Tokenizer",
+            Framework::Unknown => "Unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedModel {
+    pub repo: String,
+    pub path: PathBuf,
+    /// Bytes on disk, blobs shared between snapshots are counted once
+    pub size: u64,
+    pub framework: Framework,
+    pub quantization: Option<String>,
+    /// Unix timestamp of the newest snapshot
+    pub modified: u64,
+    /// Branch or tag name to the commit it points at
+    pub refs: BTreeMap<String, String>,
+    pub snapshots: Vec<String>,
+}
+
+impl CachedModel {
+    /// The snapshot `main` points at, or the newest one
+    pub fn snapshot(&self) -> Option<PathBuf> {
+        let commit = self
+            .refs
+            .get("main")
+            .filter(|commit| self.snapshots.contains(commit))
+            .or_else(|| self.snapshots.last())?;
+        Some(self.path.join("snapshots").join(commit))
+    }
+}
+
+// Same cut-off server/cache_utils.py uses for repos without weights
+const TOKENIZER_ONLY_SIZE: u64 = 10_000_000;
+
+pub struct HfCache {
+    root: PathBuf,
+}
+
+impl HfCache {
+    /// The cache huggingface_hub uses on this machine
+    pub fn open() -> Result<HfCache> {
+        Ok(HfCache::at(get_hf_hub_dir()?))
+    }
+
+    pub fn at(root: impl Into<PathBuf>) -> HfCache {
+        HfCache { root: root.into() }
+    }
+
+    pub fn list(&self) -> Result<Vec<CachedModel>> {
+        let mut models = vec![];
+        if !self.root.is_dir() {
+            return Ok(models);
+        }
+        for entry in fs::read_dir(&self.root)?.flatten() {
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(repo) = repo_name(&dir_name)
+                && entry.path().is_dir()
+            {
+                models.push(self.read_model(&repo, entry.path())?);
+            }
+        }
+        models.sort_by(|a, b| a.repo.to_lowercase().cmp(&b.repo.to_lowercase()));
+        Ok(models)
+    }
+
+    /// Model cached under exactly `repo`, for commands that delete it
+    pub fn get(&self, repo: &str) -> Result<CachedModel> {
+        if validate_repo(repo).is_ok() && self.model_dir(repo).is_dir() {
+            return self.read_model(repo, self.model_dir(repo));
+        }
+        match self.find(repo) {
+            Ok(model) => bail!("No cached model `{}`, did you mean `{}`?", repo, model.repo),
+            Err(_) => bail!("No cached model `{}`, see `tiles models ls`", repo),
+        }
+    }
+
+    /// Finds a model by its exact repo name or a unique case-insensitive part of it
+    pub fn find(&self, pattern: &str) -> Result<CachedModel> {
+        if validate_repo(pattern).is_ok() && self.model_dir(pattern).is_dir() {
+            return self.read_model(pattern, self.model_dir(pattern));
+        }
+        let pattern = pattern.to_lowercase();
+        let mut matches: Vec<CachedModel> = self
+            .list()?
+            .into_iter()
+            .filter(|model| model.repo.to_lowercase().contains(&pattern))
+            .collect();
+        match matches.len() {
+            0 => bail!("No cached model matches `{}`", pattern),
+            1 => Ok(matches.remove(0)),
+            _ => bail!(
+                "Multiple cached models match `{}`, be more specific:\n  {}",
+                pattern,
+                matches
+                    .iter()
+                    .map(|model| model.repo.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n  ")
+            ),
+        }
+    }
+
+    /// Snapshot dir for `revision` of `repo`, which may be a commit, a short
+    /// commit prefix or a ref such as `main`
+    pub fn snapshot_path(&self, repo: &str, revision: &str) -> Option<PathBuf> {
+        let model_dir = self.model_dir(repo);
+        let commit = fs::read_to_string(model_dir.join("refs").join(revision))
+            .map(|commit| commit.trim().to_owned())
+            .unwrap_or_else(|_| revision.to_owned());
+        let snapshots = model_dir.join("snapshots");
+        let exact = snapshots.join(&commit);
+        if exact.is_dir() {
+            return Some(exact);
+        }
+        fs::read_dir(&snapshots)
+            .ok()?
+            .flatten()
+            .find(|entry| entry.file_name().to_string_lossy().starts_with(&commit))
+            .map(|entry| entry.path())
+    }
+
+    /// Deletes the model and its download locks, returning the bytes freed
+    pub fn remove(&self, model: &CachedModel) -> Result<u64> {
+        fs::remove_dir_all(&model.path)
+            .with_context(|| format!("Failed to delete {}", model.path.display()))?;
+        let locks = self.root.join(".locks").join(cache_dir_name(&model.repo));
+        if locks.is_dir() {
+            fs::remove_dir_all(&locks)
+                .with_context(|| format!("Failed to delete locks in {}", locks.display()))?;
+        }
+        Ok(model.size)
+    }
+
+    fn model_dir(&self, repo: &str) -> PathBuf {
+        self.root.join(cache_dir_name(repo))
+    }
+
+    fn read_model(&self, repo: &str, path: PathBuf) -> Result<CachedModel> {
+        let mut refs = BTreeMap::new();
+        if let Ok(entries) = fs::read_dir(path.join("refs")) {
+            for entry in entries.flatten() {
+                if let Ok(commit) = fs::read_to_string(entry.path()) {
+                    refs.insert(
+                        entry.file_name().to_string_lossy().to_string(),
+                        commit.trim().to_owned(),
+                    );
+                }
+            }
+        }
+
+        // Oldest first, so the last one is the newest download
+        let mut snapshots: Vec<(String, u64)> = fs::read_dir(path.join("snapshots"))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().is_dir())
+                    .map(|entry| {
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        (name, modified_time(&entry.path()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        snapshots.sort_by_key(|(_, modified)| *modified);
+
+        let mut model = CachedModel {
+            repo: repo.to_owned(),
+            size: dir_size(&path),
+            framework: Framework::Unknown,
+            quantization: None,
+            modified: snapshots.last().map(|(_, modified)| *modified).unwrap_or(0),
+            refs,
+            snapshots: snapshots.into_iter().map(|(name, _)| name).collect(),
+            path,
+        };
+        if let Some(snapshot) = model.snapshot() {
+            let files = snapshot_files(&snapshot);
+            model.framework = detect_framework(&model, &snapshot, &files);
+            model.quantization = detect_quantization(&model.repo, &snapshot, &files);
+        }
+        Ok(model)
+    }
+}
+
+// `org/name` <-> `models--org--name`
+fn cache_dir_name(repo: &str) -> String {
+    format!("models--{}", repo.replace('/', "--"))
+}
+
+fn repo_name(dir_name: &str) -> Option<String> {
+    let rest = dir_name.strip_prefix("models--")?;
+    Some(match rest.split_once("--") {
+        Some((org, name)) => format!("{}/{}", org, name),
+        None => rest.to_owned(),
+    })
+}
+
+fn detect_framework(model: &CachedModel, snapshot: &Path, files: &[String]) -> Framework {
+    if model.repo.starts_with("mlx-community/") || readme_mentions_mlx(snapshot) {
+        return Framework::Mlx;
+    }
+    let has = |suffix: &str| files.iter().any(|file| file.ends_with(suffix));
+    if has(".gguf") {
+        Framework::Gguf
+    } else if model.size < TOKENIZER_ONLY_SIZE {
+        Framework::Tokenizer
+    } else if (has(".safetensors") && has("config.json")) || has("pytorch_model.bin") {
+        Framework::PyTorch
+    } else {
+        Framework::Unknown
+    }
+}
+
+// Model cards of MLX conversions have `library_name: mlx` or an `mlx` tag
+fn readme_mentions_mlx(snapshot: &Path) -> bool {
+    let Ok(readme) = fs::read_to_string(snapshot.join("README.md")) else {
+        return false;
+    };
+    let Some(front_matter) = readme
+        .strip_prefix("---")
+        .and_then(|rest| rest.split_once("\n---"))
+        .map(|(front_matter, _)| front_matter)
+    else {
+        return false;
+    };
+    let Ok(Value::Object(card)) = serde_yaml::from_str::<Value>(front_matter) else {
+        return false;
+    };
+    let library_is_mlx = card
+        .get("library_name")
+        .and_then(Value::as_str)
+        .is_some_and(|library| library.eq_ignore_ascii_case("mlx"));
+    let tagged_mlx = card
+        .get("tags")
+        .and_then(Value::as_array)
+        .is_some_and(|tags| {
+            tags.iter()
+                .filter_map(Value::as_str)
+                .any(|tag| tag.eq_ignore_ascii_case("mlx"))
+        });
+    library_is_mlx || tagged_mlx
+}
+
+const GGUF_QUANTIZATIONS: &[&str] = &[
+    "q2_k", "q3_k_s", "q3_k_m", "q3_k_l", "q3_k", "q4_0", "q4_1", "q4_k_s", "q4_k_m", "q4_k",
+    "q5_0", "q5_1", "q5_k_s", "q5_k_m", "q5_k", "q6_k", "q8_0", "f16", "bf16",
+];
+
+fn detect_quantization(repo: &str, snapshot: &Path, files: &[String]) -> Option<String> {
+    let config = fs::read_to_string(snapshot.join("config.json"))
+        .ok()
+        .and_then(|config| serde_json::from_str::<Value>(&config).ok());
+    if let Some(quantization) = config
+        .as_ref()
+        .and_then(|config| config.get("quantization"))
+    {
+        let bits = quantization.get("bits").and_then(Value::as_u64);
+        let group_size = quantization.get("group_size").and_then(Value::as_u64);
+        match (bits, group_size) {
+            (Some(bits), Some(group_size)) => {
+                return Some(format!("{}-bit (group size {})", bits, group_size));
+            }
+            (Some(bits), None) => return Some(format!("{}-bit", bits)),
+            _ => {}
+        }
+    }
+
+    let mut variants: Vec<String> = files
+        .iter()
+        .filter(|file| file.ends_with(".gguf"))
+        .filter_map(|file| {
+            let file = file.to_lowercase();
+            GGUF_QUANTIZATIONS
+                .iter()
+                .find(|quantization| file.contains(*quantization))
+                .map(|quantization| quantization.to_uppercase())
+        })
+        .collect();
+    variants.sort_unstable();
+    variants.dedup();
+    if !variants.is_empty() {
+        return Some(variants.join(", "));
+    }
+
+    let repo = repo.to_lowercase();
+    ["2bit", "3bit", "4bit", "6bit", "8bit"]
+        .iter()
+        .find(|bits| repo.contains(*bits))
+        .map(|bits| format!("{}-bit", &bits[..1]))
+}
+
+// File names relative to the snapshot dir
+fn snapshot_files(snapshot: &Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut dirs = vec![snapshot.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(relative) = path.strip_prefix(snapshot) {
+                files.push(relative.to_string_lossy().to_string());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+// Symlinks are not followed, so blobs are only counted once
+fn dir_size(path: &Path) -> u64 {
+    let mut size = 0;
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let Ok(metadata) = fs::symlink_metadata(entry.path()) else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if metadata.is_file() {
+                size += metadata.len();
+            }
+        }
+    }
+    size
+}
+
+fn modified_time(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// Rejects anything that could point outside the cache dir
+fn validate_repo(repo: &str) -> Result<&str> {
+    let is_valid = !repo.is_empty()
+        && repo.split('/').count() <= 2
+        && repo.split('/').all(|part| {
+            !part.is_empty()
+                && !part.starts_with('.')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        });
+    if is_valid {
+        Ok(repo)
+    } else {
+        Err(anyhow!("Invalid Hugging Face repo `{}`", repo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    // Writes a snapshot file the way huggingface_hub does, as a symlink into blobs
+    fn add_file(root: &Path, repo: &str, name: &str, content: &[u8]) {
+        let model_dir = root.join(cache_dir_name(repo));
+        let blob = model_dir.join("blobs").join(format!("blob-{}", name));
+        let snapshot = model_dir.join("snapshots").join(COMMIT);
+        fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        fs::create_dir_all(&snapshot).unwrap();
+        fs::create_dir_all(model_dir.join("refs")).unwrap();
+        fs::write(&blob, content).unwrap();
+        std::os::unix::fs::symlink(&blob, snapshot.join(name)).unwrap();
+        fs::write(model_dir.join("refs").join("main"), COMMIT).unwrap();
+    }
+
+    fn fake_cache() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        add_file(
+            root,
+            "mlx-community/Llama-3.2-1B-Instruct-4bit",
+            "config.json",
+            br#"{"quantization": {"bits": 4, "group_size": 64}}"#,
+        );
+        add_file(
+            root,
+            "mlx-community/Llama-3.2-1B-Instruct-4bit",
+            "model.safetensors",
+            b"weights",
+        );
+        add_file(
+            root,
+            "TheBloke/TinyLlama-GGUF",
+            "tinyllama.Q4_K_M.gguf",
+            b"GGUF",
+        );
+        add_file(root, "bert-base/tokenizer", "tokenizer.json", b"{}");
+        fs::create_dir_all(root.join(".locks").join("models--TheBloke--TinyLlama-GGUF")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_list_detects_framework_and_quantization() -> Result<()> {
+        let dir = fake_cache();
+        let models = HfCache::at(dir.path()).list()?;
+        let summary: Vec<(&str, Framework, Option<&str>)> = models
+            .iter()
+            .map(|m| (m.repo.as_str(), m.framework, m.quantization.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("bert-base/tokenizer", Framework::Tokenizer, None),
+                (
+                    "mlx-community/Llama-3.2-1B-Instruct-4bit",
+                    Framework::Mlx,
+                    Some("4-bit (group size 64)")
+                ),
+                ("TheBloke/TinyLlama-GGUF", Framework::Gguf, Some("Q4_K_M")),
+            ]
+        );
+        let mlx = &models[1];
+        assert_eq!(mlx.refs.get("main").map(String::as_str), Some(COMMIT));
+        let config = r#"{"quantization": {"bits": 4, "group_size": 64}}"#;
+        assert_eq!(
+            mlx.size as usize,
+            config.len() + "weights".len() + COMMIT.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_and_snapshot_path() -> Result<()> {
+        let dir = fake_cache();
+        let cache = HfCache::at(dir.path());
+        assert_eq!(cache.find("tinyllama")?.repo, "TheBloke/TinyLlama-GGUF");
+        assert!(cache.find("l").is_err());
+        assert!(cache.find("missing").is_err());
+        assert_eq!(
+            cache.get("TheBloke/TinyLlama-GGUF")?.repo,
+            "TheBloke/TinyLlama-GGUF"
+        );
+        let err = cache.get("tinyllama").unwrap_err().to_string();
+        assert!(
+            err.contains("did you mean `TheBloke/TinyLlama-GGUF`"),
+            "{}",
+            err
+        );
+        let repo = "mlx-community/Llama-3.2-1B-Instruct-4bit";
+        let snapshot = dir
+            .path()
+            .join(cache_dir_name(repo))
+            .join("snapshots")
+            .join(COMMIT);
+        assert_eq!(cache.snapshot_path(repo, "main"), Some(snapshot.clone()));
+        assert_eq!(cache.snapshot_path(repo, &COMMIT[..7]), Some(snapshot));
+        assert_eq!(cache.snapshot_path(repo, "dev"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_cleans_up_locks() -> Result<()> {
+        let dir = fake_cache();
+        let cache = HfCache::at(dir.path());
+        let model = cache.find("TheBloke/TinyLlama-GGUF")?;
+        assert_eq!(cache.remove(&model)? as usize, "GGUF".len() + COMMIT.len());
+        assert!(!model.path.exists());
+        assert!(
+            !dir.path()
+                .join(".locks/models--TheBloke--TinyLlama-GGUF")
+                .exists()
+        );
+        assert_eq!(cache.list()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_repo() {
+        assert!(validate_repo("mlx-community/gemma").is_ok());
+        assert!(validate_repo("../etc").is_err());
+        assert!(validate_repo("a/b/c").is_err());
+    }
+}
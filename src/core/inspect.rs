@@ -0,0 +1,207 @@
+// Summarizes the effective configuration of a Modelfile for `tiles show`
+
+use serde::Serialize;
+
+use crate::core::{
+    model_ref::ModelRef,
+    modelfile::{Modelfile, Overrides},
+};
+use crate::runner::Backend;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    File,
+    Default,
+    Override,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParameterInfo {
+    pub name: String,
+    pub value: String,
+    pub source: Source,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LicenseSummary {
+    pub title: String,
+    pub lines: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelfileInfo {
+    pub model: String,
+    pub reference: Option<ModelRef>,
+    /// What the backend will actually load, after aliases
+    pub resolved: Option<String>,
+    pub backend: Backend,
+    pub adapter: Option<String>,
+    pub parameters: Vec<ParameterInfo>,
+    pub system: Option<String>,
+    pub template: Option<String>,
+    pub template_variables: Vec<String>,
+    pub message_count: usize,
+    pub license: Option<LicenseSummary>,
+    /// Settings the backend ignores
+    pub warnings: Vec<String>,
+}
+
+/// Builds the summary for a resolved Modelfile that already has `overrides`
+/// applied. Parameters the backend would fall back on are listed as defaults.
+pub fn inspect(modelfile: &Modelfile, overrides: &Overrides) -> ModelfileInfo {
+    let model = modelfile.from.clone().unwrap_or_default();
+    let backend = Backend::for_model(&model);
+
+    let mut parameters: Vec<ParameterInfo> = modelfile
+        .parameters
+        .iter()
+        .map(|parameter| {
+            let overridden = overrides
+                .parameters
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case(&parameter.param_type));
+            ParameterInfo {
+                name: parameter.param_type.clone(),
+                value: parameter.value.to_string(),
+                source: if overridden {
+                    Source::Override
+                } else {
+                    Source::File
+                },
+            }
+        })
+        .collect();
+    for capability in backend.capabilities() {
+        let is_set = parameters.iter().any(|p| p.name == capability.parameter);
+        if let (false, Some(default)) = (is_set, capability.default) {
+            parameters.push(ParameterInfo {
+                name: capability.parameter.to_owned(),
+                value: default.to_owned(),
+                source: Source::Default,
+            });
+        }
+    }
+
+    let reference = modelfile.model_ref().ok();
+    ModelfileInfo {
+        resolved: reference
+            .as_ref()
+            .and_then(|reference| backend.model_argument(reference).ok()),
+        reference,
+        model,
+        backend,
+        adapter: modelfile.adapter.clone(),
+        parameters,
+        system: modelfile.system.clone(),
+        template: modelfile.template.clone(),
+        template_variables: modelfile
+            .template
+            .as_deref()
+            .map(template_variables)
+            .unwrap_or_default(),
+        message_count: modelfile.messages.len(),
+        license: modelfile.license.as_deref().map(summarize_license),
+        warnings: backend.warnings(modelfile),
+    }
+}
+
+/// Lists the top level fields (`.System`, `.Prompt`...) a Go template reads
+pub fn template_variables(template: &str) -> Vec<String> {
+    let mut variables: Vec<String> = vec![];
+    for action in template.split("{{").skip(1) {
+        let action = action.split("}}").next().unwrap_or_default();
+        let mut rest = action;
+        while let Some(index) = rest.find('.') {
+            let before = rest[..index].chars().last();
+            rest = &rest[index + 1..];
+            if before.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+                continue;
+            }
+            let name: String = rest
+                .chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect();
+            let variable = format!(".{}", name);
+            if !name.is_empty() && !variables.contains(&variable) {
+                variables.push(variable);
+            }
+        }
+    }
+    variables.sort();
+    variables
+}
+
+fn summarize_license(license: &str) -> LicenseSummary {
+    LicenseSummary {
+        title: license
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_owned(),
+        lines: license.lines().count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::modelfile::{parse, parse_from_file};
+
+    #[test]
+    fn test_template_variables() {
+        let template = "{{ if .System }}{{ .System }}{{ end }}{{ .Prompt }} {{- range $i, $_ := .Messages }}{{ $.Tools }}{{ .Function.Name }}{{ end }}";
+        assert_eq!(
+            template_variables(template),
+            vec![".Function", ".Messages", ".Prompt", ".System", ".Tools"]
+        );
+    }
+
+    #[test]
+    fn test_inspect_reports_parameter_sources() -> Result<(), String> {
+        let overrides = Overrides {
+            parameters: vec![("temperature".to_owned(), "0.1".to_owned())],
+            system: None,
+        };
+        let modelfile = parse_from_file("fixtures/a.modelfile")?.apply_overrides(&overrides)?;
+        let info = inspect(&modelfile, &overrides);
+        assert_eq!(info.backend, Backend::MlxChat);
+        assert_eq!(info.message_count, 6);
+        let source = |name: &str| {
+            info.parameters
+                .iter()
+                .find(|p| p.name == name)
+                .map(|p| p.source)
+        };
+        assert_eq!(source("stop"), Some(Source::File));
+        assert_eq!(source("temperature"), Some(Source::Override));
+        assert_eq!(source("top_p"), Some(Source::Default));
+        assert!(info.warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_alive_only_applies_to_the_server() -> Result<(), String> {
+        let modelfile = parse("FROM llama3.2\nPARAMETER keep_alive 10m")?;
+        let info = inspect(&modelfile, &Overrides::default());
+        assert_eq!(info.warnings.len(), 1);
+        assert!(info.warnings[0].contains("keep_alive has no effect with mlx_lm.chat"));
+        let modelfile = parse("FROM driaforall/mem-agent\nPARAMETER keep_alive 10m")?;
+        assert!(
+            inspect(&modelfile, &Overrides::default())
+                .warnings
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_license_summary() -> Result<(), String> {
+        let modelfile = parse_from_file("fixtures/mistral.modelfile")?;
+        let info = inspect(&modelfile, &Overrides::default());
+        let license = info.license.unwrap();
+        assert_eq!(license.title, "Apache License");
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+// Sets up `tracing` output on stderr, filtered by `$TILES_LOG`
+// (e.g. `TILES_LOG=tiles=debug`)
+
+use std::{
+    fmt::Display,
+    io::{self, IsTerminal},
+    str::FromStr,
+};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line with the fields of enclosing spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Unknown log format `{}`, expected text or json",
+                value
+            )),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// `default_level` applies when `$TILES_LOG` is unset, interactive commands
+/// keep it at warn so logs don't interleave with their output
+pub fn init(format: LogFormat, default_level: &str) {
+    let filter = EnvFilter::try_from_env("TILES_LOG")
+        .unwrap_or_else(|_| EnvFilter::new(format!("tiles={}", default_level)));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr);
+    let _ = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init(),
+    };
+}
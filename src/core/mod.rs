@@ -1,2 +1,16 @@
+pub mod alias;
+pub mod config;
+pub mod convert;
+pub mod gguf;
 pub mod health;
+pub mod hf_cache;
+pub mod inspect;
+pub mod logging;
+pub mod model_ref;
 pub mod modelfile;
+pub mod ollama;
+pub mod registry;
+pub mod settings;
+pub mod setup;
+pub mod template;
+pub mod token;
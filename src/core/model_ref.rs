@@ -0,0 +1,409 @@
+// Structured form of the model a Modelfile's FROM points at
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    fmt::Display,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::core::{config::get_ollama_models_dir, registry::ModelName};
+
+/// Names a registered Modelfile to inherit from rather than a model
+const MODELFILE_SCHEME: &str = "modelfile:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightsFormat {
+    Gguf,
+    Safetensors,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ModelRef {
+    /// `org/name` or `org/name@revision`
+    HuggingFace {
+        repo: String,
+        revision: Option<String>,
+    },
+    /// `name:tag`, optionally namespaced like `x/llama3.2-vision:latest`
+    OllamaTag {
+        name: String,
+        tag: String,
+    },
+    /// A `sha256-<hex>` blob from an Ollama models dir
+    OllamaBlob {
+        path: PathBuf,
+        digest: String,
+    },
+    LocalDir {
+        path: PathBuf,
+    },
+    LocalFile {
+        path: PathBuf,
+        format: WeightsFormat,
+    },
+}
+
+impl FromStr for ModelRef {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ModelRef::parse_in(s, Path::new(""))
+    }
+}
+
+impl ModelRef {
+    /// Parses a FROM with relative paths taken from `base_dir`, the
+    /// Modelfile's directory, instead of the working directory
+    pub fn parse_in(s: &str, base_dir: &Path) -> Result<ModelRef, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("FROM needs a model reference".to_owned());
+        }
+        if s.chars().any(char::is_whitespace) {
+            return Err(format!("Invalid model reference `{}`", s));
+        }
+        if s.starts_with(MODELFILE_SCHEME) {
+            return Err(format!(
+                "`{}` is a registered Modelfile, its model is known once it is resolved",
+                s
+            ));
+        }
+
+        if let Some(digest) = blob_digest(s) {
+            let path = get_ollama_models_dir()
+                .map_err(|err| err.to_string())?
+                .join("blobs")
+                .join(digest.replace(':', "-"));
+            return Ok(ModelRef::OllamaBlob { path, digest });
+        }
+
+        if let Some(path) = anchored_path(s, base_dir) {
+            return Ok(parse_path(&path));
+        }
+        if s.starts_with('/') || s.starts_with("~/") {
+            return Ok(parse_path(&expand_home(s)));
+        }
+
+        if s.contains(':') {
+            let (name, tag) = s.split_once(':').unwrap_or_default();
+            let is_valid = name.split('/').all(is_valid_segment) && is_valid_segment(tag);
+            return if is_valid && name.split('/').count() <= 2 {
+                Ok(ModelRef::OllamaTag {
+                    name: name.to_owned(),
+                    tag: tag.to_owned(),
+                })
+            } else {
+                Err(format!("Invalid Ollama model name `{}`", s))
+            };
+        }
+
+        if s.contains('/') {
+            let (repo, revision) = match s.split_once('@') {
+                Some((repo, revision)) => (repo, Some(revision)),
+                None => (s, None),
+            };
+            let parts: Vec<&str> = repo.split('/').collect();
+            let is_valid = parts.len() == 2
+                && parts.iter().all(|part| is_valid_segment(part))
+                && revision.is_none_or(is_valid_segment);
+            return if is_valid {
+                Ok(ModelRef::HuggingFace {
+                    repo: repo.to_owned(),
+                    revision: revision.map(str::to_owned),
+                })
+            } else {
+                Err(format!(
+                    "Invalid Hugging Face repo `{}`, expected org/name[@revision]",
+                    s
+                ))
+            };
+        }
+
+        if is_valid_segment(s) {
+            Ok(ModelRef::OllamaTag {
+                name: s.to_owned(),
+                tag: "latest".to_owned(),
+            })
+        } else {
+            Err(format!("Invalid model reference `{}`", s))
+        }
+    }
+}
+
+impl Display for ModelRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelRef::HuggingFace {
+                repo,
+                revision: Some(revision),
+            } => write!(f, "Hugging Face repo {}@{}", repo, revision),
+            ModelRef::HuggingFace { repo, .. } => write!(f, "Hugging Face repo {}", repo),
+            ModelRef::OllamaTag { name, tag } => write!(f, "Ollama model {}:{}", name, tag),
+            ModelRef::OllamaBlob { path, .. } => write!(f, "Ollama blob {}", path.display()),
+            ModelRef::LocalDir { path } => write!(f, "local directory {}", path.display()),
+            ModelRef::LocalFile { path, format } => {
+                let format = match format {
+                    WeightsFormat::Gguf => "GGUF",
+                    WeightsFormat::Safetensors => "safetensors",
+                };
+                write!(f, "local {} file {}", format, path.display())
+            }
+        }
+    }
+}
+
+impl ModelRef {
+    /// Checks that a local reference exists and that blobs match their digest
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ModelRef::HuggingFace { .. } | ModelRef::OllamaTag { .. } => Ok(()),
+            ModelRef::LocalDir { path } => {
+                if path.is_dir() {
+                    Ok(())
+                } else {
+                    Err(format!("Model directory {} does not exist", path.display()))
+                }
+            }
+            ModelRef::LocalFile { path, .. } => {
+                if path.is_file() {
+                    Ok(())
+                } else {
+                    Err(format!("Model file {} does not exist", path.display()))
+                }
+            }
+            ModelRef::OllamaBlob { path, digest } => {
+                if !path.is_file() {
+                    return Err(format!("Ollama blob {} does not exist", path.display()));
+                }
+                let actual = file_digest(path)
+                    .map_err(|err| format!("Reading {} failed due to {}", path.display(), err))?;
+                if &actual == digest {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Ollama blob {} is corrupt: expected {} but found {}",
+                        path.display(),
+                        digest,
+                        actual
+                    ))
+                }
+            }
+        }
+    }
+
+    /// The path on disk for local references
+    pub fn local_path(&self) -> Option<&Path> {
+        match self {
+            ModelRef::OllamaBlob { path, .. }
+            | ModelRef::LocalDir { path }
+            | ModelRef::LocalFile { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// `s` joined onto `base_dir` when it is written as a relative path: it
+/// starts with `./` or `../`, or names a `.gguf` or `.safetensors` file. A
+/// bare name stays a model name even if something of that name exists in
+/// `base_dir`
+pub fn anchored_path(s: &str, base_dir: &Path) -> Option<PathBuf> {
+    let s = s.trim();
+    let has_weights_extension = Path::new(s).extension().is_some_and(|ext| {
+        ext.eq_ignore_ascii_case("gguf") || ext.eq_ignore_ascii_case("safetensors")
+    });
+    let is_relative = s.starts_with("./")
+        || s.starts_with("../")
+        || (has_weights_extension
+            && !s.starts_with('/')
+            && !s.starts_with('~')
+            && !s.contains(':'));
+    is_relative.then(|| base_dir.join(s.strip_prefix("./").unwrap_or(s)))
+}
+
+/// The registered Modelfile of a `FROM modelfile:name[:tag]`, None for any
+/// other FROM
+pub fn registered_modelfile(from: &str) -> Option<Result<ModelName, String>> {
+    from.trim().strip_prefix(MODELFILE_SCHEME).map(str::parse)
+}
+
+/// sha256 of a file in the `sha256:<hex>` form Ollama uses
+pub fn file_digest(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+fn parse_path(path: &Path) -> ModelRef {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if let Some(digest) = blob_digest(&file_name) {
+        return ModelRef::OllamaBlob {
+            path: path.to_path_buf(),
+            digest,
+        };
+    }
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("gguf") => ModelRef::LocalFile {
+            path: path.to_path_buf(),
+            format: WeightsFormat::Gguf,
+        },
+        Some("safetensors") => ModelRef::LocalFile {
+            path: path.to_path_buf(),
+            format: WeightsFormat::Safetensors,
+        },
+        _ => ModelRef::LocalDir {
+            path: path.to_path_buf(),
+        },
+    }
+}
+
+// `sha256-<hex>` or `sha256:<hex>` normalized to `sha256:<hex>`
+fn blob_digest(s: &str) -> Option<String> {
+    let hex = s
+        .strip_prefix("sha256-")
+        .or_else(|| s.strip_prefix("sha256:"))?;
+    if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(format!("sha256:{}", hex.to_lowercase()))
+    } else {
+        None
+    }
+}
+
+fn expand_home(s: &str) -> PathBuf {
+    match (s.strip_prefix("~/"), env::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(s),
+    }
+}
+
+fn is_valid_segment(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn model_ref(s: &str) -> ModelRef {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_hugging_face_refs() {
+        assert_eq!(
+            model_ref("mlx-community/dolphin3.0-llama3.2-1B-4Bit"),
+            ModelRef::HuggingFace {
+                repo: "mlx-community/dolphin3.0-llama3.2-1B-4Bit".to_owned(),
+                revision: None
+            }
+        );
+        assert_eq!(
+            model_ref("driaforall/mem-agent@abc123"),
+            ModelRef::HuggingFace {
+                repo: "driaforall/mem-agent".to_owned(),
+                revision: Some("abc123".to_owned())
+            }
+        );
+        assert!("a/b/c".parse::<ModelRef>().is_err());
+    }
+
+    #[test]
+    fn test_parse_ollama_refs() {
+        assert_eq!(
+            model_ref("llama3.2"),
+            ModelRef::OllamaTag {
+                name: "llama3.2".to_owned(),
+                tag: "latest".to_owned()
+            }
+        );
+        assert_eq!(
+            model_ref("x/llama3.2-vision:latest"),
+            ModelRef::OllamaTag {
+                name: "x/llama3.2-vision".to_owned(),
+                tag: "latest".to_owned()
+            }
+        );
+        let blob = "/mnt/space/ollama/models/blobs/sha256-b559938ab7a0392fc9ea9675b82280f2a15669ec3e0e0fc491c9cb0a7681cf94";
+        assert!(matches!(model_ref(blob), ModelRef::OllamaBlob { .. }));
+        assert!(model_ref(blob).validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_local_refs() {
+        assert!(matches!(
+            model_ref("./models/llama.gguf"),
+            ModelRef::LocalFile {
+                format: WeightsFormat::Gguf,
+                ..
+            }
+        ));
+        assert!(matches!(
+            model_ref("/opt/models/phi"),
+            ModelRef::LocalDir { .. }
+        ));
+        assert!("my model".parse::<ModelRef>().is_err());
+    }
+
+    #[test]
+    fn test_only_explicit_paths_are_anchored() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("llama3.2"))?;
+        assert_eq!(
+            ModelRef::parse_in("llama3.2", dir.path()),
+            Ok(ModelRef::OllamaTag {
+                name: "llama3.2".to_owned(),
+                tag: "latest".to_owned()
+            })
+        );
+        assert_eq!(
+            anchored_path("./llama3.2", dir.path()),
+            Some(dir.path().join("llama3.2"))
+        );
+        assert_eq!(
+            anchored_path("weights/tiny.gguf", dir.path()),
+            Some(dir.path().join("weights/tiny.gguf"))
+        );
+        assert_eq!(anchored_path("/opt/tiny.gguf", dir.path()), None);
+        assert_eq!(anchored_path("org/model", dir.path()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_registered_modelfiles() {
+        let name = registered_modelfile("modelfile:mybot:v2").unwrap().unwrap();
+        assert_eq!((name.name.as_str(), name.tag.as_str()), ("mybot", "v2"));
+        let name = registered_modelfile("modelfile:mybot").unwrap().unwrap();
+        assert_eq!(name.tag, "latest");
+        assert!(registered_modelfile("modelfile:my/bot").unwrap().is_err());
+        assert!(registered_modelfile("llama3.2:latest").is_none());
+        assert!("modelfile:mybot".parse::<ModelRef>().is_err());
+    }
+
+    #[test]
+    fn test_blob_digest_verification() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let content = b"weights";
+        let digest = format!("{:x}", Sha256::digest(content));
+        let path = dir.path().join(format!("sha256-{}", digest));
+        fs::write(&path, content)?;
+        let blob = model_ref(&path.to_string_lossy());
+        assert!(blob.validate().is_ok());
+        fs::write(&path, b"tampered")?;
+        assert!(blob.validate().is_err());
+        Ok(())
+    }
+}
@@ -3,31 +3,73 @@
 
 // Modelfile grammar
 // command -> Instruction arguments*
-// Instruction -> "FROM" | "PARAMETER" | "TEMPLATE"...
+// Instruction -> "FROM" | "PARAMETER" | "TEMPLATE" | "ARG"...
 // arguments -> WORD | quoted_string | multiline_string
 // quoted_string -> "<str>"
 // multiline_string -> """<str>"""
+//
+// Arguments may reference variables as ${NAME} or ${NAME:-default}, which are
+// substituted before validation. `$${` escapes a literal `${`.
 
-use std::{fmt::Display, fs, str::FromStr};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::core::{
+    model_ref::{self, ModelRef},
+    registry::Registry,
+};
 
 use nom::{
     AsChar, IResult, Parser,
     branch::alt,
-    bytes::complete::{tag_no_case, take_until1, take_while1},
+    bytes::complete::{tag, tag_no_case, take_until1, take_while_m_n, take_while1},
     character::complete::multispace0,
     combinator::map,
     multi::separated_list1,
-    sequence::{delimited, pair},
+    sequence::{delimited, pair, terminated},
 };
 
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum ParamValue {
     Int(i32),
-    Float(f32),
+    Float(f64),
     Str(String),
 }
 
+/// The type a PARAMETER value is validated against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    Int,
+    Float,
+    Str,
+}
+
+/// Every PARAMETER accepted by `parse_parameter` along with its value type
+pub const PARAMETERS: &[(&str, ParamKind)] = &[
+    ("num_ctx", ParamKind::Int),
+    ("repeat_last_n", ParamKind::Int),
+    ("repeat_penalty", ParamKind::Float),
+    ("temperature", ParamKind::Float),
+    ("seed", ParamKind::Int),
+    ("stop", ParamKind::Str),
+    ("num_predict", ParamKind::Int),
+    ("top_k", ParamKind::Int),
+    ("top_p", ParamKind::Float),
+    ("min_p", ParamKind::Float),
+    ("keep_alive", ParamKind::Str),
+];
+
 impl Display for ParamValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -38,7 +80,8 @@ impl Display for ParamValue {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 enum Role {
     System,
     User,
@@ -59,6 +102,16 @@ impl<'a> Display for Output<'a> {
     }
 }
 
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::System => write!(f, "system"),
+            Role::User => write!(f, "user"),
+            Role::Assistant => write!(f, "assistant"),
+        }
+    }
+}
+
 impl FromStr for Role {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -71,16 +124,18 @@ impl FromStr for Role {
     }
 }
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Parameter {
+    #[serde(rename = "name")]
     pub param_type: String,
     pub value: ParamValue,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     role: Role,
+    #[serde(rename = "content")]
     message: String,
 }
 
@@ -90,7 +145,18 @@ impl Parameter {
     }
 }
 
-#[derive(Debug, Clone)]
+impl Message {
+    pub fn role(&self) -> String {
+        self.role.to_string()
+    }
+
+    pub fn content(&self) -> &str {
+        &self.message
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ModelfileDocument", into = "ModelfileDocument")]
 pub struct Modelfile {
     pub from: Option<String>,
     pub parameters: Vec<Parameter>,
@@ -125,7 +191,7 @@ impl Modelfile {
             Err(error)
         } else {
             self.from = Some(value.to_owned());
-            self.data.push(format!("FROM {}", value));
+            self.data.push(format!("FROM {}", quote(value)));
             Ok(())
         }
     }
@@ -137,7 +203,7 @@ impl Modelfile {
             Err(error)
         } else {
             self.template = Some(value.to_owned());
-            self.data.push(format!("TEMPLATE {}", value));
+            self.data.push(format!("TEMPLATE {}", quote(value)));
             Ok(())
         }
     }
@@ -149,7 +215,7 @@ impl Modelfile {
             Err(error)
         } else {
             self.license = Some(value.to_owned());
-            self.data.push(format!("LICENSE {}", value));
+            self.data.push(format!("LICENSE {}", quote(value)));
             Ok(())
         }
     }
@@ -161,7 +227,7 @@ impl Modelfile {
             Err(error)
         } else {
             self.adapter = Some(value.to_owned());
-            self.data.push(format!("ADAPTER {}", value));
+            self.data.push(format!("ADAPTER {}", quote(value)));
             Ok(())
         }
     }
@@ -170,11 +236,10 @@ impl Modelfile {
         if self.system.is_some() {
             let error = "Modelfile can only have one SYSTEM instruction".to_owned();
             self.errors.push(error.clone());
-
-            self.data.push(format!("SYSTEM {}", value));
             Err(error)
         } else {
             self.system = Some(value.to_owned());
+            self.data.push(format!("SYSTEM {}", quote(value)));
             Ok(())
         }
     }
@@ -189,7 +254,7 @@ impl Modelfile {
             Ok(parameter) => {
                 self.parameters.push(parameter);
                 self.data
-                    .push(format!("PARAMETER {} {}", param_type, param_value));
+                    .push(format!("PARAMETER {} {}", param_type, quote(param_value)));
                 Ok(())
             }
             Err(err) => {
@@ -203,7 +268,8 @@ impl Modelfile {
         match parse_message(role, message) {
             Ok(msg) => {
                 self.messages.push(msg);
-                self.data.push(format!("MESSAGE {} {}", role, message));
+                self.data
+                    .push(format!("MESSAGE {} {}", role, quote(message)));
                 Ok(())
             }
             Err(err) => {
@@ -213,6 +279,50 @@ impl Modelfile {
         }
     }
 
+    /// Seconds the server keeps the model loaded while idle, from PARAMETER keep_alive
+    pub fn keep_alive(&self) -> Option<i64> {
+        self.parameters
+            .iter()
+            .rfind(|parameter| parameter.param_type == "keep_alive")
+            .and_then(|parameter| parse_keep_alive(&parameter.value.to_string()).ok())
+    }
+
+    /// Joins relative FROM and ADAPTER paths onto `base_dir`, the directory
+    /// of the Modelfile, so they don't depend on the working directory
+    pub fn anchor_paths(&mut self, base_dir: &Path) {
+        if let Some(from) = self.from.clone()
+            && let Some(path) = model_ref::anchored_path(&from, base_dir)
+        {
+            let path = path.to_string_lossy().to_string();
+            self.replace_line("FROM", &from, &path);
+            self.from = Some(path);
+        }
+        if let Some(adapter) = self.adapter.clone()
+            && Path::new(&adapter).is_relative()
+            && !adapter.starts_with('~')
+        {
+            let relative = adapter.strip_prefix("./").unwrap_or(&adapter);
+            let path = base_dir.join(relative).to_string_lossy().to_string();
+            self.replace_line("ADAPTER", &adapter, &path);
+            self.adapter = Some(path);
+        }
+    }
+
+    fn replace_line(&mut self, instruction: &str, old: &str, new: &str) {
+        let old = format!("{} {}", instruction, quote(old));
+        if let Some(line) = self.data.iter_mut().find(|line| **line == old) {
+            *line = format!("{} {}", instruction, quote(new));
+        }
+    }
+
+    /// Parses FROM into a structured model reference
+    pub fn model_ref(&self) -> Result<ModelRef, String> {
+        match &self.from {
+            Some(from) => from.parse(),
+            None => Err(String::from("Modelfile should need a FROM instruction")),
+        }
+    }
+
     pub fn build(&mut self) -> Result<(), String> {
         if self.from.is_none() {
             let error = String::from("Modelfile should need a FROM instruction");
@@ -224,6 +334,41 @@ impl Modelfile {
     }
 }
 
+/// Per-run changes layered on top of a parsed Modelfile
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// `--set name=value` pairs. Every PARAMETER with an overridden name is
+    /// replaced, so repeating `--set stop=...` builds a new stop list.
+    pub parameters: Vec<(String, String)>,
+    pub system: Option<String>,
+}
+
+impl Overrides {
+    pub fn is_empty(&self) -> bool {
+        self.parameters.is_empty() && self.system.is_none()
+    }
+}
+
+impl Modelfile {
+    /// Returns a copy of the Modelfile with `overrides` applied. Values are
+    /// type checked the same way as PARAMETER instructions.
+    pub fn apply_overrides(&self, overrides: &Overrides) -> Result<Modelfile, String> {
+        let mut document = ModelfileDocument::from(self.clone());
+        let mut parameters = vec![];
+        for (name, value) in &overrides.parameters {
+            parameters.push(parse_parameter(name, value)?);
+        }
+        document
+            .parameters
+            .retain(|p| !parameters.iter().any(|o| o.param_type == p.param_type));
+        document.parameters.extend(parameters);
+        if let Some(system) = &overrides.system {
+            document.system = Some(system.clone());
+        }
+        Modelfile::try_from(document)
+    }
+}
+
 impl Default for Modelfile {
     fn default() -> Self {
         Modelfile::new()
@@ -243,33 +388,525 @@ impl Display for Modelfile {
     }
 }
 
+/// Structured form of a Modelfile used for JSON, YAML and TOML interchange.
+/// Deserializing goes through the same `add_*` builders as the text parser,
+/// so structured input gets the same validation as a Modelfile. Comments
+/// have no place in it, so converting drops them.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModelfileDocument {
+    from: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    adapter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    license: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    parameters: Vec<Parameter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    messages: Vec<Message>,
+}
+
+impl From<Modelfile> for ModelfileDocument {
+    fn from(modelfile: Modelfile) -> Self {
+        Self {
+            from: modelfile.from.unwrap_or_default(),
+            adapter: modelfile.adapter,
+            template: modelfile.template,
+            system: modelfile.system,
+            license: modelfile.license,
+            parameters: modelfile.parameters,
+            messages: modelfile.messages,
+        }
+    }
+}
+
+impl TryFrom<ModelfileDocument> for Modelfile {
+    type Error = String;
+    fn try_from(document: ModelfileDocument) -> Result<Self, Self::Error> {
+        let mut modelfile = Modelfile::new();
+        let _ = modelfile.add_from(&document.from);
+        if let Some(adapter) = &document.adapter {
+            let _ = modelfile.add_adapter(adapter);
+        }
+        if let Some(template) = &document.template {
+            let _ = modelfile.add_template(template);
+        }
+        if let Some(system) = &document.system {
+            let _ = modelfile.add_system(system);
+        }
+        if let Some(license) = &document.license {
+            let _ = modelfile.add_license(license);
+        }
+        for parameter in &document.parameters {
+            let _ = modelfile.add_parameter(&parameter.param_type, &parameter.value.to_string());
+        }
+        for message in &document.messages {
+            let _ = modelfile.add_message(&message.role.to_string(), &message.message);
+        }
+        modelfile.build()?;
+        if modelfile.errors.is_empty() {
+            Ok(modelfile)
+        } else {
+            Err(modelfile.errors.join(" , "))
+        }
+    }
+}
+
+/// JSON Schema for the structured Modelfile, derived from `PARAMETERS`
+pub fn json_schema() -> Value {
+    let parameters: Vec<Value> = PARAMETERS
+        .iter()
+        .map(|(name, kind)| {
+            let value_type = match kind {
+                ParamKind::Int => "integer",
+                ParamKind::Float => "number",
+                ParamKind::Str => "string",
+            };
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": { "const": name },
+                    "value": { "type": value_type }
+                },
+                "required": ["name", "value"],
+                "additionalProperties": false
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Modelfile",
+        "description": "Structured form of a tiles Modelfile",
+        "type": "object",
+        "properties": {
+            "from": { "type": "string", "minLength": 1, "description": "FROM instruction" },
+            "adapter": { "type": "string", "description": "ADAPTER instruction" },
+            "template": { "type": "string", "description": "TEMPLATE instruction" },
+            "system": { "type": "string", "description": "SYSTEM instruction" },
+            "license": { "type": "string", "description": "LICENSE instruction" },
+            "parameters": {
+                "type": "array",
+                "description": "PARAMETER instructions",
+                "items": { "oneOf": parameters }
+            },
+            "messages": {
+                "type": "array",
+                "description": "MESSAGE instructions",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "role": { "enum": ["system", "user", "assistant"] },
+                        "content": { "type": "string" }
+                    },
+                    "required": ["role", "content"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["from"],
+        "additionalProperties": false
+    })
+}
+
+// Writes a value so that the rendered Modelfile parses back to it. Values
+// that can't stay bare go in a fence of quotes longer than any run of quotes
+// they contain, padded with a space on each side when they start or end with
+// a quote or space, which `parse_quoted` takes off again.
+fn quote(value: &str) -> String {
+    let is_bare = !value.is_empty()
+        && !value.contains(['\n', '"'])
+        && value.trim() == value
+        && parse_argument_name(value).is_err();
+    if is_bare {
+        return value.to_owned();
+    }
+    let longest_run = value.split(|c| c != '"').map(str::len).max().unwrap_or(0);
+    let fence = "\"".repeat((longest_run + 1).max(3));
+    let padding =
+        if value.is_empty() || value.starts_with(['"', ' ']) || value.ends_with(['"', ' ']) {
+            " "
+        } else {
+            ""
+        };
+    format!("{fence}{padding}{value}{padding}{fence}")
+}
+
 pub fn parse_from_file(path: &str) -> Result<Modelfile, String> {
+    parse_from_file_with_args(path, &[])
+}
+
+pub fn parse_from_file_with_args(
+    path: &str,
+    args: &[(String, String)],
+) -> Result<Modelfile, String> {
     match fs::read_to_string(path) {
-        Ok(content) => parse(content.as_str()),
+        Ok(content) => parse_with_args(content.as_str(), args),
         Err(err) => Err(format!("Parsing Modelfile failed due to {}", err)),
     }
 }
 
+/// How `stop` parameters of a child Modelfile combine with the ones it inherits
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StopPolicy {
+    #[default]
+    Concat,
+    Replace,
+}
+
+impl FromStr for StopPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "concat" => Ok(StopPolicy::Concat),
+            "replace" => Ok(StopPolicy::Replace),
+            other => Err(format!(
+                "Unknown stop policy `{}`, expected concat or replace",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    pub stop_policy: StopPolicy,
+    /// Variables passed with `--arg NAME=value`
+    pub args: Vec<(String, String)>,
+}
+
+/// Parses the Modelfile at `path` and flattens its inheritance chain.
+/// A FROM pointing at another Modelfile (`FROM ./base.modelfile` or
+/// `FROM modelfile:name`) is resolved recursively and merged with `merge`.
+pub fn resolve_from_file(path: &str, options: &ResolveOptions) -> Result<Modelfile, String> {
+    let registry = Registry::open().ok();
+    resolve_path(Path::new(path), options, registry.as_ref(), &mut vec![])
+}
+
+/// Resolves Modelfile text without a file of its own, such as one sent to
+/// the Ollama API, the same way. Relative paths are read from `base_dir`
+/// and `modelfile:` names looked up in `registry`.
+pub fn resolve_source(
+    source: &str,
+    base_dir: &Path,
+    registry: &Registry,
+    options: &ResolveOptions,
+) -> Result<Modelfile, String> {
+    let modelfile = parse_with_args(source, &options.args)?;
+    resolve_parent(modelfile, base_dir, options, Some(registry), &mut vec![])
+}
+
+fn resolve_path(
+    path: &Path,
+    options: &ResolveOptions,
+    registry: Option<&Registry>,
+    chain: &mut Vec<PathBuf>,
+) -> Result<Modelfile, String> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| format!("Resolving {} failed due to {}", path.display(), err))?;
+    if chain.contains(&canonical) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(format!(
+            "Modelfile inheritance cycle: {}",
+            cycle.join(" -> ")
+        ));
+    }
+    // Stored Modelfiles had their variables substituted when they were created
+    let is_stored = registry.is_some_and(|registry| registry.stores(&canonical));
+    let modelfile = if is_stored {
+        fs::read_to_string(&canonical)
+            .map_err(|err| format!("Parsing Modelfile failed due to {}", err))
+            .and_then(|content| parse_literal(&content))?
+    } else {
+        parse_from_file_with_args(&canonical.to_string_lossy(), &options.args)?
+    };
+    let base_dir = canonical.parent().unwrap_or(Path::new(".")).to_owned();
+    chain.push(canonical);
+    let resolved = resolve_parent(modelfile, &base_dir, options, registry, chain);
+    chain.pop();
+    resolved
+}
+
+// Anchors the paths of a parsed Modelfile and merges it over its parent
+fn resolve_parent(
+    mut modelfile: Modelfile,
+    base_dir: &Path,
+    options: &ResolveOptions,
+    registry: Option<&Registry>,
+    chain: &mut Vec<PathBuf>,
+) -> Result<Modelfile, String> {
+    modelfile.anchor_paths(base_dir);
+    let from = modelfile.from.clone().unwrap_or_default();
+    match parent_modelfile_path(&from, base_dir, registry)? {
+        Some(parent_path) => {
+            let parent = resolve_path(&parent_path, options, registry, chain)?;
+            merge(&parent, &modelfile, options.stop_policy)
+        }
+        None => Ok(modelfile),
+    }
+}
+
+// Returns the path of the parent Modelfile if FROM refers to one
+fn parent_modelfile_path(
+    from: &str,
+    base_dir: &Path,
+    registry: Option<&Registry>,
+) -> Result<Option<PathBuf>, String> {
+    if let Some(name) = model_ref::registered_modelfile(from) {
+        let name = name?;
+        let path = match registry {
+            Some(registry) => registry.modelfile_path(&name),
+            None => Registry::open().and_then(|registry| registry.modelfile_path(&name)),
+        }
+        .map_err(|err| err.to_string())?;
+        return Ok(Some(path));
+    }
+    let path = Path::new(from);
+    let is_modelfile = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase().ends_with("modelfile"))
+        .unwrap_or(false);
+    if is_modelfile {
+        Ok(Some(base_dir.join(path)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Layers `child` over `parent`. The child's PARAMETERs win over the parent's
+/// of the same name, except `stop` which follows `stop_policy`. TEMPLATE,
+/// SYSTEM, ADAPTER and LICENSE are inherited unless the child sets them, and
+/// the child's MESSAGEs continue the conversation it inherits.
+pub fn merge(
+    parent: &Modelfile,
+    child: &Modelfile,
+    stop_policy: StopPolicy,
+) -> Result<Modelfile, String> {
+    let mut merged = Modelfile::new();
+    if let Some(from) = &parent.from {
+        merged.add_from(from)?;
+    }
+    if let Some(adapter) = child.adapter.as_ref().or(parent.adapter.as_ref()) {
+        merged.add_adapter(adapter)?;
+    }
+    if let Some(template) = child.template.as_ref().or(parent.template.as_ref()) {
+        merged.add_template(template)?;
+    }
+    if let Some(system) = child.system.as_ref().or(parent.system.as_ref()) {
+        merged.add_system(system)?;
+    }
+    if let Some(license) = child.license.as_ref().or(parent.license.as_ref()) {
+        merged.add_license(license)?;
+    }
+
+    let overridden = |name: &str| {
+        child.parameters.iter().any(|p| p.param_type == name)
+            && (name != "stop" || stop_policy == StopPolicy::Replace)
+    };
+    for parameter in parent
+        .parameters
+        .iter()
+        .filter(|p| !overridden(&p.param_type))
+        .chain(child.parameters.iter())
+    {
+        merged.add_parameter(&parameter.param_type, &parameter.value.to_string())?;
+    }
+
+    for message in parent.messages.iter().chain(&child.messages) {
+        merged.add_message(&message.role.to_string(), &message.message)?;
+    }
+    merged.build()?;
+    Ok(merged)
+}
+
 pub fn parse(input: &str) -> Result<Modelfile, String> {
+    parse_with_args(input, &[])
+}
+
+/// Parses a Modelfile substituting `${NAME}` references. Values come from
+/// `args` first, then the environment, then the default given by an
+/// `ARG NAME=default` instruction and finally an inline `${NAME:-default}`.
+pub fn parse_with_args(input: &str, args: &[(String, String)]) -> Result<Modelfile, String> {
+    match parse_all(input) {
+        Ok(parsed_data) => {
+            let declared = collect_args(&parsed_data)?;
+            let lookup = |name: &str| {
+                args.iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.clone())
+                    .or_else(|| env::var(name).ok())
+                    .or_else(|| declared.get(name).cloned().flatten())
+            };
+
+            let mut unresolved = vec![];
+            let values: Vec<String> = parsed_data
+                .iter()
+                .map(|(instruction, output)| {
+                    let value = match output {
+                        Output::Single(value) | Output::Pair((_, value)) => *value,
+                    };
+                    match instruction.to_lowercase().as_str() {
+                        "#" | "arg" => value.to_owned(),
+                        _ => interpolate(input, value, &lookup, &mut unresolved),
+                    }
+                })
+                .collect();
+            if !unresolved.is_empty() {
+                return Err(format!("Unresolved variables: {}", unresolved.join(", ")));
+            }
+
+            let commands = parsed_data
+                .iter()
+                .zip(values.iter())
+                .filter(|((instruction, _), _)| !instruction.eq_ignore_ascii_case("arg"))
+                .map(|((instruction, output), value)| match output {
+                    Output::Single(_) => (*instruction, Output::Single(value)),
+                    Output::Pair((key, _)) => (*instruction, Output::Pair((key, value))),
+                })
+                .collect();
+            create_modelfile(commands)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Parses a Modelfile as written, without substituting variables, for
+/// Modelfiles stored after their variables were substituted
+pub fn parse_literal(input: &str) -> Result<Modelfile, String> {
+    let commands = parse_all(input)?
+        .into_iter()
+        .filter(|(instruction, _)| !instruction.eq_ignore_ascii_case("arg"))
+        .collect();
+    create_modelfile(commands)
+}
+
+// Every command in `input`, input that isn't one is an error
+fn parse_all(input: &str) -> Result<Vec<(&str, Output<'_>)>, String> {
     match parse_file(input) {
-        Ok((_rest, parsed_data)) => create_modelfile(parsed_data.clone()),
+        Ok((rest, _)) if !rest.trim().is_empty() => {
+            let offset = input.len() - rest.trim_start().len();
+            let line = input[..offset].matches('\n').count() + 1;
+            Err(format!(
+                "Modelfile failed to parse at line {}: `{}`",
+                line,
+                rest.trim().lines().next().unwrap_or_default()
+            ))
+        }
+        Ok((_rest, commands)) => Ok(commands),
         Err(err) => Err(format!("Modelfile failed to parse due to {:?}", err)),
     }
 }
 
+// Collects `ARG NAME` and `ARG NAME=default` declarations
+fn collect_args(commands: &[(&str, Output)]) -> Result<HashMap<String, Option<String>>, String> {
+    let mut declared = HashMap::new();
+    for (instruction, output) in commands {
+        if !instruction.eq_ignore_ascii_case("arg") {
+            continue;
+        }
+        let declaration = output.to_string();
+        let (name, default) = match declaration.split_once('=') {
+            Some((name, default)) => (name.trim(), Some(default.trim().to_owned())),
+            None => (declaration.trim(), None),
+        };
+        if !is_variable_name(name) {
+            return Err(format!("Invalid ARG name `{}`", name));
+        }
+        declared.insert(name.to_owned(), default);
+    }
+    Ok(declared)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Substitutes variables in `value`, a slice of `input`. References without a
+// value are recorded in `unresolved` along with their line and column.
+fn interpolate(
+    input: &str,
+    value: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    unresolved: &mut Vec<String>,
+) -> String {
+    let base = value.as_ptr() as usize - input.as_ptr() as usize;
+    let location = |index: usize| {
+        let before = &input[..base + index];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+        format!("line {}, column {}", line, column)
+    };
+
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let index = value.len() - rest.len() + start;
+        let tail = &rest[start..];
+        if let Some(escaped) = tail.strip_prefix("$${") {
+            result.push_str("${");
+            rest = escaped;
+        } else if let Some(body) = tail.strip_prefix("${") {
+            let Some(end) = body.find('}') else {
+                unresolved.push(format!("unterminated `${{` at {}", location(index)));
+                return result;
+            };
+            let (name, default) = match body[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&body[..end], None),
+            };
+            match lookup(name).or(default.map(str::to_owned)) {
+                Some(substituted) if is_variable_name(name) => result.push_str(&substituted),
+                _ => unresolved.push(format!("{} at {}", name, location(index))),
+            }
+            rest = &body[end + 1..];
+        } else {
+            result.push('$');
+            rest = &tail[1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
 fn parse_file(input: &str) -> IResult<&str, Vec<(&str, Output<'_>)>> {
     separated_list1(multispace0, parse_command).parse(input)
 }
 
 fn parse_command(input: &str) -> IResult<&str, (&str, Output<'_>)> {
+    alt((
+        parse_arg,
+        pair(
+            delimited(multispace0, parse_instruction, multispace0),
+            alt((
+                map(parse_quoted, Output::Single),
+                map(parse_multi_arguments, Output::Pair),
+                map(parse_singleline, Output::Single),
+            )),
+        ),
+    ))
+    .parse(input)
+}
+
+// `ARG NAME[=default]` takes the rest of its line as is, names like
+// USER_NAME must not be read as a MESSAGE role or a PARAMETER
+fn parse_arg(input: &str) -> IResult<&str, (&str, Output<'_>)> {
     pair(
-        delimited(multispace0, parse_instruction, multispace0),
-        alt((
-            map(parse_multiquote, Output::Single),
-            map(parse_singlequote, Output::Single),
-            map(parse_multi_arguments, Output::Pair),
-            map(parse_singleline, Output::Single),
-        )),
+        delimited(multispace0, tag_no_case("ARG"), multispace0),
+        map(parse_singleline, Output::Single),
     )
     .parse(input)
 }
@@ -289,47 +926,61 @@ fn parse_instruction(input: &str) -> IResult<&str, &str> {
 }
 
 fn parse_multi_arguments(input: &str) -> IResult<&str, (&str, &str)> {
-    pair(
-        delimited(
-            multispace0,
-            alt((
-                tag_no_case("stop"),
-                tag_no_case("num_ctx"),
-                tag_no_case("repeat_last_n"),
-                tag_no_case("temperature"),
-                tag_no_case("seed"),
-                tag_no_case("top_k"),
-                tag_no_case("top_p"),
-                tag_no_case("min_p"),
-                tag_no_case("num_predict"),
-                tag_no_case("repeat_penalty"),
-                tag_no_case("user"),
-                tag_no_case("assistant"),
-                tag_no_case("system"),
-            )),
-            multispace0,
-        ),
-        alt((parse_multiquote, parse_singlequote, parse_singleline)),
-    )
-    .parse(input)
+    pair(parse_argument_name, alt((parse_quoted, parse_singleline))).parse(input)
 }
 
-fn parse_multiquote(input: &str) -> IResult<&str, &str> {
+// The PARAMETER name or MESSAGE role in front of an argument
+fn parse_argument_name(input: &str) -> IResult<&str, &str> {
     delimited(
-        tag_no_case("\"\"\""),
-        take_until1("\"\"\""),
-        tag_no_case("\"\"\""),
+        multispace0,
+        alt((
+            tag_no_case("stop"),
+            tag_no_case("num_ctx"),
+            tag_no_case("repeat_last_n"),
+            tag_no_case("temperature"),
+            tag_no_case("seed"),
+            tag_no_case("top_k"),
+            tag_no_case("top_p"),
+            tag_no_case("min_p"),
+            tag_no_case("num_predict"),
+            tag_no_case("repeat_penalty"),
+            tag_no_case("keep_alive"),
+            tag_no_case("user"),
+            tag_no_case("assistant"),
+            tag_no_case("system"),
+        )),
+        multispace0,
     )
     .parse(input)
 }
 
+// `"""value"""`, or a longer fence such as `""""value""""` when the value
+// itself contains `"""`
+fn parse_multiquote(input: &str) -> IResult<&str, &str> {
+    let (rest, fence) = take_while_m_n(3, usize::MAX, |c| c == '"').parse(input)?;
+    terminated(take_until1(fence), tag(fence)).parse(rest)
+}
+
 fn parse_singlequote(input: &str) -> IResult<&str, &str> {
     delimited(tag_no_case("\""), take_until1("\""), tag_no_case("\"")).parse(input)
 }
+// Quoted values are kept as written, but for one space of padding on each
+// side that `quote` adds around values starting or ending with a quote
+fn parse_quoted(input: &str) -> IResult<&str, &str> {
+    map(
+        alt((parse_multiquote, parse_singlequote)),
+        |value: &str| match value.strip_prefix(' ').and_then(|v| v.strip_suffix(' ')) {
+            Some(unpadded) => unpadded,
+            None => value,
+        },
+    )
+    .parse(input)
+}
+
 fn parse_singleline(input: &str) -> IResult<&str, &str> {
     delimited(
         multispace0,
-        take_while1(|c: char| !c.is_newline()),
+        map(take_while1(|c: char| !c.is_newline()), str::trim_end),
         multispace0,
     )
     .parse(input)
@@ -337,20 +988,30 @@ fn parse_singleline(input: &str) -> IResult<&str, &str> {
 fn create_modelfile(commands: Vec<(&str, Output)>) -> Result<Modelfile, String> {
     // TODO: There might be a better way
     let mut modelfile: Modelfile = Modelfile::new();
+    let mut invalid_from = false;
     for command in commands {
         let _ = match (command.0.to_lowercase().as_str(), command.1) {
-            //TODO: Can add validations for path if its a gguf file later
-            ("from", Output::Single(from)) => modelfile.add_from(from.trim()),
+            // Only the shape of FROM is checked here, existence is checked before running
+            ("from", Output::Single(from)) => match model_ref::registered_modelfile(from)
+                .map(|name| name.map(|_| ()))
+                .unwrap_or_else(|| from.trim().parse::<ModelRef>().map(|_| ()))
+            {
+                Ok(()) => modelfile.add_from(from.trim()),
+                Err(err) => {
+                    invalid_from = true;
+                    modelfile.errors.push(err.clone());
+                    Err(err)
+                }
+            },
+            // Unquoted values were trimmed while parsing, quoted ones are kept as written
             ("parameter", Output::Pair((param, argument))) => {
-                modelfile.add_parameter(param, argument.trim())
-            }
-            ("template", Output::Single(template)) => modelfile.add_template(template.trim()),
-            ("system", Output::Single(system)) => modelfile.add_system(system.trim()),
-            ("adapter", Output::Single(adapter)) => modelfile.add_adapter(adapter.trim()),
-            ("message", Output::Pair((role, message))) => {
-                modelfile.add_message(role, message.trim())
+                modelfile.add_parameter(param, argument)
             }
-            ("license", Output::Single(license)) => modelfile.add_license(license.trim()),
+            ("template", Output::Single(template)) => modelfile.add_template(template),
+            ("system", Output::Single(system)) => modelfile.add_system(system),
+            ("adapter", Output::Single(adapter)) => modelfile.add_adapter(adapter),
+            ("message", Output::Pair((role, message))) => modelfile.add_message(role, message),
+            ("license", Output::Single(license)) => modelfile.add_license(license),
             ("#", comment) => {
                 let comment_str = comment.to_string();
                 modelfile.add_comment(&comment_str)
@@ -365,7 +1026,10 @@ fn create_modelfile(commands: Vec<(&str, Output)>) -> Result<Modelfile, String>
         };
     }
 
-    modelfile.build()?;
+    // An invalid FROM was already reported, it isn't a missing one
+    if !invalid_from {
+        modelfile.build()?;
+    }
     if modelfile.errors.is_empty() {
         Ok(modelfile.clone())
     } else {
@@ -376,27 +1040,50 @@ fn create_modelfile(commands: Vec<(&str, Output)>) -> Result<Modelfile, String>
 
 fn parse_parameter(param: &str, argument: &str) -> Result<Parameter, String> {
     let param_type: String = param.to_lowercase();
-    match (param_type.as_str(), argument) {
-        ("num_ctx", value) => parse_int(param_type, value),
-        ("repeat_last_n", value) => parse_int(param_type, value),
-        ("repeat_penalty", value) => parse_float(param_type, value),
-        ("temperature", value) => parse_float(param_type, value),
-        ("seed", value) => parse_int(param_type, value),
-
-        ("stop", value) => Ok(Parameter::new(
-            param_type,
-            ParamValue::Str(value.to_owned()),
-        )),
-
-        ("num_predict", value) => parse_int(param_type, value),
-
-        ("top_k", value) => parse_int(param_type, value),
-
-        ("top_p", value) => parse_float(param_type, value),
+    match PARAMETERS
+        .iter()
+        .find(|(name, _)| *name == param_type.as_str())
+    {
+        Some((_, ParamKind::Int)) => parse_int(param_type, argument),
+        Some((_, ParamKind::Float)) => parse_float(param_type, argument),
+        Some((_, ParamKind::Str)) => {
+            if param_type == "keep_alive" {
+                parse_keep_alive(argument)?;
+            }
+            Ok(Parameter::new(
+                param_type,
+                ParamValue::Str(argument.to_owned()),
+            ))
+        }
+        None => Err("Invalid Parameter type".to_owned()),
+    }
+}
 
-        ("min_p", value) => parse_float(param_type, value),
-        _ => Err("Invalid Parameter type".to_owned()),
+/// Seconds from `30`, `30s`, `10m` or `1h`, any negative number keeps the
+/// model loaded until it is unloaded
+pub fn parse_keep_alive(value: &str) -> Result<i64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &value[number.len()..]),
+        None => (value, "s"),
+    };
+    let number: i64 = number.parse().map_err(|_| {
+        format!(
+            "keep_alive `{}` is not a duration like 300, 10m or 1h",
+            value
+        )
+    })?;
+    if number < 0 {
+        return Ok(-1);
     }
+    let scale = match unit {
+        "m" => 60,
+        "h" => 3600,
+        _ => 1,
+    };
+    number
+        .checked_mul(scale)
+        .ok_or_else(|| format!("keep_alive `{}` is too long", value))
 }
 
 fn parse_int(param_type: String, value: &str) -> Result<Parameter, String> {
@@ -408,7 +1095,7 @@ fn parse_int(param_type: String, value: &str) -> Result<Parameter, String> {
 }
 
 fn parse_float(param_type: String, value: &str) -> Result<Parameter, String> {
-    if let Ok(parsed_val) = value.parse::<f32>() {
+    if let Ok(parsed_val) = value.parse::<f64>() {
         Ok(Parameter::new(param_type, ParamValue::Float(parsed_val)))
     } else {
         Err(format!("{} not a Float", param_type))
@@ -439,6 +1126,18 @@ mod tests {
         let res = parse("");
         assert!(res.is_err());
     }
+    #[test]
+    fn test_keep_alive() {
+        let modelfile = parse("FROM llama3.2\nPARAMETER keep_alive 10m").unwrap();
+        assert_eq!(modelfile.keep_alive(), Some(600));
+        assert!(parse("FROM llama3.2\nPARAMETER keep_alive soon").is_err());
+        assert_eq!(parse_keep_alive("90"), Ok(90));
+        assert_eq!(parse_keep_alive("1h"), Ok(3600));
+        assert_eq!(parse_keep_alive("-1"), Ok(-1));
+        assert!(parse_keep_alive(&format!("{}h", i64::MAX)).is_err());
+        assert_eq!(parse("FROM llama3.2").unwrap().keep_alive(), None);
+    }
+
     #[test]
     fn test_wrong_instruction() {
         assert!(parse("FRO llama").is_err());
@@ -481,6 +1180,69 @@ mod tests {
         assert!(parse(modelfile).is_err())
     }
 
+    #[test]
+    fn test_invalid_from_is_reported() {
+        let err = parse("FROM my model").unwrap_err();
+        assert_eq!(err, "Invalid model reference `my model`");
+    }
+
+    #[test]
+    fn test_from_tagged_registered_modelfile() {
+        let modelfile = parse("FROM modelfile:mybot:v2").unwrap();
+        assert_eq!(modelfile.from.as_deref(), Some("modelfile:mybot:v2"));
+        assert!(parse("FROM modelfile:my/bot").is_err());
+    }
+
+    #[test]
+    fn test_round_trip_with_quotes() -> Result<(), Box<dyn Error>> {
+        let document = json!({
+            "from": "llama3.2",
+            "template": "{{ .Prompt }} \"\"\" {{ .Response }}",
+            "system": "Say \"hi\"",
+            "parameters": [
+                { "name": "stop", "value": "\"" },
+                { "name": "stop", "value": "user:" }
+            ],
+            "messages": [
+                { "role": "user", "content": "\"Quoted\" \"\"\"\"" },
+                { "role": "assistant", "content": "system is up" }
+            ]
+        });
+        let modelfile: Modelfile = serde_json::from_value(document.clone())?;
+        let parsed = parse(&modelfile.to_string())?;
+        assert_eq!(serde_json::to_value(&parsed)?, document);
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip_keeps_whitespace() -> Result<(), Box<dyn Error>> {
+        let document = json!({
+            "from": "llama3.2",
+            "template": "{{ .Prompt }}\n",
+            "system": " padded ",
+            "parameters": [
+                { "name": "stop", "value": "\n" },
+                { "name": "stop", "value": " " },
+                { "name": "stop", "value": "\t\"" }
+            ]
+        });
+        let modelfile: Modelfile = serde_json::from_value(document.clone())?;
+        let parsed = parse(&modelfile.to_string())?;
+        assert_eq!(serde_json::to_value(&parsed)?, document);
+        let written = parse(
+            "FROM llama3.2\nSYSTEM \"\"\"\nBe brief.\n\"\"\"\nPARAMETER stop \"\"\"\n\"\"\"",
+        )?;
+        assert_eq!(written.system.as_deref(), Some("\nBe brief.\n"));
+        assert_eq!(written.parameters[0].value.to_string(), "\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_leftover_input_is_an_error() {
+        let err = parse("FROM llama3.2\nSYSTEM \"\"\"Say \"hi\"\"\"\"").unwrap_err();
+        assert_eq!(err, "Modelfile failed to parse at line 2: `\"`");
+    }
+
     #[test]
     fn test_parse_multiline_single_arguments() {
         let modelfile = "
@@ -559,4 +1321,190 @@ mod tests {
         assert_eq!(modelfile.from.unwrap(), String::from("llama3.2"));
         Ok(())
     }
+
+    #[test]
+    fn test_resolve_inherited_modelfile() -> Result<(), String> {
+        let modelfile = resolve_from_file(
+            "fixtures/inherit/child.modelfile",
+            &ResolveOptions::default(),
+        )?;
+        assert_eq!(modelfile.from, Some("llama3.2:latest".to_owned()));
+        assert_eq!(modelfile.system, Some("You are a pirate".to_owned()));
+        assert!(modelfile.template.is_some());
+        assert_eq!(modelfile.messages.len(), 2);
+        let temperatures: Vec<String> = modelfile
+            .parameters
+            .iter()
+            .filter(|p| p.param_type == "temperature")
+            .map(|p| p.value.to_string())
+            .collect();
+        assert_eq!(temperatures, vec!["0.2"]);
+        let stops = modelfile
+            .parameters
+            .iter()
+            .filter(|p| p.param_type == "stop")
+            .count();
+        assert_eq!(stops, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_child_messages_follow_inherited_ones() -> Result<(), String> {
+        let parent = parse("FROM llama3.2\nMESSAGE user Hi\nMESSAGE assistant Ahoy")?;
+        let child = parse("FROM ./base.modelfile\nMESSAGE user Where is the treasure?")?;
+        let merged = merge(&parent, &child, StopPolicy::Concat)?;
+        let messages: Vec<&str> = merged
+            .messages
+            .iter()
+            .map(|message| message.message.as_str())
+            .collect();
+        assert_eq!(messages, vec!["Hi", "Ahoy", "Where is the treasure?"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_with_stop_replace_policy() -> Result<(), String> {
+        let options = ResolveOptions {
+            stop_policy: StopPolicy::Replace,
+            ..Default::default()
+        };
+        let modelfile = resolve_from_file("fixtures/inherit/child.modelfile", &options)?;
+        let stops: Vec<String> = modelfile
+            .parameters
+            .iter()
+            .filter(|p| p.param_type == "stop")
+            .map(|p| p.value.to_string())
+            .collect();
+        assert_eq!(stops, vec!["</s>"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_paths_follow_the_modelfile() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("weights"))?;
+        let path = dir.path().join("Modelfile");
+        std::fs::write(&path, "FROM ./weights\nADAPTER ./lora.safetensors")?;
+        let modelfile = resolve_from_file(&path.to_string_lossy(), &ResolveOptions::default())?;
+        let weights = dir.path().canonicalize()?.join("weights");
+        assert_eq!(
+            modelfile.model_ref()?,
+            ModelRef::LocalDir {
+                path: weights.clone()
+            }
+        );
+        assert!(modelfile.model_ref()?.validate().is_ok());
+        assert!(
+            modelfile
+                .to_string()
+                .contains(&format!("FROM {}", weights.display()))
+        );
+        assert_eq!(
+            modelfile.adapter.map(PathBuf::from),
+            Some(dir.path().canonicalize()?.join("lora.safetensors"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_detects_cycles() {
+        let err = resolve_from_file(
+            "fixtures/inherit/cycle_a.modelfile",
+            &ResolveOptions::default(),
+        )
+        .unwrap_err();
+        assert!(err.contains("cycle"));
+    }
+
+    #[test]
+    fn test_arg_defaults_and_overrides() -> Result<(), String> {
+        let modelfile_content = "
+            ARG TILES_TEST_BOT=Tilly
+            FROM llama3.2
+            PARAMETER temperature ${TILES_TEST_TEMP:-0.7}
+            SYSTEM \"\"\"You are ${TILES_TEST_BOT}\"\"\"
+        ";
+        let modelfile = parse(modelfile_content)?;
+        assert_eq!(modelfile.system, Some("You are Tilly".to_owned()));
+        assert_eq!(modelfile.parameters[0].value, ParamValue::Float(0.7));
+
+        let args = vec![
+            ("TILES_TEST_BOT".to_owned(), "Robo".to_owned()),
+            ("TILES_TEST_TEMP".to_owned(), "0.1".to_owned()),
+        ];
+        let modelfile = parse_with_args(modelfile_content, &args)?;
+        assert_eq!(modelfile.system, Some("You are Robo".to_owned()));
+        assert_eq!(modelfile.parameters[0].value, ParamValue::Float(0.1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_arg_names_starting_with_keywords() -> Result<(), String> {
+        let modelfile_content = "
+            ARG USER_NAME=bob
+            ARG SYSTEM_PROMPT=hi
+            ARG STOP_WORD
+            FROM llama3.2
+            PARAMETER stop ${STOP_WORD:-</s>}
+            SYSTEM \"\"\"${SYSTEM_PROMPT} ${USER_NAME}\"\"\"
+        ";
+        let modelfile = parse(modelfile_content)?;
+        assert_eq!(modelfile.system, Some("hi bob".to_owned()));
+        assert_eq!(
+            modelfile.parameters[0].value,
+            ParamValue::Str("</s>".to_owned())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_substituted_values_are_validated() {
+        let modelfile_content = "FROM llama3.2\nPARAMETER num_ctx ${TILES_TEST_CTX}";
+        let args = vec![("TILES_TEST_CTX".to_owned(), "lots".to_owned())];
+        assert!(parse_with_args(modelfile_content, &args).is_err());
+    }
+
+    #[test]
+    fn test_unresolved_variables_report_spans() {
+        let modelfile_content = "FROM llama3.2\nSYSTEM Hi ${TILES_TEST_MISSING} and $${LITERAL}";
+        let err = parse(modelfile_content).unwrap_err();
+        assert_eq!(
+            err,
+            "Unresolved variables: TILES_TEST_MISSING at line 2, column 11"
+        );
+    }
+
+    #[test]
+    fn test_apply_overrides() -> Result<(), String> {
+        let modelfile = parse_from_file("fixtures/a.modelfile")?;
+        let overrides = Overrides {
+            parameters: vec![
+                ("temperature".to_owned(), "0.1".to_owned()),
+                ("stop".to_owned(), "</s>".to_owned()),
+            ],
+            system: Some("Be brief".to_owned()),
+        };
+        let modelfile = modelfile.apply_overrides(&overrides)?;
+        let stops = modelfile
+            .parameters
+            .iter()
+            .filter(|p| p.param_type == "stop")
+            .count();
+        assert_eq!(stops, 1);
+        assert_eq!(modelfile.system, Some("Be brief".to_owned()));
+        assert!(modelfile.to_string().contains("PARAMETER temperature 0.1"));
+        assert_eq!(modelfile.messages.len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overrides_are_type_checked() -> Result<(), String> {
+        let modelfile = parse("FROM llama3.2")?;
+        let overrides = Overrides {
+            parameters: vec![("num_ctx".to_owned(), "big".to_owned())],
+            system: None,
+        };
+        assert!(modelfile.apply_overrides(&overrides).is_err());
+        Ok(())
+    }
 }
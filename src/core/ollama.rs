@@ -0,0 +1,607 @@
+// Reads and writes models in the layout of an Ollama models dir
+//
+// Layout:
+// manifests/<host>/<namespace>/<name>/<tag>   JSON manifest listing layers
+// blobs/sha256-<hex>                          layer content addressed by digest
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::{
+    env::consts,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::core::{
+    alias::AliasTable,
+    config::get_ollama_models_dir,
+    gguf,
+    model_ref::{ModelRef, WeightsFormat, file_digest},
+    modelfile::{Modelfile, PARAMETERS},
+    registry::sha256_digest,
+};
+
+pub const MEDIA_TYPE_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+pub const MEDIA_TYPE_CONFIG: &str = "application/vnd.docker.container.image.v1+json";
+pub const MEDIA_TYPE_MODEL: &str = "application/vnd.ollama.image.model";
+pub const MEDIA_TYPE_ADAPTER: &str = "application/vnd.ollama.image.adapter";
+pub const MEDIA_TYPE_TEMPLATE: &str = "application/vnd.ollama.image.template";
+pub const MEDIA_TYPE_SYSTEM: &str = "application/vnd.ollama.image.system";
+pub const MEDIA_TYPE_PARAMS: &str = "application/vnd.ollama.image.params";
+pub const MEDIA_TYPE_LICENSE: &str = "application/vnd.ollama.image.license";
+pub const MEDIA_TYPE_MESSAGES: &str = "application/vnd.ollama.image.messages";
+
+const DEFAULT_HOST: &str = "registry.ollama.ai";
+const DEFAULT_NAMESPACE: &str = "library";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Layer {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub config: Layer,
+    pub layers: Vec<Layer>,
+}
+
+/// A model name as Ollama stores it: `[host/][namespace/]name[:tag]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OllamaName {
+    pub host: String,
+    pub namespace: String,
+    pub name: String,
+    pub tag: String,
+}
+
+impl FromStr for OllamaName {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, tag) = match s.rsplit_once(':') {
+            Some((path, tag)) if !tag.contains('/') => (path, tag),
+            _ => (s, "latest"),
+        };
+        let parts: Vec<&str> = path.split('/').collect();
+        let (host, namespace, name) = match parts.as_slice() {
+            [name] => (DEFAULT_HOST, DEFAULT_NAMESPACE, *name),
+            [namespace, name] => (DEFAULT_HOST, *namespace, *name),
+            [host, namespace, name] => (*host, *namespace, *name),
+            _ => return Err(format!("Invalid Ollama model name `{}`", s)),
+        };
+        let is_valid = |part: &str| {
+            !part.is_empty()
+                && !part.starts_with('.')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        };
+        if [host, namespace, name, tag].into_iter().all(is_valid) {
+            Ok(OllamaName {
+                host: host.to_owned(),
+                namespace: namespace.to_owned(),
+                name: name.to_owned(),
+                tag: tag.to_owned(),
+            })
+        } else {
+            Err(format!("Invalid Ollama model name `{}`", s))
+        }
+    }
+}
+
+impl OllamaName {
+    /// The registry name an import goes by unless given one, outside the
+    /// library namespace the namespace stays in front: `user-model:tag`
+    pub fn registry_name(&self) -> String {
+        if self.namespace == DEFAULT_NAMESPACE {
+            format!("{}:{}", self.name, self.tag)
+        } else {
+            format!("{}-{}:{}", self.namespace, self.name, self.tag)
+        }
+    }
+}
+
+impl Display for OllamaName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host != DEFAULT_HOST {
+            write!(f, "{}/{}/", self.host, self.namespace)?;
+        } else if self.namespace != DEFAULT_NAMESPACE {
+            write!(f, "{}/", self.namespace)?;
+        }
+        write!(f, "{}:{}", self.name, self.tag)
+    }
+}
+
+/// Result of reconstructing a Modelfile from an Ollama manifest
+pub struct Imported {
+    pub modelfile: Modelfile,
+    /// Ollama parameters tiles doesn't support and left out
+    pub skipped: Vec<String>,
+}
+
+pub struct OllamaStore {
+    root: PathBuf,
+}
+
+impl OllamaStore {
+    /// The store of the local Ollama installation
+    pub fn open() -> Result<OllamaStore> {
+        Ok(OllamaStore::at(get_ollama_models_dir()?))
+    }
+
+    pub fn at(root: impl Into<PathBuf>) -> OllamaStore {
+        OllamaStore { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn manifest_path(&self, name: &OllamaName) -> PathBuf {
+        self.root
+            .join("manifests")
+            .join(&name.host)
+            .join(&name.namespace)
+            .join(&name.name)
+            .join(&name.tag)
+    }
+
+    pub fn blob_path(&self, digest: &str) -> PathBuf {
+        self.root.join("blobs").join(digest.replace(':', "-"))
+    }
+
+    pub fn read_manifest(&self, name: &OllamaName) -> Result<Manifest> {
+        let path = self.manifest_path(name);
+        let content = fs::read_to_string(&path)
+            .map_err(|_| anyhow!("{} not found in {}", name, self.root.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Corrupt manifest for {}", name))
+    }
+
+    /// Rebuilds the Modelfile for `name` from its manifest and blobs. Neither
+    /// backend runs Ollama's GGUF weights, so FROM is the MLX weights `aliases`
+    /// map the name to
+    pub fn import(&self, name: &OllamaName, aliases: &AliasTable) -> Result<Imported> {
+        let manifest = self.read_manifest(name)?;
+        let mut modelfile = Modelfile::new();
+        let mut skipped = vec![];
+        let mut licenses = vec![];
+
+        if !manifest
+            .layers
+            .iter()
+            .any(|layer| layer.media_type == MEDIA_TYPE_MODEL)
+        {
+            bail!("{} has no model layer", name);
+        }
+        let mlx = aliases
+            .lookup(&name.to_string())
+            .and_then(|alias| alias.mlx)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} only has GGUF weights, which tiles can't run. Map it to MLX weights with `tiles alias add {} --mlx <repo>` and import it again",
+                    name,
+                    name
+                )
+            })?;
+        add(modelfile.add_from(&mlx))?;
+
+        for layer in &manifest.layers {
+            match layer.media_type.as_str() {
+                MEDIA_TYPE_ADAPTER => {
+                    bail!(
+                        "{} has a GGUF adapter, which can't be applied to MLX weights",
+                        name
+                    )
+                }
+                MEDIA_TYPE_TEMPLATE => add(modelfile.add_template(&self.read_text(layer)?))?,
+                MEDIA_TYPE_SYSTEM => add(modelfile.add_system(&self.read_text(layer)?))?,
+                MEDIA_TYPE_LICENSE => licenses.push(self.read_text(layer)?),
+                MEDIA_TYPE_PARAMS => {
+                    let params: serde_json::Map<String, Value> =
+                        serde_json::from_str(&self.read_text(layer)?)
+                            .context("Params layer is not a JSON object")?;
+                    for (param, value) in params {
+                        if !PARAMETERS.iter().any(|(name, _)| *name == param) {
+                            skipped.push(param);
+                            continue;
+                        }
+                        let values = match value {
+                            Value::Array(values) => values,
+                            value => vec![value],
+                        };
+                        for value in values {
+                            let value = match value {
+                                Value::String(value) => value,
+                                value => value.to_string(),
+                            };
+                            add(modelfile.add_parameter(&param, &value))?;
+                        }
+                    }
+                }
+                MEDIA_TYPE_MESSAGES => {
+                    let messages: Vec<Value> = serde_json::from_str(&self.read_text(layer)?)
+                        .context("Messages layer is not a JSON array")?;
+                    for message in messages {
+                        let role = message["role"].as_str().unwrap_or_default();
+                        let content = message["content"].as_str().unwrap_or_default();
+                        add(modelfile.add_message(role, content))?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !licenses.is_empty() {
+            add(modelfile.add_license(&licenses.join("\n\n")))?;
+        }
+        add(modelfile.build())?;
+        Ok(Imported { modelfile, skipped })
+    }
+
+    /// Writes `modelfile` and its weights as `name`, so Ollama can run it
+    /// without `ollama create`
+    pub fn export(&self, name: &OllamaName, modelfile: &Modelfile) -> Result<Manifest> {
+        let weights = weights_path(modelfile)?;
+        let info = gguf::read_info(&weights)?;
+        let mut layers = vec![self.copy_blob(MEDIA_TYPE_MODEL, &weights)?];
+        if let Some(adapter) = &modelfile.adapter {
+            // MLX adapters are a directory of safetensors and a config, which
+            // Ollama can't load
+            if Path::new(adapter).is_dir() {
+                bail!(
+                    "ADAPTER {} is a directory of MLX adapter weights, but Ollama only loads a single GGUF adapter file",
+                    adapter
+                );
+            }
+            layers.push(self.copy_blob(MEDIA_TYPE_ADAPTER, Path::new(adapter))?);
+        }
+        if let Some(template) = &modelfile.template {
+            layers.push(self.write_blob(MEDIA_TYPE_TEMPLATE, template.as_bytes())?);
+        }
+        if let Some(system) = &modelfile.system {
+            layers.push(self.write_blob(MEDIA_TYPE_SYSTEM, system.as_bytes())?);
+        }
+        if !modelfile.parameters.is_empty() {
+            let mut params = Map::new();
+            for parameter in &modelfile.parameters {
+                let value = serde_json::to_value(&parameter.value)?;
+                if parameter.param_type == "stop" {
+                    let stops = params
+                        .entry(parameter.param_type.clone())
+                        .or_insert_with(|| Value::Array(vec![]));
+                    if let Value::Array(stops) = stops {
+                        stops.push(value);
+                    }
+                } else {
+                    params.insert(parameter.param_type.clone(), value);
+                }
+            }
+            let params = serde_json::to_vec(&params)?;
+            layers.push(self.write_blob(MEDIA_TYPE_PARAMS, &params)?);
+        }
+        if !modelfile.messages.is_empty() {
+            let messages = serde_json::to_vec(&modelfile.messages)?;
+            layers.push(self.write_blob(MEDIA_TYPE_MESSAGES, &messages)?);
+        }
+        if let Some(license) = &modelfile.license {
+            layers.push(self.write_blob(MEDIA_TYPE_LICENSE, license.as_bytes())?);
+        }
+
+        let architecture = match consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+        let config = json!({
+            "model_format": "gguf",
+            "model_family": info.architecture,
+            "model_families": [info.architecture],
+            "model_type": info.size,
+            "file_type": info.file_type,
+            "architecture": architecture,
+            "os": consts::OS,
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": layers.iter().map(|layer| &layer.digest).collect::<Vec<_>>(),
+            },
+        });
+        let config = self.write_blob(MEDIA_TYPE_CONFIG, &serde_json::to_vec(&config)?)?;
+
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: MEDIA_TYPE_MANIFEST.to_owned(),
+            config,
+            layers,
+        };
+        let path = self.manifest_path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create manifests directory")?;
+        }
+        fs::write(&path, serde_json::to_vec(&manifest)?).context("Failed to write manifest")?;
+        Ok(manifest)
+    }
+
+    fn write_blob(&self, media_type: &str, content: &[u8]) -> Result<Layer> {
+        let digest = sha256_digest(content);
+        let path = self.blob_path(&digest);
+        if !path.exists() {
+            fs::create_dir_all(self.root.join("blobs"))
+                .context("Failed to create blobs directory")?;
+            fs::write(&path, content)
+                .with_context(|| format!("Failed to write blob {}", digest))?;
+        }
+        Ok(Layer {
+            media_type: media_type.to_owned(),
+            digest,
+            size: content.len() as u64,
+        })
+    }
+
+    // Weights can be large, so they are hashed and copied as a stream
+    fn copy_blob(&self, media_type: &str, source: &Path) -> Result<Layer> {
+        let digest =
+            file_digest(source).with_context(|| format!("Failed to read {}", source.display()))?;
+        let path = self.blob_path(&digest);
+        if !path.exists() {
+            fs::create_dir_all(self.root.join("blobs"))
+                .context("Failed to create blobs directory")?;
+            let partial = path.with_extension("partial");
+            fs::copy(source, &partial)
+                .with_context(|| format!("Failed to copy {}", source.display()))?;
+            fs::rename(&partial, &path)?;
+        }
+        Ok(Layer {
+            media_type: media_type.to_owned(),
+            digest,
+            size: fs::metadata(&path)?.len(),
+        })
+    }
+
+    // Reads a text layer, checking it against its digest. Kept as is, since
+    // quoted Modelfile values keep their whitespace too
+    fn read_text(&self, layer: &Layer) -> Result<String> {
+        let path = self.blob_path(&layer.digest);
+        let content =
+            fs::read(&path).with_context(|| format!("Blob {} is missing", layer.digest))?;
+        if sha256_digest(&content) != layer.digest {
+            bail!("Blob {} does not match its digest", layer.digest);
+        }
+        String::from_utf8(content).with_context(|| format!("Blob {} is not text", layer.digest))
+    }
+
+    /// Checks a weights blob against its digest, which reads the whole file
+    pub fn verify_blob(&self, digest: &str) -> Result<()> {
+        let path = self.blob_path(digest);
+        if file_digest(&path)? != digest {
+            bail!("Blob {} does not match its digest", digest);
+        }
+        Ok(())
+    }
+}
+
+// Ollama only runs GGUF weights, Ollama names are looked up in the alias table
+fn weights_path(modelfile: &Modelfile) -> Result<PathBuf> {
+    let model = modelfile.model_ref().map_err(|err| anyhow!(err))?;
+    match &model {
+        ModelRef::OllamaBlob { path, .. }
+        | ModelRef::LocalFile {
+            path,
+            format: WeightsFormat::Gguf,
+        } => {
+            model.validate().map_err(|err| anyhow!(err))?;
+            Ok(path.clone())
+        }
+        ModelRef::OllamaTag { name, tag } => {
+            let name = format!("{}:{}", name, tag);
+            let gguf = AliasTable::load()?
+                .lookup(&name)
+                .and_then(|alias| alias.gguf);
+            match gguf {
+                Some(gguf) => {
+                    let mut modelfile = Modelfile::new();
+                    add(modelfile.add_from(&gguf))?;
+                    weights_path(&modelfile)
+                }
+                None => bail!(
+                    "No GGUF weights known for {}, add them with `tiles alias add {} --gguf <path>`",
+                    name,
+                    name
+                ),
+            }
+        }
+        _ => bail!("Ollama needs GGUF weights, {} can't be exported", model),
+    }
+}
+
+fn add(result: Result<(), String>) -> Result<()> {
+    result.map_err(|err| anyhow!(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        alias::Alias,
+        modelfile::ParamValue,
+        registry::{ModelName, Registry},
+    };
+
+    #[test]
+    fn test_parse_ollama_names() {
+        let name: OllamaName = "llama3.2".parse().unwrap();
+        assert_eq!(name.namespace, "library");
+        assert_eq!(name.tag, "latest");
+        assert_eq!(name.registry_name(), "llama3.2:latest");
+        let name: OllamaName = "x/llama3.2-vision:11b".parse().unwrap();
+        assert_eq!((name.namespace.as_str(), name.tag.as_str()), ("x", "11b"));
+        assert_eq!(name.to_string(), "x/llama3.2-vision:11b");
+        assert_eq!(name.registry_name(), "x-llama3.2-vision:11b");
+        assert!("a/b/c/d".parse::<OllamaName>().is_err());
+    }
+
+    fn aliases(dir: &Path, name: &str) -> Result<AliasTable> {
+        let mut aliases = AliasTable::load_from(dir.join("aliases.toml"))?;
+        let alias = Alias {
+            mlx: Some("mlx-community/TinyLlama-1.1B-Chat-v1.0-4bit".to_owned()),
+            gguf: None,
+        };
+        aliases.add(name, alias)?;
+        Ok(aliases)
+    }
+
+    #[test]
+    fn test_import_fixture_manifest() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = OllamaStore::at("fixtures/ollama");
+        let name: OllamaName = "tinyllama".parse().map_err(|e: String| anyhow!(e))?;
+        let err = store
+            .import(&name, &AliasTable::load_from(dir.path().join("none.toml"))?)
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("tiles alias add tinyllama:latest --mlx")
+        );
+
+        let imported = store.import(&name, &aliases(dir.path(), "tinyllama")?)?;
+        let modelfile = imported.modelfile;
+
+        assert_eq!(
+            modelfile.from.as_deref(),
+            Some("mlx-community/TinyLlama-1.1B-Chat-v1.0-4bit")
+        );
+        assert!(
+            modelfile
+                .template
+                .as_ref()
+                .unwrap()
+                .contains("{{ .Prompt }}")
+        );
+        assert_eq!(
+            modelfile.system.as_deref(),
+            Some("You are a tiny but helpful assistant.")
+        );
+        assert!(modelfile.license.as_ref().unwrap().starts_with("Apache"));
+        assert_eq!(modelfile.messages.len(), 2);
+        let stops = modelfile
+            .parameters
+            .iter()
+            .filter(|p| p.param_type == "stop")
+            .count();
+        assert_eq!(stops, 3);
+        assert!(
+            modelfile
+                .parameters
+                .iter()
+                .any(|p| p.param_type == "num_ctx" && p.value == ParamValue::Int(2048))
+        );
+        assert_eq!(imported.skipped, vec!["mirostat"]);
+        assert!(
+            store
+                .verify_blob(&store.read_manifest(&"tinyllama".parse().unwrap())?.layers[0].digest)
+                .is_ok()
+        );
+
+        let registry = Registry::at(dir.path().join("models"));
+        let name: ModelName = "tinyllama".parse().map_err(|e: String| anyhow!(e))?;
+        registry.create(&name, &modelfile)?;
+        assert_eq!(registry.get(&name)?.to_string(), modelfile.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_round_trips_through_import() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let weights = dir.path().join("tiny.gguf");
+        fs::write(&weights, gguf::test_header("llama", 15, &[32000, 34376]))?;
+        let modelfile = crate::core::modelfile::parse(&format!(
+            "FROM {}\nTEMPLATE {{{{ .Prompt }}}}\nSYSTEM Be brief.\nPARAMETER temperature 0.3\nPARAMETER stop <|user|>\nPARAMETER stop </s>\nMESSAGE user Hi\nLICENSE MIT",
+            weights.display()
+        ))
+        .map_err(|e| anyhow!(e))?;
+
+        let store = OllamaStore::at(dir.path().join("models"));
+        let name: OllamaName = "tiny:v1".parse().map_err(|e: String| anyhow!(e))?;
+        let manifest = store.export(&name, &modelfile)?;
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_MODEL);
+        assert_eq!(manifest.layers.len(), 6);
+        for layer in manifest.layers.iter().chain([&manifest.config]) {
+            store.verify_blob(&layer.digest)?;
+        }
+        let params = fs::read_to_string(store.blob_path(&manifest.layers[3].digest))?;
+        assert_eq!(params, r#"{"stop":["<|user|>","</s>"],"temperature":0.3}"#);
+        let config: Value =
+            serde_json::from_slice(&fs::read(store.blob_path(&manifest.config.digest))?)?;
+        assert_eq!(config["model_family"], "llama");
+        assert_eq!(config["model_type"], "1.1B");
+        assert_eq!(config["file_type"], "Q4_K_M");
+
+        let imported = store
+            .import(&name, &aliases(dir.path(), "tiny:v1")?)?
+            .modelfile;
+        assert_eq!(imported.template, modelfile.template);
+        assert_eq!(imported.system, modelfile.system);
+        assert_eq!(imported.license, modelfile.license);
+        assert_eq!(imported.messages.len(), 1);
+        assert_eq!(imported.parameters.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_needs_gguf_weights() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = OllamaStore::at(dir.path());
+        let modelfile =
+            crate::core::modelfile::parse("FROM mlx-community/gemma-2-2b-it-4bit").unwrap();
+        let err = store
+            .export(&"gemma".parse().unwrap(), &modelfile)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("GGUF"));
+    }
+
+    #[test]
+    fn test_export_rejects_adapter_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let weights = dir.path().join("tiny.gguf");
+        fs::write(&weights, gguf::test_header("llama", 15, &[32000, 34376]))?;
+        let adapter = dir.path().join("adapters");
+        fs::create_dir(&adapter)?;
+        fs::write(adapter.join("adapters.safetensors"), b"weights")?;
+        let modelfile = crate::core::modelfile::parse(&format!(
+            "FROM {}\nADAPTER {}",
+            weights.display(),
+            adapter.display()
+        ))
+        .map_err(|e| anyhow!(e))?;
+
+        let store = OllamaStore::at(dir.path().join("models"));
+        let err = store
+            .export(&"tiny".parse().map_err(|e: String| anyhow!(e))?, &modelfile)
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("only loads a single GGUF adapter file")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_model_is_reported() {
+        let store = OllamaStore::at("fixtures/ollama");
+        let aliases = AliasTable::load_from("fixtures/ollama/aliases.toml").unwrap();
+        let err = store
+            .import(&"missing".parse().unwrap(), &aliases)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("not found"));
+    }
+}
@@ -0,0 +1,366 @@
+// Local registry of named Modelfiles stored under the tiles data dir
+//
+// Layout:
+// models/blobs/sha256-<hex>          Modelfile content, addressed by its digest
+// models/manifests/<name>/<tag>      JSON manifest pointing at a blob
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::core::{
+    config::get_data_dir,
+    modelfile::{self, Modelfile},
+};
+
+/// A `name[:tag]` reference, the tag defaults to `latest`. Both are
+/// lowercased, so `MyBot:V2` and `mybot:v2` are the same model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelName {
+    pub name: String,
+    pub tag: String,
+}
+
+impl FromStr for ModelName {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, tag) = s.split_once(':').unwrap_or((s, "latest"));
+        let is_valid = |part: &str| {
+            !part.is_empty()
+                && !part.starts_with('.')
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        };
+        if is_valid(name) && is_valid(tag) {
+            Ok(ModelName {
+                name: name.to_lowercase(),
+                tag: tag.to_lowercase(),
+            })
+        } else {
+            Err(format!(
+                "Invalid model name `{}`, expected name[:tag] using letters, digits, `.`, `_` or `-`",
+                s
+            ))
+        }
+    }
+}
+
+impl Display for ModelName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.name, self.tag)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub digest: String,
+    pub size: u64,
+    pub from: String,
+    pub created: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: ModelName,
+    pub manifest: Manifest,
+}
+
+pub struct Registry {
+    root: PathBuf,
+}
+
+impl Registry {
+    /// The registry under the tiles data dir
+    pub fn open() -> Result<Registry> {
+        Ok(Registry::at(get_data_dir()?.join("models")))
+    }
+
+    pub fn at(root: impl Into<PathBuf>) -> Registry {
+        Registry { root: root.into() }
+    }
+
+    /// Stores `modelfile` under `name`, replacing whatever the tag pointed at
+    pub fn create(&self, name: &ModelName, modelfile: &Modelfile) -> Result<Entry> {
+        // Relative paths would otherwise be read from the blobs directory
+        let mut modelfile = modelfile.clone();
+        modelfile.anchor_paths(&env::current_dir().context("Failed to fetch CURRENT_DIR")?);
+        let content = modelfile.to_string();
+        let digest = sha256_digest(content.as_bytes());
+        let blob = self.blob_path(&digest);
+        fs::create_dir_all(self.root.join("blobs")).context("Failed to create blobs directory")?;
+        if !blob.exists() {
+            fs::write(&blob, &content).context("Failed to write Modelfile blob")?;
+        }
+
+        let manifest = Manifest {
+            digest,
+            size: content.len() as u64,
+            from: modelfile.from.clone().unwrap_or_default(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        };
+        self.write_manifest(name, &manifest)?;
+        Ok(Entry {
+            name: name.clone(),
+            manifest,
+        })
+    }
+
+    pub fn manifest(&self, name: &ModelName) -> Result<Manifest> {
+        let path = self.manifest_path(name);
+        let content = fs::read_to_string(&path).map_err(|_| anyhow!("Model {} not found", name))?;
+        serde_json::from_str(&content).with_context(|| format!("Corrupt manifest for {}", name))
+    }
+
+    /// Path of the stored Modelfile for `name`, after checking its digest
+    pub fn modelfile_path(&self, name: &ModelName) -> Result<PathBuf> {
+        let manifest = self.manifest(name)?;
+        let blob = self.blob_path(&manifest.digest);
+        let content =
+            fs::read(&blob).with_context(|| format!("Blob {} is missing", manifest.digest))?;
+        if sha256_digest(&content) != manifest.digest {
+            bail!("Blob {} does not match its digest", manifest.digest);
+        }
+        Ok(blob)
+    }
+
+    pub fn get(&self, name: &ModelName) -> Result<Modelfile> {
+        let path = self.modelfile_path(name)?;
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        modelfile::parse_literal(&content).map_err(|err| anyhow!(err))
+    }
+
+    /// Whether `path` is one of the stored Modelfiles
+    pub fn stores(&self, path: &Path) -> bool {
+        let blobs = self.root.join("blobs").canonicalize();
+        path.parent()
+            .and_then(|parent| parent.canonicalize().ok())
+            .is_some_and(|parent| blobs.is_ok_and(|blobs| blobs == parent))
+    }
+
+    pub fn list(&self) -> Result<Vec<Entry>> {
+        let mut entries = vec![];
+        let manifests = self.root.join("manifests");
+        if !manifests.is_dir() {
+            return Ok(entries);
+        }
+        for model_dir in fs::read_dir(&manifests)?.flatten() {
+            for tag_file in fs::read_dir(model_dir.path())?.flatten() {
+                let name = ModelName {
+                    name: model_dir.file_name().to_string_lossy().to_string(),
+                    tag: tag_file.file_name().to_string_lossy().to_string(),
+                };
+                if let Ok(manifest) = self.manifest(&name) {
+                    entries.push(Entry { name, manifest });
+                }
+            }
+        }
+        entries.sort_by(|a, b| a.name.to_string().cmp(&b.name.to_string()));
+        Ok(entries)
+    }
+
+    pub fn copy(&self, source: &ModelName, destination: &ModelName) -> Result<()> {
+        let manifest = self.manifest(source)?;
+        self.write_manifest(destination, &manifest)
+    }
+
+    /// Removes the tag and deletes its blob once nothing references it
+    pub fn remove(&self, name: &ModelName) -> Result<()> {
+        let manifest = self.manifest(name)?;
+        let path = self.manifest_path(name);
+        fs::remove_file(&path).context("Failed to remove manifest")?;
+        if let Some(model_dir) = path.parent()
+            && fs::read_dir(model_dir)?.next().is_none()
+        {
+            fs::remove_dir(model_dir)?;
+        }
+        let still_used = self
+            .list()?
+            .iter()
+            .any(|entry| entry.manifest.digest == manifest.digest);
+        if !still_used {
+            let _ = fs::remove_file(self.blob_path(&manifest.digest));
+        }
+        Ok(())
+    }
+
+    fn write_manifest(&self, name: &ModelName, manifest: &Manifest) -> Result<()> {
+        let path = self.manifest_path(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create manifests directory")?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(manifest)?)
+            .context("Failed to write manifest")
+    }
+
+    fn manifest_path(&self, name: &ModelName) -> PathBuf {
+        self.root.join("manifests").join(&name.name).join(&name.tag)
+    }
+
+    fn blob_path(&self, digest: &str) -> PathBuf {
+        self.root.join("blobs").join(digest.replace(':', "-"))
+    }
+}
+
+/// Finds the Modelfile a CLI argument refers to. A registered name wins over
+/// a file of the same name in the working directory; anything that can't be
+/// a name, like `./bot` or `/tmp/Modelfile`, is a path
+pub fn locate(reference: &str) -> Result<PathBuf, String> {
+    locate_in(reference, &Registry::open().map_err(|err| err.to_string())?)
+}
+
+pub fn locate_in(reference: &str, registry: &Registry) -> Result<PathBuf, String> {
+    match as_name(reference, registry) {
+        Some(name) => registry
+            .modelfile_path(&name)
+            .map_err(|err| err.to_string()),
+        None if Path::new(reference).exists() => Ok(PathBuf::from(reference)),
+        None => Err(format!("No Modelfile or model named `{}`", reference)),
+    }
+}
+
+/// The name a Modelfile path or registered name goes by, following the same
+/// precedence as `locate`
+pub fn name_for(reference: &str) -> Result<ModelName, String> {
+    let registry = Registry::open().map_err(|err| err.to_string())?;
+    match as_name(reference, &registry) {
+        Some(name) => Ok(name),
+        None => name_for_path(Path::new(reference)),
+    }
+}
+
+// A reference is a name when it parses as one and is registered, or when
+// there is no file by that name either
+fn as_name(reference: &str, registry: &Registry) -> Option<ModelName> {
+    let name = reference.parse::<ModelName>().ok()?;
+    (registry.manifest(&name).is_ok() || !Path::new(reference).exists()).then_some(name)
+}
+
+/// `bot.modelfile` and `Modelfile.bot` go by bot, `bot/Modelfile` too
+pub fn name_for_path(path: &Path) -> Result<ModelName, String> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = if let Some(name) = file_name.strip_prefix("Modelfile.") {
+        name.to_owned()
+    } else if file_name == "Modelfile" {
+        path.canonicalize()
+            .ok()
+            .and_then(|path| path.parent()?.file_name().map(|name| name.to_owned()))
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    } else {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    name.to_lowercase().parse()
+}
+
+pub fn sha256_digest(content: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(s: &str) -> ModelName {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_model_name_parsing() {
+        assert_eq!(name("mybot").to_string(), "mybot:latest");
+        assert_eq!(name("MyBot:V2").to_string(), "mybot:v2");
+        assert_eq!(name("MyBot:V2"), name("mybot:v2"));
+        assert!("my bot".parse::<ModelName>().is_err());
+        assert!("../bot".parse::<ModelName>().is_err());
+        assert!("bot:".parse::<ModelName>().is_err());
+    }
+
+    #[test]
+    fn test_locate_prefers_registered_names() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = Registry::at(dir.path());
+        let modelfile =
+            modelfile::parse_from_file("fixtures/a.modelfile").map_err(|e| anyhow!(e))?;
+        // Both exist in the crate root, the working directory of tests
+        registry.create(&name("Cargo.toml"), &modelfile)?;
+        let path = locate_in("Cargo.toml", &registry).map_err(|e| anyhow!(e))?;
+        assert!(registry.stores(&path));
+        let path = locate_in("./Cargo.toml", &registry).map_err(|e| anyhow!(e))?;
+        assert_eq!(path, PathBuf::from("./Cargo.toml"));
+        let path = locate_in("README.md", &registry).map_err(|e| anyhow!(e))?;
+        assert_eq!(path, PathBuf::from("README.md"));
+        let err = locate_in("missing", &registry).unwrap_err();
+        assert!(err.contains("not found"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_list_copy_remove() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = Registry::at(dir.path());
+        let modelfile =
+            modelfile::parse_from_file("fixtures/a.modelfile").map_err(|e| anyhow!(e))?;
+
+        let entry = registry.create(&name("mybot"), &modelfile)?;
+        assert!(entry.manifest.digest.starts_with("sha256:"));
+        let stored = registry.get(&name("mybot:latest"))?;
+        assert_eq!(stored.to_string(), modelfile.to_string());
+
+        registry.copy(&name("mybot"), &name("mybot:v2"))?;
+        assert_eq!(registry.list()?.len(), 2);
+
+        registry.remove(&name("mybot"))?;
+        assert!(registry.get(&name("mybot:v2")).is_ok());
+        registry.remove(&name("mybot:v2"))?;
+        assert!(registry.list()?.is_empty());
+        assert_eq!(fs::read_dir(dir.path().join("blobs"))?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stored_modelfiles_keep_literals_and_paths() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = Registry::at(dir.path());
+        let modelfile = modelfile::parse(
+            "FROM llama3.2\nADAPTER ./lora.safetensors\nSYSTEM Write $${NAME} for the name",
+        )
+        .map_err(|e| anyhow!(e))?;
+        registry.create(&name("mybot"), &modelfile)?;
+        let stored = registry.get(&name("mybot"))?;
+        assert_eq!(stored.system.as_deref(), Some("Write ${NAME} for the name"));
+        let adapter = PathBuf::from(stored.adapter.unwrap_or_default());
+        assert_eq!(adapter, env::current_dir()?.join("lora.safetensors"));
+        let blob = registry.modelfile_path(&name("mybot"))?;
+        assert!(registry.stores(&blob));
+        assert!(!registry.stores(Path::new("fixtures/a.modelfile")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_blob_is_rejected() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = Registry::at(dir.path());
+        let modelfile = modelfile::parse("FROM llama3.2").map_err(|e| anyhow!(e))?;
+        let entry = registry.create(&name("mybot"), &modelfile)?;
+        fs::write(registry.blob_path(&entry.manifest.digest), "FROM other")?;
+        assert!(registry.get(&name("mybot")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+// Settings read from `tiles.toml` under the tiles config dir, every field
+// is optional:
+//
+// [scheduler]
+// max_concurrent = 1
+// max_queue = 16
+// queue_timeout = 300
+// request_timeout = 600
+//
+// [scheduler.concurrency]
+// "driaforall/mem-agent" = 2
+//
+// [server]
+// keep_alive = 300
+// max_loaded = 1
+//
+// [metrics]
+// enabled = true
+// address = "127.0.0.1:6971"
+//
+// [service.environment]
+// HF_HOME = "/data/huggingface"
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path, time::Duration};
+
+use crate::core::config::get_config_dir;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub scheduler: SchedulerSettings,
+    pub metrics: MetricsSettings,
+    pub service: ServiceSettings,
+}
+
+/// How the server holds models in memory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// Seconds an idle model stays loaded unless its Modelfile sets
+    /// PARAMETER keep_alive, negative keeps it until `tiles server unload`
+    pub keep_alive: i64,
+    /// Models loaded at once, loading another unloads the least recently used
+    pub max_loaded: usize,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            keep_alive: 300,
+            max_loaded: 1,
+        }
+    }
+}
+
+/// How the proxy in front of the server queues generations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerSettings {
+    /// Generations running at once for each model
+    pub max_concurrent: usize,
+    /// Generations waiting for each model before new ones are turned away
+    pub max_queue: usize,
+    /// Seconds a generation may wait in the queue
+    pub queue_timeout: u64,
+    /// Seconds a generation may run before it is cancelled
+    pub request_timeout: u64,
+    /// `max_concurrent` for particular models
+    pub concurrency: BTreeMap<String, usize>,
+}
+
+impl Default for SchedulerSettings {
+    fn default() -> Self {
+        SchedulerSettings {
+            max_concurrent: 1,
+            max_queue: 16,
+            queue_timeout: 300,
+            request_timeout: 600,
+            concurrency: BTreeMap::new(),
+        }
+    }
+}
+
+/// Where the proxy serves `/metrics` without a token, for Prometheus to scrape
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    pub address: String,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings {
+            enabled: true,
+            address: "127.0.0.1:6971".to_owned(),
+        }
+    }
+}
+
+/// Goes into the systemd unit `tiles server install-service` writes
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServiceSettings {
+    /// Variables the service runs with, on top of the PATH tiles was installed with
+    pub environment: BTreeMap<String, String>,
+}
+
+impl SchedulerSettings {
+    pub fn limit(&self, model: &str) -> usize {
+        self.concurrency
+            .get(model)
+            .copied()
+            .unwrap_or(self.max_concurrent)
+            .max(1)
+    }
+
+    pub fn queue_timeout(&self) -> Duration {
+        Duration::from_secs(self.queue_timeout)
+    }
+
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout)
+    }
+}
+
+impl Settings {
+    pub fn load() -> Result<Settings> {
+        Settings::load_from(&get_config_dir()?.join("tiles.toml"))
+    }
+
+    /// Defaults when the file doesn't exist
+    pub fn load_from(path: &Path) -> Result<Settings> {
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tiles.toml");
+        assert_eq!(Settings::load_from(&path).unwrap(), Settings::default());
+
+        fs::write(
+            &path,
+            "[scheduler]\nqueue_timeout = 5\n\n[scheduler.concurrency]\n\"driaforall/mem-agent\" = 2\n",
+        )
+        .unwrap();
+        let settings = Settings::load_from(&path).unwrap();
+        assert_eq!(settings.scheduler.queue_timeout(), Duration::from_secs(5));
+        assert_eq!(settings.scheduler.max_queue, 16);
+        assert_eq!(settings.scheduler.limit("driaforall/mem-agent"), 2);
+        assert_eq!(settings.scheduler.limit("other"), 1);
+
+        assert!(settings.metrics.enabled);
+        assert_eq!(settings.server.keep_alive, 300);
+
+        fs::write(&path, "[scheduler]\nmax_concurency = 2\n").unwrap();
+        assert!(Settings::load_from(&path).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+// Installs the Python server project and prepares its venv with uv
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::core::config::get_server_dir;
+
+/// The server project shipped inside the binary, as paths relative to the server dir
+const SERVER_FILES: &[(&str, &str)] = &[
+    (".gitignore", include_str!("../../server/.gitignore")),
+    ("__init__.py", include_str!("../../server/__init__.py")),
+    ("api.py", include_str!("../../server/api.py")),
+    (
+        "cache_utils.py",
+        include_str!("../../server/cache_utils.py"),
+    ),
+    ("config.py", include_str!("../../server/config.py")),
+    ("main.py", include_str!("../../server/main.py")),
+    (
+        "mem_agent/__init__.py",
+        include_str!("../../server/mem_agent/__init__.py"),
+    ),
+    (
+        "mem_agent/engine.py",
+        include_str!("../../server/mem_agent/engine.py"),
+    ),
+    (
+        "mem_agent/tools.py",
+        include_str!("../../server/mem_agent/tools.py"),
+    ),
+    (
+        "mem_agent/utils.py",
+        include_str!("../../server/mem_agent/utils.py"),
+    ),
+    ("mlx_runner.py", include_str!("../../server/mlx_runner.py")),
+    ("model_card.py", include_str!("../../server/model_card.py")),
+    (
+        "pyproject.toml",
+        include_str!("../../server/pyproject.toml"),
+    ),
+    (
+        "reasoning_utils.py",
+        include_str!("../../server/reasoning_utils.py"),
+    ),
+    (
+        "system_prompt.txt",
+        include_str!("../../server/system_prompt.txt"),
+    ),
+    ("uv.lock", include_str!("../../server/uv.lock")),
+];
+
+/// Written once the venv is synced and imports work, read back before starting the server
+const STAMP_FILE: &str = ".tiles_setup.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stamp {
+    /// Version in the server's pyproject.toml
+    pub version: String,
+    /// Version of the tiles binary that ran the setup
+    pub tiles: String,
+    /// sha256 of the uv.lock the venv was synced from
+    pub lock: String,
+}
+
+/// What `setup` did, so running it again can report that nothing changed
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub server_dir: PathBuf,
+    pub version: String,
+    pub written: Vec<String>,
+    pub synced: bool,
+}
+
+/// The server project tiles uses, installing it when needed
+pub struct ServerEnv {
+    dir: PathBuf,
+}
+
+impl ServerEnv {
+    pub fn open() -> Result<ServerEnv> {
+        Ok(ServerEnv::at(get_server_dir()?))
+    }
+
+    pub fn at(dir: PathBuf) -> ServerEnv {
+        ServerEnv { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Installs the server, syncs its venv and verifies its imports. Steps
+    /// that are already done are skipped unless `force` is set
+    pub fn setup(&self, force: bool) -> Result<Outcome> {
+        let written = self.install()?;
+        let version = self.version()?;
+        let expected = Stamp {
+            version: version.clone(),
+            tiles: env!("CARGO_PKG_VERSION").to_owned(),
+            lock: self.lock_digest()?,
+        };
+        let mut outcome = Outcome {
+            server_dir: self.dir.clone(),
+            version,
+            written,
+            synced: false,
+        };
+        if !force && self.stamp().as_ref() == Some(&expected) && self.has_venv() {
+            return Ok(outcome);
+        }
+        // The stamp must not outlive a failed sync
+        let _ = fs::remove_file(self.dir.join(STAMP_FILE));
+        self.sync()?;
+        self.verify_imports()?;
+        fs::write(
+            self.dir.join(STAMP_FILE),
+            serde_json::to_string_pretty(&expected)?,
+        )
+        .context("Failed to record the server version")?;
+        outcome.synced = true;
+        Ok(outcome)
+    }
+
+    /// Writes the bundled server files that are missing or out of date.
+    /// Debug builds run the server from the checkout, which is never overwritten
+    pub fn install(&self) -> Result<Vec<String>> {
+        if cfg!(debug_assertions) && self.dir.join("pyproject.toml").is_file() {
+            return Ok(vec![]);
+        }
+        let mut written = vec![];
+        for (path, content) in SERVER_FILES {
+            let target = self.dir.join(path);
+            if fs::read(&target).is_ok_and(|current| current == content.as_bytes()) {
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            fs::write(&target, content)
+                .with_context(|| format!("Failed to write {}", target.display()))?;
+            written.push(path.to_string());
+        }
+        Ok(written)
+    }
+
+    /// `project.version` from the server's pyproject.toml
+    pub fn version(&self) -> Result<String> {
+        let path = self.dir.join("pyproject.toml");
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        parse_version(&content).with_context(|| format!("No project.version in {}", path.display()))
+    }
+
+    /// The recorded setup, if any
+    pub fn stamp(&self) -> Option<Stamp> {
+        let content = fs::read_to_string(self.dir.join(STAMP_FILE)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Whether a finished setup matches the current uv.lock
+    pub fn is_ready(&self) -> bool {
+        match (self.stamp(), self.lock_digest()) {
+            (Some(stamp), Ok(lock)) => stamp.lock == lock && self.has_venv(),
+            _ => false,
+        }
+    }
+
+    fn has_venv(&self) -> bool {
+        self.dir.join(".venv/pyvenv.cfg").is_file()
+    }
+
+    fn lock_digest(&self) -> Result<String> {
+        let path = self.dir.join("uv.lock");
+        let content =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(format!("{:x}", Sha256::digest(content)))
+    }
+
+    fn sync(&self) -> Result<()> {
+        let status = Command::new("uv")
+            .arg("sync")
+            .arg("--frozen")
+            .arg("--project")
+            .arg(&self.dir)
+            .status()
+            .context("Failed to run uv, install it from https://docs.astral.sh/uv")?;
+        if !status.success() {
+            bail!("uv sync failed with {}", status);
+        }
+        Ok(())
+    }
+
+    fn verify_imports(&self) -> Result<()> {
+        let output = Command::new("uv")
+            .arg("run")
+            .arg("--frozen")
+            .arg("--project")
+            .arg(&self.dir)
+            .args(["python", "-c", import_check()])
+            .output()
+            .context("Failed to run uv")?;
+        if !output.status.success() {
+            bail!(
+                "The server environment can't import its dependencies:\n{}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+// mlx only installs on Apple Silicon, elsewhere the web stack is all that can load
+fn import_check() -> &'static str {
+    if env::consts::OS == "macos" && env::consts::ARCH == "aarch64" {
+        "import server.api"
+    } else {
+        "import fastapi, uvicorn"
+    }
+}
+
+fn parse_version(pyproject: &str) -> Option<String> {
+    let value: toml::Table = pyproject.parse().ok()?;
+    value
+        .get("project")?
+        .get("version")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundle_covers_server_project() {
+        // Compared against what git tracks, so local caches and venvs don't matter
+        let output = Command::new("git")
+            .args(["ls-files", "server"])
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .unwrap();
+        if !output.status.success() {
+            eprintln!("skipping, not a git checkout");
+            return;
+        }
+        let mut tracked: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|path| path.trim_start_matches("server/").to_owned())
+            .collect();
+        tracked.sort();
+        let bundled: Vec<&str> = SERVER_FILES.iter().map(|(path, _)| *path).collect();
+        assert_eq!(tracked, bundled);
+    }
+
+    #[test]
+    fn test_install_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = ServerEnv::at(dir.path().join("server"));
+        assert_eq!(server.install().unwrap().len(), SERVER_FILES.len());
+        assert!(server.install().unwrap().is_empty());
+        assert_eq!(server.version().unwrap(), "0.1.0");
+        assert!(!server.is_ready());
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let pyproject = "[project]\nname = \"server\"\nversion = \"0.2.1\"\n";
+        assert_eq!(parse_version(pyproject).as_deref(), Some("0.2.1"));
+        assert_eq!(parse_version("[tool.uv]\n"), None);
+    }
+}
@@ -0,0 +1,813 @@
+// Renders the Go template subset Modelfile TEMPLATEs use
+
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    /// `.Role` or `.` itself
+    Path(Vec<String>),
+    /// `$i`, `$m.Content`, or `$.System` where `$` is the data rendered
+    Var {
+        name: String,
+        fields: Vec<String>,
+    },
+    Literal(Value),
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Print(Expr),
+    /// `$x := value` declares, `$x = value` assigns, neither prints
+    Set {
+        name: String,
+        value: Expr,
+        declare: bool,
+    },
+    If {
+        branches: Vec<(Expr, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+    /// `vars` binds the element, or the index then the element
+    Range {
+        vars: Vec<String>,
+        over: Expr,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    With {
+        vars: Vec<String>,
+        value: Expr,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A parsed TEMPLATE
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+    uses_messages: bool,
+}
+
+/// One turn of a conversation as templates see it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Turn {
+    pub role: String,
+    pub content: String,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let tokens = lex(source)?;
+        let mut tokens = tokens.into_iter().peekable();
+        let (nodes, end) = parse_nodes(&mut tokens)?;
+        if let Some(end) = end {
+            return Err(format!("unexpected {{{{ {} }}}} in template", end));
+        }
+        Ok(Template {
+            nodes,
+            uses_messages: source.contains(".Messages"),
+        })
+    }
+
+    /// Renders against arbitrary data, such as `{"System": .., "Prompt": ..}`
+    pub fn render(&self, data: &Value) -> Result<String, String> {
+        let mut out = String::new();
+        let mut vars = vec![(String::new(), data.clone())];
+        render_nodes(&self.nodes, data, &mut vars, &mut out)?;
+        Ok(out)
+    }
+
+    /// Renders a conversation the way Ollama does: templates that read
+    /// `.Messages` get it whole, older ones get `.Prompt` and `.Response`
+    /// once per exchange with `.System` on the first
+    pub fn render_chat(&self, system: Option<&str>, turns: &[Turn]) -> Result<String, String> {
+        if self.uses_messages {
+            let messages: Vec<Value> = system
+                .map(|system| json!({"Role": "system", "Content": system}))
+                .into_iter()
+                .chain(
+                    turns
+                        .iter()
+                        .map(|turn| json!({"Role": turn.role, "Content": turn.content})),
+                )
+                .collect();
+            return self.render(&json!({
+                "System": system.unwrap_or_default(),
+                "Messages": messages,
+            }));
+        }
+        let mut out = String::new();
+        let mut system = system.map(str::to_owned);
+        let mut prompt: Option<&str> = None;
+        for turn in turns {
+            match turn.role.as_str() {
+                "system" => system = Some(turn.content.clone()),
+                "user" => {
+                    if let Some(prompt) = prompt.take() {
+                        out += &self.render_exchange(system.take(), prompt, "")?;
+                    }
+                    prompt = Some(&turn.content);
+                }
+                _ => {
+                    out += &self.render_exchange(
+                        system.take(),
+                        prompt.take().unwrap_or_default(),
+                        &turn.content,
+                    )?;
+                }
+            }
+        }
+        if prompt.is_some() || system.is_some() {
+            out += &self.render_exchange(system, prompt.unwrap_or_default(), "")?;
+        }
+        Ok(out)
+    }
+
+    fn render_exchange(
+        &self,
+        system: Option<String>,
+        prompt: &str,
+        response: &str,
+    ) -> Result<String, String> {
+        self.render(&json!({
+            "System": system.unwrap_or_default(),
+            "Prompt": prompt,
+            "Response": response,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Text(String),
+    Action(String),
+}
+
+fn lex(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source;
+    let mut trim_next = false;
+    while let Some(start) = rest.find("{{") {
+        let mut text = &rest[..start];
+        if trim_next {
+            text = text.trim_start();
+        }
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| "unclosed {{ in template".to_owned())?;
+        let mut action = &after[..end];
+        if let Some(trimmed) = action.strip_prefix('-') {
+            text = text.trim_end();
+            action = trimmed;
+        }
+        trim_next = false;
+        if let Some(trimmed) = action.strip_suffix('-') {
+            trim_next = true;
+            action = trimmed;
+        }
+        if !text.is_empty() {
+            tokens.push(Token::Text(text.to_owned()));
+        }
+        let action = action.trim();
+        if !(action.starts_with("/*") && action.ends_with("*/")) {
+            tokens.push(Token::Action(action.to_owned()));
+        }
+        rest = &after[end + 2..];
+    }
+    let text = if trim_next { rest.trim_start() } else { rest };
+    if !text.is_empty() {
+        tokens.push(Token::Text(text.to_owned()));
+    }
+    Ok(tokens)
+}
+
+type Tokens = std::iter::Peekable<std::vec::IntoIter<Token>>;
+
+// Parses until `else`/`end`, returning the action that stopped it
+fn parse_nodes(tokens: &mut Tokens) -> Result<(Vec<Node>, Option<String>), String> {
+    let mut nodes = vec![];
+    while let Some(token) = tokens.next() {
+        let action = match token {
+            Token::Text(text) => {
+                nodes.push(Node::Text(text));
+                continue;
+            }
+            Token::Action(action) => action,
+        };
+        let (keyword, rest) = action.split_once(' ').unwrap_or((action.as_str(), ""));
+        match keyword {
+            "end" | "else" => return Ok((nodes, Some(action))),
+            "if" => {
+                let mut branches = vec![];
+                let mut condition = parse_expr(rest)?;
+                let otherwise = loop {
+                    let (body, end) = parse_nodes(tokens)?;
+                    branches.push((condition, body));
+                    match end.as_deref() {
+                        Some("end") => break vec![],
+                        Some("else") => break parse_block_end(tokens)?,
+                        Some(end) if end.starts_with("else if ") => {
+                            condition = parse_expr(&end["else if ".len()..])?;
+                        }
+                        _ => return Err("{{ if }} without {{ end }} in template".to_owned()),
+                    }
+                };
+                nodes.push(Node::If {
+                    branches,
+                    otherwise,
+                });
+            }
+            "range" | "with" => {
+                let (vars, value) = parse_declaration(rest)?;
+                let value = parse_expr(value)?;
+                let (body, end) = parse_nodes(tokens)?;
+                let otherwise = match end.as_deref() {
+                    Some("end") => vec![],
+                    Some("else") => parse_block_end(tokens)?,
+                    _ => {
+                        return Err(format!(
+                            "{{{{ {} }}}} without {{{{ end }}}} in template",
+                            keyword
+                        ));
+                    }
+                };
+                nodes.push(if keyword == "range" {
+                    Node::Range {
+                        vars,
+                        over: value,
+                        body,
+                        otherwise,
+                    }
+                } else {
+                    Node::With {
+                        vars,
+                        value,
+                        body,
+                        otherwise,
+                    }
+                });
+            }
+            _ => nodes.push(match parse_assignment(&action) {
+                Some((name, value, declare)) => Node::Set {
+                    name: name.to_owned(),
+                    value: parse_expr(value)?,
+                    declare,
+                },
+                None => Node::Print(parse_expr(&action)?),
+            }),
+        }
+    }
+    Ok((nodes, None))
+}
+
+// The `else` part of a block, which has to close with `end`
+fn parse_block_end(tokens: &mut Tokens) -> Result<Vec<Node>, String> {
+    match parse_nodes(tokens)? {
+        (nodes, Some(end)) if end == "end" => Ok(nodes),
+        _ => Err("{{ else }} without {{ end }} in template".to_owned()),
+    }
+}
+
+// `range $i, $m := .Messages` binds variables, `.` works the same inside
+fn parse_declaration(expr: &str) -> Result<(Vec<String>, &str), String> {
+    let Some((names, value)) = expr.split_once(":=") else {
+        return Ok((vec![], expr));
+    };
+    let vars = names
+        .split(',')
+        .map(|name| {
+            variable_name(name.trim())
+                .map(str::to_owned)
+                .ok_or_else(|| format!("bad variable {} in template", name.trim()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if vars.len() > 2 {
+        return Err(format!(
+            "too many variables in {} in template",
+            names.trim()
+        ));
+    }
+    Ok((vars, value.trim()))
+}
+
+// `$x := value` or `$x = value`, with whether it declares `$x`
+fn parse_assignment(action: &str) -> Option<(&str, &str, bool)> {
+    let (name, value, declare) = match action.split_once(":=") {
+        Some((name, value)) => (name, value, true),
+        None => {
+            let (name, value) = action.split_once('=')?;
+            (name, value, false)
+        }
+    };
+    Some((variable_name(name.trim())?, value.trim(), declare))
+}
+
+// `x` for `$x`, `_` included
+fn variable_name(word: &str) -> Option<&str> {
+    let name = word.strip_prefix('$')?;
+    (!name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')).then_some(name)
+}
+
+fn parse_expr(source: &str) -> Result<Expr, String> {
+    let words = split_words(source)?;
+    let mut words = words.iter().map(String::as_str).peekable();
+    let expr = parse_call(&mut words)?;
+    match words.next() {
+        None => Ok(expr),
+        Some(word) => Err(format!("unexpected {} in template", word)),
+    }
+}
+
+fn parse_call<'a>(
+    words: &mut std::iter::Peekable<impl Iterator<Item = &'a str>>,
+) -> Result<Expr, String> {
+    let first = words
+        .next()
+        .ok_or_else(|| "empty action in template".to_owned())?;
+    if first.starts_with(['.', '$', '"', '('])
+        || first.parse::<f64>().is_ok()
+        || matches!(first, "true" | "false" | "nil")
+    {
+        return parse_operand(first);
+    }
+    let mut args = vec![];
+    while let Some(word) = words.peek() {
+        if *word == ")" {
+            break;
+        }
+        args.push(parse_operand(words.next().unwrap())?);
+    }
+    Ok(Expr::Call(first.to_owned(), args))
+}
+
+fn parse_operand(word: &str) -> Result<Expr, String> {
+    if let Some(inner) = word.strip_prefix('(').and_then(|w| w.strip_suffix(')')) {
+        return parse_expr(inner);
+    }
+    if word.starts_with('"') {
+        return serde_json::from_str(word)
+            .map(Expr::Literal)
+            .map_err(|_| format!("bad string {} in template", word));
+    }
+    if let Ok(number) = word.parse::<i64>() {
+        return Ok(Expr::Literal(json!(number)));
+    }
+    if let Ok(number) = word.parse::<f64>() {
+        return Ok(Expr::Literal(json!(number)));
+    }
+    match word {
+        "true" => return Ok(Expr::Literal(json!(true))),
+        "false" => return Ok(Expr::Literal(json!(false))),
+        "nil" => return Ok(Expr::Literal(Value::Null)),
+        _ => {}
+    }
+    let fields = |path: &str| {
+        path.split('.')
+            .filter(|field| !field.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+    let Some(var) = word.strip_prefix('$') else {
+        if !word.starts_with('.') {
+            return Err(format!("unexpected {} in template", word));
+        }
+        return Ok(Expr::Path(fields(word)));
+    };
+    let (name, path) = var.split_at(var.find('.').unwrap_or(var.len()));
+    if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("bad variable ${} in template", var));
+    }
+    Ok(Expr::Var {
+        name: name.to_owned(),
+        fields: fields(path),
+    })
+}
+
+// Splits on spaces, keeping strings and parenthesized groups whole
+fn split_words(source: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut chars = source.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            word.push(chars.next().unwrap());
+            let mut escaped = false;
+            for c in chars.by_ref() {
+                word.push(c);
+                if c == '"' && !escaped {
+                    break;
+                }
+                escaped = c == '\\' && !escaped;
+            }
+        } else if c == '(' {
+            let mut depth = 0;
+            for c in chars.by_ref() {
+                word.push(c);
+                depth += match c {
+                    '(' => 1,
+                    ')' => -1,
+                    _ => 0,
+                };
+                if depth == 0 {
+                    break;
+                }
+            }
+            if depth != 0 {
+                return Err("unbalanced ( in template".to_owned());
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+// Variables live until the end of the block that declared them, `$` first
+type Vars = Vec<(String, Value)>;
+
+fn render_nodes(
+    nodes: &[Node],
+    dot: &Value,
+    vars: &mut Vars,
+    out: &mut String,
+) -> Result<(), String> {
+    let scope = vars.len();
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Print(expr) => match eval(expr, dot, vars)? {
+                Value::String(text) => out.push_str(&text),
+                Value::Null => {}
+                value => out.push_str(&value.to_string()),
+            },
+            Node::Set {
+                name,
+                value,
+                declare,
+            } => {
+                let value = eval(value, dot, vars)?;
+                if *declare {
+                    vars.push((name.clone(), value));
+                } else {
+                    let var = vars
+                        .iter_mut()
+                        .rev()
+                        .find(|(var, _)| var == name)
+                        .ok_or_else(|| format!("undefined variable ${} in template", name))?;
+                    var.1 = value;
+                }
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let mut chosen = otherwise;
+                for (condition, body) in branches {
+                    if truthy(&eval(condition, dot, vars)?) {
+                        chosen = body;
+                        break;
+                    }
+                }
+                render_nodes(chosen, dot, vars, out)?;
+            }
+            Node::Range {
+                vars: names,
+                over,
+                body,
+                otherwise,
+            } => {
+                let items: Vec<(Value, Value)> = match eval(over, dot, vars)? {
+                    Value::Array(items) => items
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, item)| (json!(index), item))
+                        .collect(),
+                    // Go ranges over maps in key order, which serde_json keeps
+                    Value::Object(fields) => fields
+                        .into_iter()
+                        .map(|(key, item)| (json!(key), item))
+                        .collect(),
+                    _ => vec![],
+                };
+                if items.is_empty() {
+                    render_nodes(otherwise, dot, vars, out)?;
+                }
+                for (key, item) in items {
+                    let outer = vars.len();
+                    match names.as_slice() {
+                        [value] => vars.push((value.clone(), item.clone())),
+                        [index, value] => {
+                            vars.push((index.clone(), key));
+                            vars.push((value.clone(), item.clone()));
+                        }
+                        _ => {}
+                    }
+                    render_nodes(body, &item, vars, out)?;
+                    vars.truncate(outer);
+                }
+            }
+            Node::With {
+                vars: names,
+                value,
+                body,
+                otherwise,
+            } => {
+                let value = eval(value, dot, vars)?;
+                if truthy(&value) {
+                    let outer = vars.len();
+                    if let Some(name) = names.first() {
+                        vars.push((name.clone(), value.clone()));
+                    }
+                    render_nodes(body, &value, vars, out)?;
+                    vars.truncate(outer);
+                } else {
+                    render_nodes(otherwise, dot, vars, out)?;
+                }
+            }
+        }
+    }
+    vars.truncate(scope);
+    Ok(())
+}
+
+fn eval(expr: &Expr, dot: &Value, vars: &Vars) -> Result<Value, String> {
+    let lookup = |mut value: &Value, fields: &[String]| {
+        for field in fields {
+            value = value.get(field).unwrap_or(&Value::Null);
+        }
+        value.clone()
+    };
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Path(fields) => Ok(lookup(dot, fields)),
+        Expr::Var { name, fields } => {
+            let (_, value) = vars
+                .iter()
+                .rev()
+                .find(|(var, _)| var == name)
+                .ok_or_else(|| format!("undefined variable ${} in template", name))?;
+            Ok(lookup(value, fields))
+        }
+        Expr::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, dot, vars))
+                .collect::<Result<Vec<_>, _>>()?;
+            call(name, &args)
+        }
+    }
+}
+
+fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+    let arity = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(format!("{} takes {} arguments in template", name, n))
+        }
+    };
+    match name {
+        "eq" => {
+            arity(2)?;
+            Ok(json!(args[0] == args[1]))
+        }
+        "ne" => {
+            arity(2)?;
+            Ok(json!(args[0] != args[1]))
+        }
+        "lt" | "le" | "gt" | "ge" => {
+            arity(2)?;
+            let ordering = compare(&args[0], &args[1])
+                .ok_or_else(|| format!("{} of incomparable values in template", name))?;
+            Ok(json!(match name {
+                "lt" => ordering.is_lt(),
+                "le" => ordering.is_le(),
+                "gt" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        "index" => {
+            let (value, keys) = args
+                .split_first()
+                .ok_or_else(|| "index takes at least 1 argument in template".to_owned())?;
+            let mut value = value.clone();
+            for key in keys {
+                value = match (&value, key) {
+                    (Value::Array(items), Value::Number(index)) => index
+                        .as_u64()
+                        .and_then(|index| items.get(index as usize))
+                        .cloned()
+                        .ok_or_else(|| format!("index {} out of range in template", index))?,
+                    (Value::Object(fields), Value::String(key)) => {
+                        fields.get(key).cloned().unwrap_or(Value::Null)
+                    }
+                    (Value::Null, _) => Value::Null,
+                    _ => return Err(format!("can't index with {} in template", key)),
+                };
+            }
+            Ok(value)
+        }
+        "slice" => {
+            if args.is_empty() || args.len() > 3 {
+                return Err("slice takes 1 to 3 arguments in template".to_owned());
+            }
+            let bounds = args[1..]
+                .iter()
+                .map(|bound| {
+                    bound
+                        .as_u64()
+                        .map(|bound| bound as usize)
+                        .ok_or_else(|| format!("bad slice index {} in template", bound))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let slice = |len: usize| {
+                let start = bounds.first().copied().unwrap_or(0);
+                let end = bounds.get(1).copied().unwrap_or(len);
+                if start <= end && end <= len {
+                    Ok(start..end)
+                } else {
+                    Err(format!(
+                        "slice [{}:{}] out of range in template",
+                        start, end
+                    ))
+                }
+            };
+            match &args[0] {
+                Value::Array(items) => Ok(json!(items[slice(items.len())?].to_vec())),
+                Value::String(text) => text
+                    .get(slice(text.len())?)
+                    .map(|text| json!(text))
+                    .ok_or_else(|| "slice inside a character in template".to_owned()),
+                _ => Err("slice of a value without length in template".to_owned()),
+            }
+        }
+        "not" => {
+            arity(1)?;
+            Ok(json!(!truthy(&args[0])))
+        }
+        "and" => Ok(args
+            .iter()
+            .find(|arg| !truthy(arg))
+            .or(args.last())
+            .cloned()
+            .unwrap_or(Value::Null)),
+        "or" => Ok(args
+            .iter()
+            .find(|arg| truthy(arg))
+            .or(args.last())
+            .cloned()
+            .unwrap_or(Value::Null)),
+        "len" => {
+            arity(1)?;
+            match &args[0] {
+                Value::Array(items) => Ok(json!(items.len())),
+                Value::String(text) => Ok(json!(text.len())),
+                Value::Null => Ok(json!(0)),
+                _ => Err("len of a value without length in template".to_owned()),
+            }
+        }
+        _ => Err(format!("unsupported function {} in template", name)),
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(role: &str, content: &str) -> Turn {
+        Turn {
+            role: role.to_owned(),
+            content: content.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_legacy_prompt_template() {
+        let template = Template::parse(
+            "{{ if .System }}<|system|>\n{{ .System }}</s>\n{{ end }}<|user|>\n{{ .Prompt }}</s>\n<|assistant|>\n{{ .Response }}",
+        )
+        .unwrap();
+        let rendered = template
+            .render_chat(
+                Some("Be brief."),
+                &[
+                    turn("user", "Hi"),
+                    turn("assistant", "Hello"),
+                    turn("user", "Bye"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            rendered,
+            "<|system|>\nBe brief.</s>\n<|user|>\nHi</s>\n<|assistant|>\nHello<|user|>\nBye</s>\n<|assistant|>\n"
+        );
+    }
+
+    #[test]
+    fn test_messages_template_with_trimming() {
+        let template = Template::parse(
+            "{{- range .Messages }}<|{{ .Role }}|>\n{{ .Content }}\n{{ end -}}\n  <|assistant|>",
+        )
+        .unwrap();
+        let rendered = template
+            .render_chat(Some("sys"), &[turn("user", "Hi")])
+            .unwrap();
+        assert_eq!(rendered, "<|system|>\nsys\n<|user|>\nHi\n<|assistant|>");
+    }
+
+    #[test]
+    fn test_conditions() {
+        let template = Template::parse(
+            r#"{{ range $i, $m := .Messages }}{{ if eq .Role "user" }}U:{{ else if and (ne .Role "system") $.System }}A:{{ else }}S:{{ end }}{{ .Content }};{{ end }}{{ with .System }}[{{ . }}]{{ end }}{{/* done */}}"#,
+        )
+        .unwrap();
+        let rendered = template
+            .render_chat(Some("sys"), &[turn("user", "a"), turn("assistant", "b")])
+            .unwrap();
+        assert_eq!(rendered, "S:sys;U:a;A:b;[sys]");
+    }
+
+    #[test]
+    fn test_variables_and_functions() {
+        let template = Template::parse(
+            r#"{{- range $i, $_ := .Messages }}{{ $last := eq (len (slice $.Messages $i)) 1 }}{{ $i }}{{ if $last }}!{{ end }}{{ end }}|{{ index .Messages 1 "Content" }}|{{ if and (le 1 2) (lt 1 2) (ge 2 2) (gt "b" "a") }}ok{{ end }}{{ $n := 1 }}{{ $n = 2 }}{{ $n }}{{ with $m := .System }}{{ $m }}{{ end }}"#,
+        )
+        .unwrap();
+        let rendered = template
+            .render_chat(Some("s"), &[turn("user", "a"), turn("assistant", "b")])
+            .unwrap();
+        assert_eq!(rendered, "012!|a|ok2s");
+    }
+
+    #[test]
+    fn test_ollama_mistral_template() {
+        let modelfile =
+            crate::core::modelfile::parse_from_file("fixtures/mistral.modelfile").unwrap();
+        let template = Template::parse(modelfile.template.as_deref().unwrap()).unwrap();
+        let rendered = template
+            .render_chat(
+                Some("Be brief."),
+                &[
+                    turn("user", "Hi"),
+                    turn("assistant", "Hello"),
+                    turn("user", "Bye"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            rendered,
+            "[INST] Hi[/INST] Hello</s>[INST] Be brief.\n\nBye[/INST]"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Template::parse("{{ if .System }}x").is_err());
+        assert!(Template::parse("{{ .Prompt").is_err());
+        assert!(Template::parse("{{ end }}").is_err());
+        let template = Template::parse("{{ printf .Messages }}").unwrap();
+        assert_eq!(
+            template.render(&json!({})).unwrap_err(),
+            "unsupported function printf in template"
+        );
+        let template = Template::parse("{{ $x = 1 }}").unwrap();
+        assert_eq!(
+            template.render(&json!({})).unwrap_err(),
+            "undefined variable $x in template"
+        );
+    }
+}
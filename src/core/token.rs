@@ -0,0 +1,149 @@
+// Bearer token the CLI sends to the daemon, kept 0600 in the config dir
+
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use crate::core::config::get_config_dir;
+
+const TOKEN_FILE: &str = "server.token";
+// 32 random bytes, hex encoded
+const TOKEN_BYTES: usize = 32;
+
+/// The token file shared by `tiles server start`, the clients and the server
+#[derive(Debug, Clone)]
+pub struct TokenStore {
+    path: PathBuf,
+}
+
+impl TokenStore {
+    pub fn open() -> Result<TokenStore> {
+        Ok(TokenStore::at(get_config_dir()?.join(TOKEN_FILE)))
+    }
+
+    pub fn at(path: PathBuf) -> TokenStore {
+        TokenStore { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The current token, `None` before the server was first started
+    pub fn read(&self) -> Result<Option<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(token) if !token.trim().is_empty() => Ok(Some(token.trim().to_owned())),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {}", self.path.display())),
+        }
+    }
+
+    pub fn get_or_create(&self) -> Result<String> {
+        match self.read()? {
+            Some(token) => Ok(token),
+            None => self.rotate(),
+        }
+    }
+
+    /// Replaces the token, the server checks the file on every request so it applies at once
+    pub fn rotate(&self) -> Result<String> {
+        let token = generate()?;
+        let dir = self.path.parent().context("Token path has no parent")?;
+        fs::create_dir_all(dir).context("Failed to create config directory")?;
+        // Written next to the token and renamed over it, so readers never see half a token
+        let staging = self.path.with_extension("new");
+        let _ = fs::remove_file(&staging);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&staging)
+            .with_context(|| format!("Failed to create {}", staging.display()))?;
+        file.write_all(token.as_bytes())
+            .and_then(|_| file.sync_all())
+            .with_context(|| format!("Failed to write {}", staging.display()))?;
+        fs::rename(&staging, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(token)
+    }
+
+    /// Whether an `Authorization` header carries the current token
+    pub fn verify(&self, authorization: Option<&str>) -> bool {
+        let (Ok(Some(token)), Some(authorization)) = (self.read(), authorization) else {
+            return false;
+        };
+        let expected = format!("Bearer {}", token);
+        // Compares every byte so the time taken doesn't hint at the token
+        expected.len() == authorization.len()
+            && expected
+                .bytes()
+                .zip(authorization.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// Refuses a token file other users can read
+    pub fn check_permissions(&self) -> Result<()> {
+        let mode = fs::metadata(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            bail!(
+                "{} is readable by other users (mode {:o}), run `tiles token rotate`",
+                self.path.display(),
+                mode & 0o777
+            );
+        }
+        Ok(())
+    }
+}
+
+fn generate() -> Result<String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut bytes))
+        .context("Failed to read random bytes")?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_is_created_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TokenStore::at(dir.path().join("tiles").join(TOKEN_FILE));
+        assert_eq!(store.read().unwrap(), None);
+
+        let token = store.get_or_create().unwrap();
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(store.get_or_create().unwrap(), token);
+        let mode = fs::metadata(store.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        store.check_permissions().unwrap();
+    }
+
+    #[test]
+    fn test_rotate_replaces_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TokenStore::at(dir.path().join(TOKEN_FILE));
+        let first = store.rotate().unwrap();
+        let second = store.rotate().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(store.read().unwrap(), Some(second.clone()));
+        assert!(store.verify(Some(&format!("Bearer {}", second))));
+        assert!(!store.verify(Some(&format!("Bearer {}", first))));
+        assert!(!store.verify(None));
+
+        fs::set_permissions(store.path(), fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(store.check_permissions().is_err());
+    }
+}
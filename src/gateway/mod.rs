@@ -0,0 +1,611 @@
+// HTTP gateway serving Modelfiles as models, so any client gets the
+// Modelfile's SYSTEM, MESSAGEs, TEMPLATE and PARAMETERs applied
+
+pub mod ollama;
+pub mod openai;
+
+use anyhow::{Context, Result};
+use futures_util::{Stream, StreamExt, stream};
+use reqwest::{Client, Response};
+use serde_json::{Map, Value, json};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    net::TcpListener,
+    path::Path,
+    pin::Pin,
+    process::Stdio,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::process::Child;
+use tracing::warn;
+
+use crate::core::{
+    modelfile::{self, Modelfile, ResolveOptions},
+    registry::{self, ModelName, Registry},
+    template::{Template, Turn},
+};
+use crate::runner::{
+    Backend,
+    mlx::get_memory_path,
+    protocol::{self, ChatMessage, ChatRequest, StartRequest},
+    server::{self, Transport},
+};
+
+/// Generated text as it arrives from a backend
+pub type TextStream = Pin<Box<dyn Stream<Item = Result<String, String>> + Send>>;
+
+/// A Modelfile ready to serve under a name
+#[derive(Debug, Clone)]
+pub struct ServedModel {
+    pub name: ModelName,
+    pub modelfile: Modelfile,
+    pub backend: Backend,
+    /// What the backend loads, see [`Backend::model_argument`]
+    pub model: String,
+    pub created: u64,
+    template: Option<Template>,
+}
+
+impl ServedModel {
+    pub fn new(name: ModelName, modelfile: Modelfile) -> Result<ServedModel, String> {
+        let from = modelfile
+            .from
+            .as_deref()
+            .ok_or_else(|| format!("{} has no FROM", name))?;
+        let backend = Backend::for_model(from);
+        let model = backend.model_argument(&modelfile.model_ref()?)?;
+        // A TEMPLATE this renderer can't handle shouldn't keep the model from being served
+        let template = modelfile.template.as_deref().and_then(|source| {
+            Template::parse(source)
+                .inspect_err(|err| warn!("{}: {}, using the model's own chat template", name, err))
+                .ok()
+        });
+        Ok(ServedModel {
+            name,
+            modelfile,
+            backend,
+            model,
+            created: now(),
+            template,
+        })
+    }
+
+    /// The Modelfile's SYSTEM and MESSAGEs followed by the client's turns.
+    /// A system turn from the client replaces SYSTEM
+    pub fn conversation(&self, turns: Vec<Turn>) -> Vec<Turn> {
+        let mut conversation = vec![];
+        if let Some(system) = &self.modelfile.system
+            && !turns.iter().any(|turn| turn.role == "system")
+        {
+            conversation.push(Turn {
+                role: "system".to_owned(),
+                content: system.clone(),
+            });
+        }
+        conversation.extend(self.modelfile.messages.iter().map(|message| Turn {
+            role: message.role(),
+            content: message.content().to_owned(),
+        }));
+        conversation.extend(turns);
+        conversation
+    }
+
+    /// The conversation rendered with TEMPLATE, `None` without one, or when
+    /// it fails to render, so the backend applies the model's own chat template
+    pub fn render(&self, conversation: &[Turn]) -> Option<String> {
+        let template = self.template.as_ref()?;
+        let (system, turns) = match conversation.split_first() {
+            Some((first, rest)) if first.role == "system" => (Some(first.content.as_str()), rest),
+            _ => (None, conversation),
+        };
+        template
+            .render_chat(system, turns)
+            .inspect_err(|err| {
+                warn!(
+                    "{}: {}, using the model's own chat template",
+                    self.name, err
+                )
+            })
+            .ok()
+    }
+
+    /// The same model rendering prompts with another TEMPLATE
+    pub fn with_template(&self, source: &str) -> Result<ServedModel, String> {
+        let mut model = self.clone();
+        model.template = Some(Template::parse(source)?);
+        Ok(model)
+    }
+
+    /// PARAMETERs as request fields, overridden by the ones the client sent
+    pub fn options(&self, request: &Map<String, Value>) -> Map<String, Value> {
+        let mut options = server::chat_options(&self.modelfile);
+        for (key, value) in request {
+            if !value.is_null() {
+                options.insert(key.clone(), value.clone());
+            }
+        }
+        options
+    }
+}
+
+/// The HTTP API a gateway speaks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Api {
+    OpenAi,
+    Ollama,
+}
+
+impl Api {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Api::OpenAi => 6970,
+            Api::Ollama => 11434,
+        }
+    }
+}
+
+impl FromStr for Api {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(Api::OpenAi),
+            "ollama" => Ok(Api::Ollama),
+            other => Err(format!(
+                "Unknown API `{}`, expected openai or ollama",
+                other
+            )),
+        }
+    }
+}
+
+/// State shared by the routes
+pub struct Gateway {
+    pub catalog: Catalog,
+    pub backends: Backends,
+    /// Where models created over the API are registered
+    pub registry: Registry,
+}
+
+/// Serves the gateway on `addr` until Ctrl-C
+/// Listens on `addr`, ahead of starting any backend so a taken port fails first
+pub async fn bind(addr: &str) -> Result<tokio::net::TcpListener> {
+    tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))
+}
+
+pub async fn serve(gateway: Gateway, api: Api, listener: tokio::net::TcpListener) -> Result<()> {
+    let router = match api {
+        Api::OpenAi => openai::router(),
+        Api::Ollama => ollama::router(),
+    };
+    let app = router.with_state(Arc::new(gateway));
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .context("Gateway stopped")
+}
+
+/// What a backend is asked to generate
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub conversation: Vec<Turn>,
+    /// Prompt rendered with TEMPLATE, sent instead of the conversation
+    pub prompt: Option<String>,
+    /// OpenAI style sampling fields such as temperature and max_tokens
+    pub options: Map<String, Value>,
+}
+
+/// The models a gateway serves, looked up by `name` or `name:tag`
+#[derive(Default)]
+pub struct Catalog {
+    models: RwLock<BTreeMap<String, Arc<ServedModel>>>,
+}
+
+impl Catalog {
+    /// Loads Modelfile paths or registered names, plus the Modelfiles in
+    /// `dir`. Every registered model is served when both are empty
+    pub fn load(references: &[String], dir: Option<&Path>) -> Result<Catalog, String> {
+        let mut sources: Vec<(ModelName, String)> = vec![];
+        for reference in references {
+            let path = registry::locate(reference)?;
+            let name = registry::name_for(reference)?;
+            sources.push((name, path.to_string_lossy().to_string()));
+        }
+        if let Some(dir) = dir {
+            sources.extend(modelfiles_in(dir)?);
+        }
+        if references.is_empty() && dir.is_none() {
+            let registry = Registry::open().map_err(|err| err.to_string())?;
+            for entry in registry.list().map_err(|err| err.to_string())? {
+                let path = registry
+                    .modelfile_path(&entry.name)
+                    .map_err(|err| err.to_string())?;
+                sources.push((entry.name, path.to_string_lossy().to_string()));
+            }
+        }
+        let catalog = Catalog::default();
+        for (name, path) in sources {
+            let modelfile = modelfile::resolve_from_file(&path, &ResolveOptions::default())
+                .map_err(|err| format!("{}: {}", path, err))?;
+            catalog.insert(ServedModel::new(name, modelfile)?);
+        }
+        Ok(catalog)
+    }
+
+    pub fn insert(&self, model: ServedModel) {
+        let mut models = self.models.write().unwrap();
+        models.insert(model.name.to_string(), Arc::new(model));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<ServedModel>> {
+        let name: ModelName = name.parse().ok()?;
+        self.models.read().unwrap().get(&name.to_string()).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Arc<ServedModel>> {
+        self.models.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Arc<ServedModel>> {
+        let name: ModelName = name.parse().ok()?;
+        self.models.write().unwrap().remove(&name.to_string())
+    }
+}
+
+fn modelfiles_in(dir: &Path) -> Result<Vec<(ModelName, String)>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("Failed to read {}: {}", dir.display(), err))?;
+    let mut found = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let path = if path.is_dir() {
+            path.join("Modelfile")
+        } else {
+            path
+        };
+        let is_modelfile = path.is_file()
+            && (path.ends_with("Modelfile")
+                || file_name.starts_with("Modelfile.")
+                || path.extension().is_some_and(|ext| ext == "modelfile"));
+        if is_modelfile {
+            found.push((
+                registry::name_for_path(&path)?,
+                path.to_string_lossy().to_string(),
+            ));
+        }
+    }
+    found.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(found)
+}
+
+/// Where the gateway sends generations: `mlx_lm.server` for MLX models
+/// and the tiles server for the mem-agent
+pub struct Backends {
+    client: Client,
+    mlx_url: String,
+    /// `mlx_lm.server` started by the gateway, killed when it stops
+    mlx_server: Option<Child>,
+    /// Model each backend ran last, with when it was last used
+    loaded: std::sync::Mutex<BTreeMap<&'static str, (Arc<ServedModel>, u64)>>,
+}
+
+impl Backends {
+    /// Uses the `mlx_lm.server` at `mlx_url`, or starts one when `None`
+    pub fn start(mlx_url: Option<String>, needs_mlx: bool) -> Result<Backends, String> {
+        let (mlx_url, mlx_server) = match mlx_url {
+            Some(url) => (url.trim_end_matches('/').to_owned(), None),
+            None if needs_mlx => {
+                let port = TcpListener::bind("127.0.0.1:0")
+                    .and_then(|listener| listener.local_addr())
+                    .map_err(|err| err.to_string())?
+                    .port();
+                let child = tokio::process::Command::new("mlx_lm.server")
+                    .args(["--host", "127.0.0.1", "--port", &port.to_string()])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|err| {
+                        format!(
+                            "Failed to start mlx_lm.server: {}, install mlx-lm or pass --mlx-url",
+                            err
+                        )
+                    })?;
+                (format!("http://127.0.0.1:{}", port), Some(child))
+            }
+            None => (String::new(), None),
+        };
+        Ok(Backends {
+            client: Client::new(),
+            mlx_url,
+            mlx_server,
+            loaded: Default::default(),
+        })
+    }
+
+    pub fn mlx_url(&self) -> &str {
+        &self.mlx_url
+    }
+
+    pub fn owns_mlx_server(&self) -> bool {
+        self.mlx_server.is_some()
+    }
+
+    pub async fn generate(
+        &self,
+        model: &Arc<ServedModel>,
+        generation: Generation,
+    ) -> Result<TextStream, String> {
+        let text = match model.backend {
+            Backend::MlxChat => self.generate_mlx(model, generation).await?,
+            Backend::Server => self.generate_server(model, generation).await?,
+        };
+        // Both backends keep a single model in memory, the one asked for last
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.insert(model.backend.name(), (model.clone(), now()));
+        Ok(text)
+    }
+
+    /// Models the backends hold in memory, with when they were last used
+    pub fn loaded(&self) -> Vec<(Arc<ServedModel>, u64)> {
+        self.loaded.lock().unwrap().values().cloned().collect()
+    }
+
+    async fn generate_mlx(
+        &self,
+        model: &ServedModel,
+        generation: Generation,
+    ) -> Result<TextStream, String> {
+        let mut body = generation.options;
+        body.insert("model".to_owned(), json!(model.model));
+        body.insert("stream".to_owned(), json!(true));
+        if let Some(adapter) = &model.modelfile.adapter {
+            body.insert("adapters".to_owned(), json!(adapter));
+        }
+        let (path, field): (&str, fn(&Value) -> Option<&str>) = match generation.prompt {
+            Some(prompt) => {
+                body.insert("prompt".to_owned(), json!(prompt));
+                ("/v1/completions", |chunk| {
+                    chunk["choices"][0]["text"].as_str()
+                })
+            }
+            None => {
+                let messages: Vec<Value> = generation
+                    .conversation
+                    .iter()
+                    .map(|turn| json!({"role": turn.role, "content": turn.content}))
+                    .collect();
+                body.insert("messages".to_owned(), json!(messages));
+                ("/v1/chat/completions", |chunk| {
+                    chunk["choices"][0]["delta"]["content"].as_str()
+                })
+            }
+        };
+        let url = format!("{}{}", self.mlx_url, path);
+        // A freshly started mlx_lm.server takes a moment to listen
+        let mut attempts = if self.owns_mlx_server() { 60 } else { 1 };
+        let response = loop {
+            attempts -= 1;
+            match self.client.post(&url).json(&body).send().await {
+                Ok(response) => break response,
+                Err(err) if err.is_connect() && attempts > 0 => {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+                Err(err) => return Err(format!("mlx_lm.server is not reachable: {}", err)),
+            }
+        };
+        let response = check_status(response).await?;
+        Ok(sse_text(response, field))
+    }
+
+    // Each request gets a session of its own on the tiles server, opened
+    // with the conversation's system turn and closed once the reply is in,
+    // so clients never see each other's history
+    async fn generate_server(
+        &self,
+        model: &ServedModel,
+        generation: Generation,
+    ) -> Result<TextStream, String> {
+        let transport = Transport::current().map_err(|err| err.to_string())?;
+        let handshake = server::handshake(&transport)
+            .await
+            .map_err(|err| format!("{:#}", err))?;
+        let client = transport.client().map_err(|err| err.to_string())?;
+        let name = model.name.to_string();
+        let system_prompt = generation
+            .conversation
+            .iter()
+            .find(|turn| turn.role == "system")
+            .map(|turn| turn.content.clone());
+        let start = StartRequest {
+            model: model.model.clone(),
+            memory_path: get_memory_path().map_err(|err| err.to_string())?,
+            system_prompt,
+            session_id: Some(protocol::new_id()),
+            keep_alive: model.modelfile.keep_alive(),
+        };
+        let session_id = server::start_session(&transport, &client, &start, &name)
+            .await
+            .map_err(|err| format!("{:#}", err))?;
+        let request = ChatRequest {
+            model: model.model.clone(),
+            messages: generation
+                .conversation
+                .into_iter()
+                .filter(|turn| turn.role != "system")
+                .map(|turn| ChatMessage {
+                    role: turn.role,
+                    content: turn.content,
+                })
+                .collect(),
+            session_id: session_id.clone(),
+            prompt: generation.prompt.filter(|_| handshake.supports("prompt")),
+            options: if handshake.supports("sampling_options") {
+                generation.options
+            } else {
+                Map::new()
+            },
+        };
+        let content = server::chat(&transport, &client, &request, &protocol::new_id(), &name).await;
+        if let Some(session_id) = &session_id
+            && let Err(err) = server::close_session(&transport, &client, session_id).await
+        {
+            warn!(session_id, "failed to close the session: {:#}", err);
+        }
+        let content = content.map_err(|err| format!("{:#}", err))?;
+        Ok(Box::pin(stream::once(async move { Ok(content) })))
+    }
+}
+
+async fn check_status(response: Response) -> Result<Response, String> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("backend answered {}: {}", status, body.trim()))
+}
+
+// Text pieces from an OpenAI style server-sent event stream
+fn sse_text(response: Response, field: fn(&Value) -> Option<&str>) -> TextStream {
+    let state = (response.bytes_stream(), Vec::new(), VecDeque::new(), false);
+    Box::pin(stream::unfold(
+        state,
+        move |(mut bytes, mut buffer, mut pending, mut done)| async move {
+            loop {
+                if let Some(text) = pending.pop_front() {
+                    return Some((Ok(text), (bytes, buffer, pending, done)));
+                }
+                if done {
+                    return None;
+                }
+                match bytes.next().await {
+                    Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                    Some(Err(err)) => {
+                        done = true;
+                        return Some((Err(err.to_string()), (bytes, buffer, pending, done)));
+                    }
+                    None => done = true,
+                }
+                while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    let Some(data) = line.trim().strip_prefix("data:") else {
+                        continue;
+                    };
+                    let data = data.trim();
+                    if data == "[DONE]" {
+                        done = true;
+                        break;
+                    }
+                    if let Ok(chunk) = serde_json::from_str::<Value>(data)
+                        && let Some(text) = field(&chunk)
+                        && !text.is_empty()
+                    {
+                        pending.push_back(text.to_owned());
+                    }
+                }
+            }
+        },
+    ))
+}
+
+/// Collects a whole stream, for clients that didn't ask to stream
+pub async fn collect(mut text: TextStream) -> Result<String, String> {
+    let mut out = String::new();
+    while let Some(piece) = text.next().await {
+        out += &piece?;
+    }
+    Ok(out)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn served(source: &str) -> ServedModel {
+        let modelfile = modelfile::parse(source).unwrap();
+        ServedModel::new("bot".parse().unwrap(), modelfile).unwrap()
+    }
+
+    fn turn(role: &str, content: &str) -> Turn {
+        Turn {
+            role: role.to_owned(),
+            content: content.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_conversation_applies_system_and_messages() {
+        let model = served(
+            "FROM mlx-community/Llama-3.2-1B-Instruct-4bit\nSYSTEM Be brief.\nMESSAGE user Hi\nMESSAGE assistant Hello",
+        );
+        let conversation = model.conversation(vec![turn("user", "Bye")]);
+        let roles: Vec<&str> = conversation.iter().map(|t| t.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user"]);
+        assert_eq!(conversation[0].content, "Be brief.");
+
+        let conversation =
+            model.conversation(vec![turn("system", "Be verbose."), turn("user", "Bye")]);
+        assert_eq!(conversation[0].role, "user");
+        assert_eq!(model.render(&conversation), None);
+    }
+
+    #[test]
+    fn test_template_and_options() {
+        let model = served(
+            "FROM mlx-community/Llama-3.2-1B-Instruct-4bit\nTEMPLATE \"\"\"{{ .System }}|{{ .Prompt }}|\"\"\"\nSYSTEM sys\nPARAMETER temperature 0.2\nPARAMETER num_predict 64",
+        );
+        let conversation = model.conversation(vec![turn("user", "Hi")]);
+        assert_eq!(model.render(&conversation).as_deref(), Some("sys|Hi|"));
+        let mut request = Map::new();
+        request.insert("temperature".to_owned(), json!(0.9));
+        request.insert("top_p".to_owned(), Value::Null);
+        let options = model.options(&request);
+        assert_eq!(options["temperature"], json!(0.9));
+        assert_eq!(options["max_tokens"], json!(64));
+        assert!(!options.contains_key("top_p"));
+    }
+
+    #[test]
+    fn test_unusable_template_falls_back() {
+        for template in ["{{ if .System }}", "{{ printf .Prompt }}"] {
+            let model = served(&format!(
+                "FROM mlx-community/Llama-3.2-1B-Instruct-4bit\nTEMPLATE \"\"\"{}\"\"\"",
+                template
+            ));
+            let conversation = model.conversation(vec![turn("user", "Hi")]);
+            assert_eq!(model.render(&conversation), None);
+        }
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = "FROM mlx-community/Llama-3.2-1B-Instruct-4bit\n";
+        fs::write(dir.path().join("Pirate.modelfile"), from).unwrap();
+        fs::write(dir.path().join("Modelfile.coder"), from).unwrap();
+        fs::create_dir(dir.path().join("tutor")).unwrap();
+        fs::write(dir.path().join("tutor/Modelfile"), from).unwrap();
+        fs::write(dir.path().join("notes.txt"), "FROM nothing").unwrap();
+        let catalog = Catalog::load(&[], Some(dir.path())).unwrap();
+        let names: Vec<String> = catalog.list().iter().map(|m| m.name.to_string()).collect();
+        assert_eq!(names, ["coder:latest", "pirate:latest", "tutor:latest"]);
+        assert!(catalog.get("tutor").is_some());
+        assert!(catalog.get("tutor:v2").is_none());
+    }
+}
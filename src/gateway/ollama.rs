@@ -0,0 +1,553 @@
+// Ollama compatible routes, so editor plugins and Open WebUI can use tiles
+// as a drop-in local replacement
+
+use axum::{
+    Json, Router,
+    body::{Body, Bytes},
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use futures_util::{StreamExt, stream};
+use serde_json::{Map, Value, json};
+use std::{
+    convert::Infallible,
+    env,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::core::{
+    hf_cache::HfCache,
+    model_ref::ModelRef,
+    modelfile::{self, ResolveOptions},
+    registry::{ModelName, sha256_digest},
+    template::Turn,
+};
+use crate::gateway::{Gateway, Generation, ServedModel, TextStream, collect, now};
+use crate::runner::Backend;
+
+pub fn router() -> Router<Arc<Gateway>> {
+    Router::new()
+        .route("/", get(|| async { "Ollama is running" }))
+        .route("/api/version", get(version))
+        .route("/api/tags", get(tags))
+        .route("/api/ps", get(ps))
+        .route("/api/show", post(show))
+        .route("/api/chat", post(chat))
+        .route("/api/generate", post(generate))
+        .route("/api/create", post(create))
+}
+
+/// An error as Ollama reports it, `{"error": ".."}`
+pub struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({"error": self.1}))).into_response()
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError(StatusCode::BAD_REQUEST, message.into())
+}
+
+async fn version() -> Json<Value> {
+    Json(json!({"version": env!("CARGO_PKG_VERSION")}))
+}
+
+async fn tags(State(gateway): State<Arc<Gateway>>) -> Json<Value> {
+    let models: Vec<Value> = gateway
+        .catalog
+        .list()
+        .iter()
+        .map(|model| {
+            let mut entry = model_entry(model);
+            entry.insert("modified_at".to_owned(), json!(rfc3339(model.created)));
+            Value::Object(entry)
+        })
+        .collect();
+    Json(json!({"models": models}))
+}
+
+async fn ps(State(gateway): State<Arc<Gateway>>) -> Json<Value> {
+    let models: Vec<Value> = gateway
+        .backends
+        .loaded()
+        .iter()
+        .map(|(model, _)| Value::Object(model_entry(model)))
+        .collect();
+    Json(json!({"models": models}))
+}
+
+async fn show(State(gateway): State<Arc<Gateway>>, body: Bytes) -> Result<Json<Value>, ApiError> {
+    let request = parse_body(&body)?;
+    let model = find_model(&gateway, &request)?;
+    let modelfile = &model.modelfile;
+    let parameters: Vec<String> = modelfile
+        .parameters
+        .iter()
+        .map(|parameter| format!("{} {}", parameter.param_type, parameter.value))
+        .collect();
+    let messages: Vec<Value> = modelfile
+        .messages
+        .iter()
+        .map(|message| json!({"role": message.role(), "content": message.content()}))
+        .collect();
+    let entry = model_entry(&model);
+    Ok(Json(json!({
+        "modelfile": modelfile.to_string(),
+        "parameters": parameters.join("\n"),
+        "template": modelfile.template.clone().unwrap_or_default(),
+        "system": modelfile.system.clone().unwrap_or_default(),
+        "license": modelfile.license.clone().unwrap_or_default(),
+        "messages": messages,
+        "details": entry["details"],
+        "modified_at": rfc3339(model.created),
+    })))
+}
+
+async fn chat(State(gateway): State<Arc<Gateway>>, body: Bytes) -> Result<Response, ApiError> {
+    let request = parse_body(&body)?;
+    let model = find_model(&gateway, &request)?;
+    let messages = match &request["messages"] {
+        Value::Array(messages) => messages.as_slice(),
+        Value::Null => &[],
+        _ => return Err(bad_request("messages must be an array")),
+    };
+    let turns = messages
+        .iter()
+        .map(|message| {
+            let role = message["role"]
+                .as_str()
+                .ok_or_else(|| bad_request("every message needs a role"))?;
+            Ok(Turn {
+                role: role.to_owned(),
+                content: message["content"].as_str().unwrap_or_default().to_owned(),
+            })
+        })
+        .collect::<Result<Vec<_>, ApiError>>()?;
+    let name = model.name.to_string();
+    // No messages just loads the model
+    if turns.is_empty() {
+        return Ok(Json(json!({
+            "model": name,
+            "created_at": rfc3339(now()),
+            "message": {"role": "assistant", "content": ""},
+            "done_reason": "load",
+            "done": true,
+        }))
+        .into_response());
+    }
+    let conversation = model.conversation(turns);
+    let prompt = model.render(&conversation);
+    let generation = Generation {
+        conversation,
+        prompt,
+        options: model.options(&options(&request["options"])),
+    };
+    let text = generate_text(&gateway, &model, generation).await?;
+    let piece = |text: String| json!({"message": {"role": "assistant", "content": text}});
+    respond(text, name, &request, piece).await
+}
+
+async fn generate(State(gateway): State<Arc<Gateway>>, body: Bytes) -> Result<Response, ApiError> {
+    let request = parse_body(&body)?;
+    let mut model = find_model(&gateway, &request)?;
+    let prompt = request["prompt"].as_str().unwrap_or_default();
+    let name = model.name.to_string();
+    if prompt.is_empty() {
+        return Ok(Json(json!({
+            "model": name,
+            "created_at": rfc3339(now()),
+            "response": "",
+            "done_reason": "load",
+            "done": true,
+        }))
+        .into_response());
+    }
+    if let Some(template) = request["template"].as_str() {
+        model = Arc::new(model.with_template(template).map_err(bad_request)?);
+    }
+    let mut turns = vec![];
+    if let Some(system) = request["system"].as_str() {
+        turns.push(Turn {
+            role: "system".to_owned(),
+            content: system.to_owned(),
+        });
+    }
+    turns.push(Turn {
+        role: "user".to_owned(),
+        content: prompt.to_owned(),
+    });
+    let conversation = model.conversation(turns);
+    // Raw prompts skip SYSTEM, MESSAGEs and TEMPLATE
+    let prompt = if request["raw"].as_bool().unwrap_or(false) {
+        Some(prompt.to_owned())
+    } else {
+        model.render(&conversation)
+    };
+    let generation = Generation {
+        conversation,
+        prompt,
+        options: model.options(&options(&request["options"])),
+    };
+    let text = generate_text(&gateway, &model, generation).await?;
+    respond(text, name, &request, |text| json!({"response": text})).await
+}
+
+async fn create(State(gateway): State<Arc<Gateway>>, body: Bytes) -> Result<Response, ApiError> {
+    let request = parse_body(&body)?;
+    let name = request["model"]
+        .as_str()
+        .or(request["name"].as_str())
+        .ok_or_else(|| bad_request("model is required"))?;
+    let name: ModelName = name.parse().map_err(bad_request)?;
+    let source = request["modelfile"]
+        .as_str()
+        .ok_or_else(|| bad_request("modelfile is required"))?;
+    // The same resolution `tiles create` does, relative paths follow the gateway's directory
+    let base_dir = env::current_dir().map_err(|err| internal(err.to_string()))?;
+    let modelfile = modelfile::resolve_source(
+        source,
+        &base_dir,
+        &gateway.registry,
+        &ResolveOptions::default(),
+    )
+    .map_err(bad_request)?;
+    let served = ServedModel::new(name.clone(), modelfile.clone()).map_err(bad_request)?;
+    gateway
+        .registry
+        .create(&name, &modelfile)
+        .map_err(|err| internal(err.to_string()))?;
+    gateway.catalog.insert(served);
+    let statuses = ["parsing modelfile", "writing manifest", "success"];
+    if !request["stream"].as_bool().unwrap_or(true) {
+        return Ok(Json(json!({"status": "success"})).into_response());
+    }
+    let lines = statuses.map(|status| json!({"status": status}));
+    Ok(ndjson(stream::iter(lines)))
+}
+
+// Ollama clients often send JSON without a content type
+fn parse_body(body: &[u8]) -> Result<Value, ApiError> {
+    serde_json::from_slice(body).map_err(|err| bad_request(format!("invalid JSON body: {}", err)))
+}
+
+fn find_model(gateway: &Gateway, request: &Value) -> Result<Arc<ServedModel>, ApiError> {
+    let name = request["model"]
+        .as_str()
+        .or(request["name"].as_str())
+        .ok_or_else(|| bad_request("model is required"))?;
+    gateway
+        .catalog
+        .get(name)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("model '{}' not found", name)))
+}
+
+fn internal(message: String) -> ApiError {
+    ApiError(StatusCode::INTERNAL_SERVER_ERROR, message)
+}
+
+async fn generate_text(
+    gateway: &Gateway,
+    model: &Arc<ServedModel>,
+    generation: Generation,
+) -> Result<TextStream, ApiError> {
+    gateway
+        .backends
+        .generate(model, generation)
+        .await
+        .map_err(|err| ApiError(StatusCode::BAD_GATEWAY, err))
+}
+
+// Ollama `options` use PARAMETER names, backends take OpenAI fields
+fn options(request: &Value) -> Map<String, Value> {
+    let mut options = Map::new();
+    let Value::Object(fields) = request else {
+        return options;
+    };
+    for (name, value) in fields {
+        if let Some(capability) = Backend::Server.capability(name) {
+            options.insert(capability.option.to_owned(), value.clone());
+        } else if name == "seed" {
+            options.insert(name.clone(), value.clone());
+        }
+    }
+    if let Some(Value::String(stop)) = options.get("stop") {
+        options.insert("stop".to_owned(), json!([stop]));
+    }
+    options
+}
+
+// Streams NDJSON unless the request set `"stream": false`, Ollama's default is to stream
+async fn respond(
+    text: TextStream,
+    model: String,
+    request: &Value,
+    piece: fn(String) -> Value,
+) -> Result<Response, ApiError> {
+    let started = Instant::now();
+    let line = move |fields: Value, done: bool| {
+        let mut line = json!({"model": model, "created_at": rfc3339(now())});
+        if let (Value::Object(line), Value::Object(fields)) = (&mut line, fields) {
+            line.extend(fields);
+            line.insert("done".to_owned(), json!(done));
+        }
+        line
+    };
+    let last = move |count: usize| {
+        json!({
+            "done_reason": "stop",
+            "total_duration": started.elapsed().as_nanos() as u64,
+            "eval_count": count,
+        })
+    };
+    if !request["stream"].as_bool().unwrap_or(true) {
+        let content = collect(text)
+            .await
+            .map_err(|err| ApiError(StatusCode::BAD_GATEWAY, err))?;
+        let mut fields = piece(content);
+        if let (Value::Object(fields), Value::Object(stats)) = (&mut fields, last(1)) {
+            fields.extend(stats);
+        }
+        return Ok(Json(line(fields, true)).into_response());
+    }
+    // Counted pieces stand in for tokens, the backends don't report them
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = count.clone();
+    let line_for_pieces = line.clone();
+    let pieces = text.map(move |result| match result {
+        Ok(text) => {
+            counter.fetch_add(1, Ordering::Relaxed);
+            line_for_pieces(piece(text), false)
+        }
+        Err(err) => json!({"error": err}),
+    });
+    let done = stream::once(async move {
+        let mut done = piece(String::new());
+        if let (Value::Object(done), Value::Object(stats)) =
+            (&mut done, last(count.load(Ordering::Relaxed)))
+        {
+            done.extend(stats);
+        }
+        line(done, true)
+    });
+    Ok(ndjson(pieces.chain(done)))
+}
+
+fn ndjson(lines: impl futures_util::Stream<Item = Value> + Send + 'static) -> Response {
+    let body = lines.map(|line| Ok::<_, Infallible>(Bytes::from(format!("{}\n", line))));
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(body))
+        .unwrap()
+}
+
+// Name, digest, size and details as /api/tags and /api/ps list them
+fn model_entry(model: &ServedModel) -> Map<String, Value> {
+    let digest = sha256_digest(model.modelfile.to_string().as_bytes());
+    let (format, size, quantization) = match model.modelfile.model_ref() {
+        Ok(ModelRef::HuggingFace { repo, .. }) => {
+            let cached = HfCache::open().and_then(|cache| cache.find(&repo)).ok();
+            let size = cached
+                .as_ref()
+                .map(|cached| cached.size)
+                .unwrap_or_default();
+            let quantization = cached.and_then(|cached| cached.quantization);
+            ("mlx", size, quantization)
+        }
+        Ok(ModelRef::LocalFile { .. } | ModelRef::OllamaBlob { .. }) => ("gguf", 0, None),
+        _ => ("mlx", 0, None),
+    };
+    let name = model.name.to_string();
+    let mut entry = Map::new();
+    entry.insert("name".to_owned(), json!(name));
+    entry.insert("model".to_owned(), json!(name));
+    entry.insert(
+        "digest".to_owned(),
+        json!(digest.trim_start_matches("sha256:")),
+    );
+    entry.insert("size".to_owned(), json!(size));
+    entry.insert(
+        "details".to_owned(),
+        json!({
+            "format": format,
+            "family": "",
+            "parameter_size": "",
+            "quantization_level": quantization.unwrap_or_default(),
+        }),
+    );
+    entry
+}
+
+/// Formats seconds since the epoch as `2024-05-01T12:00:00Z`
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // Days to a civil date, from Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::registry::Registry;
+    use crate::gateway::{Backends, Catalog};
+    use axum::body::to_bytes;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn gateway(registry: &std::path::Path) -> Arc<Gateway> {
+        let catalog = Catalog::default();
+        let modelfile = modelfile::parse(
+            "FROM mlx-community/Llama-3.2-1B-Instruct-4bit\nSYSTEM Be brief.\nPARAMETER temperature 0.3",
+        )
+        .unwrap();
+        catalog.insert(ServedModel::new("bot".parse().unwrap(), modelfile).unwrap());
+        // Nothing listens on port 9, so generations fail fast
+        let backends = Backends::start(Some("http://127.0.0.1:9".to_owned()), true).unwrap();
+        Arc::new(Gateway {
+            catalog,
+            backends,
+            registry: Registry::at(registry),
+        })
+    }
+
+    async fn call(
+        gateway: Arc<Gateway>,
+        method: &str,
+        uri: &str,
+        body: Value,
+    ) -> (StatusCode, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router().with_state(gateway).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_tags_and_show() {
+        let dir = tempfile::tempdir().unwrap();
+        let gateway = gateway(dir.path());
+        let (status, body) = call(gateway.clone(), "GET", "/api/tags", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let tags: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(tags["models"][0]["name"], "bot:latest");
+        assert_eq!(tags["models"][0]["details"]["format"], "mlx");
+
+        let (_, body) = call(
+            gateway.clone(),
+            "POST",
+            "/api/show",
+            json!({"model": "bot"}),
+        )
+        .await;
+        let show: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(show["system"], "Be brief.");
+        assert_eq!(show["parameters"], "temperature 0.3");
+
+        let (status, body) = call(gateway, "POST", "/api/show", json!({"model": "nope"})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, r#"{"error":"model 'nope' not found"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_create_registers_and_serves() {
+        let dir = tempfile::tempdir().unwrap();
+        let gateway = gateway(dir.path());
+        let request = json!({
+            "model": "pirate",
+            "modelfile": "FROM mlx-community/Llama-3.2-1B-Instruct-4bit\nSYSTEM Talk like a pirate.",
+        });
+        let (status, body) = call(gateway.clone(), "POST", "/api/create", request).await;
+        assert_eq!(status, StatusCode::OK);
+        let statuses: Vec<Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(statuses.last().unwrap()["status"], "success");
+        assert!(gateway.catalog.get("pirate").is_some());
+        assert!(gateway.registry.get(&"pirate".parse().unwrap()).is_ok());
+
+        let child = json!({
+            "model": "parrot",
+            "modelfile": "FROM modelfile:pirate\nPARAMETER temperature 0.1",
+        });
+        let (status, _) = call(gateway.clone(), "POST", "/api/create", child).await;
+        assert_eq!(status, StatusCode::OK);
+        let parrot = gateway.registry.get(&"parrot".parse().unwrap()).unwrap();
+        assert_eq!(
+            parrot.from.as_deref(),
+            Some("mlx-community/Llama-3.2-1B-Instruct-4bit")
+        );
+        assert_eq!(parrot.system.as_deref(), Some("Talk like a pirate."));
+        assert!(gateway.catalog.get("parrot").is_some());
+
+        let bad = json!({"model": "broken", "modelfile": "PARAMETER temperature 0.1"});
+        let (status, _) = call(gateway, "POST", "/api/create", bad).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_chat_without_messages_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = call(
+            gateway(dir.path()),
+            "POST",
+            "/api/chat",
+            json!({"model": "bot"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["done_reason"], "load");
+
+        let request = json!({"model": "bot", "messages": [{"role": "user", "content": "Hi"}]});
+        let (status, _) = call(gateway(dir.path()), "POST", "/api/chat", request).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn test_options_use_parameter_names() {
+        let options =
+            options(&json!({"num_predict": 12, "repeat_penalty": 1.2, "stop": "x", "mirostat": 1}));
+        assert_eq!(options["max_tokens"], json!(12));
+        assert_eq!(options["repetition_penalty"], json!(1.2));
+        assert_eq!(options["stop"], json!(["x"]));
+        assert!(!options.contains_key("mirostat"));
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(1_709_210_096), "2024-02-29T12:34:56Z");
+    }
+}
@@ -0,0 +1,376 @@
+// OpenAI compatible routes: /v1/models, /v1/chat/completions and /v1/completions
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, Sse},
+    },
+    routing::{get, post},
+};
+use futures_util::{StreamExt, stream};
+use serde_json::{Map, Value, json};
+use std::{
+    convert::Infallible,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::core::template::Turn;
+use crate::gateway::{Gateway, Generation, ServedModel, TextStream, collect, now};
+
+/// Request fields passed on to backends, the rest are ignored
+const SAMPLING_FIELDS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "max_tokens",
+    "stop",
+    "seed",
+    "repetition_penalty",
+];
+
+pub fn router() -> Router<Arc<Gateway>> {
+    Router::new()
+        .route("/v1/models", get(models))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/completions", post(completions))
+}
+
+/// An error in the shape OpenAI clients parse
+pub struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let kind = if self.0.is_client_error() {
+            "invalid_request_error"
+        } else {
+            "api_error"
+        };
+        let body = json!({"error": {"message": self.1, "type": kind, "code": null}});
+        (self.0, Json(body)).into_response()
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError(StatusCode::BAD_REQUEST, message.into())
+}
+
+async fn models(State(gateway): State<Arc<Gateway>>) -> Json<Value> {
+    let data: Vec<Value> = gateway
+        .catalog
+        .list()
+        .iter()
+        .map(|model| {
+            json!({
+                "id": model.name.to_string(),
+                "object": "model",
+                "created": model.created,
+                "owned_by": "tiles",
+            })
+        })
+        .collect();
+    Json(json!({"object": "list", "data": data}))
+}
+
+async fn chat_completions(
+    State(gateway): State<Arc<Gateway>>,
+    Json(request): Json<Value>,
+) -> Result<Response, ApiError> {
+    let model = find_model(&gateway, &request)?;
+    let messages = request["messages"]
+        .as_array()
+        .ok_or_else(|| bad_request("messages must be an array"))?;
+    let turns = messages
+        .iter()
+        .map(parse_message)
+        .collect::<Result<Vec<_>, _>>()?;
+    let conversation = model.conversation(turns);
+    let prompt = model.render(&conversation);
+    let generation = Generation {
+        conversation,
+        prompt,
+        options: model.options(&sampling(&request)),
+    };
+    respond(&gateway, &model, generation, &request, Kind::Chat).await
+}
+
+async fn completions(
+    State(gateway): State<Arc<Gateway>>,
+    Json(request): Json<Value>,
+) -> Result<Response, ApiError> {
+    let model = find_model(&gateway, &request)?;
+    let prompt = match &request["prompt"] {
+        Value::String(prompt) => prompt.clone(),
+        Value::Array(prompts) if prompts.len() == 1 && prompts[0].is_string() => {
+            prompts[0].as_str().unwrap_or_default().to_owned()
+        }
+        _ => return Err(bad_request("prompt must be a string")),
+    };
+    let conversation = model.conversation(vec![Turn {
+        role: "user".to_owned(),
+        content: prompt,
+    }]);
+    let prompt = model.render(&conversation);
+    let generation = Generation {
+        conversation,
+        prompt,
+        options: model.options(&sampling(&request)),
+    };
+    respond(&gateway, &model, generation, &request, Kind::Completion).await
+}
+
+fn find_model(gateway: &Gateway, request: &Value) -> Result<Arc<ServedModel>, ApiError> {
+    let name = request["model"]
+        .as_str()
+        .ok_or_else(|| bad_request("model is required"))?;
+    gateway.catalog.get(name).ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            format!("The model `{}` does not exist", name),
+        )
+    })
+}
+
+// `content` is a string or a list of parts, of which only text is supported
+fn parse_message(message: &Value) -> Result<Turn, ApiError> {
+    let role = message["role"]
+        .as_str()
+        .ok_or_else(|| bad_request("every message needs a role"))?;
+    let content = match &message["content"] {
+        Value::String(content) => content.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .map(
+                |part| match (part["type"].as_str(), part["text"].as_str()) {
+                    (Some("text"), Some(text)) => Ok(text),
+                    _ => Err(bad_request("only text content parts are supported")),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?
+            .concat(),
+        Value::Null => String::new(),
+        _ => return Err(bad_request("message content must be a string")),
+    };
+    Ok(Turn {
+        role: role.to_owned(),
+        content,
+    })
+}
+
+fn sampling(request: &Value) -> Map<String, Value> {
+    let mut options = Map::new();
+    for field in SAMPLING_FIELDS {
+        if let Some(value) = request.get(*field) {
+            options.insert(field.to_string(), value.clone());
+        }
+    }
+    if let Some(max_tokens) = request.get("max_completion_tokens") {
+        options.insert("max_tokens".to_owned(), max_tokens.clone());
+    }
+    if let Some(Value::String(stop)) = options.get("stop") {
+        options.insert("stop".to_owned(), json!([stop]));
+    }
+    options
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Chat,
+    Completion,
+}
+
+async fn respond(
+    gateway: &Gateway,
+    model: &Arc<ServedModel>,
+    generation: Generation,
+    request: &Value,
+    kind: Kind,
+) -> Result<Response, ApiError> {
+    let text = gateway
+        .backends
+        .generate(model, generation)
+        .await
+        .map_err(|err| ApiError(StatusCode::BAD_GATEWAY, err))?;
+    let id = completion_id(kind);
+    let name = model.name.to_string();
+    if request["stream"].as_bool().unwrap_or(false) {
+        return Ok(stream_response(text, id, name, kind).into_response());
+    }
+    let content = collect(text)
+        .await
+        .map_err(|err| ApiError(StatusCode::BAD_GATEWAY, err))?;
+    let body = match kind {
+        Kind::Chat => json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": now(),
+            "model": name,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop",
+            }],
+        }),
+        Kind::Completion => json!({
+            "id": id,
+            "object": "text_completion",
+            "created": now(),
+            "model": name,
+            "choices": [{"index": 0, "text": content, "finish_reason": "stop"}],
+        }),
+    };
+    Ok(Json(body).into_response())
+}
+
+fn stream_response(
+    text: TextStream,
+    id: String,
+    model: String,
+    kind: Kind,
+) -> Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>> {
+    let created = now();
+    let chunk = move |choice: Value| {
+        let object = match kind {
+            Kind::Chat => "chat.completion.chunk",
+            Kind::Completion => "text_completion",
+        };
+        json!({
+            "id": id,
+            "object": object,
+            "created": created,
+            "model": model,
+            "choices": [choice],
+        })
+    };
+    let piece = move |text: String| match kind {
+        Kind::Chat => json!({"index": 0, "delta": {"content": text}, "finish_reason": null}),
+        Kind::Completion => json!({"index": 0, "text": text, "finish_reason": null}),
+    };
+    let last = match kind {
+        Kind::Chat => json!({"index": 0, "delta": {}, "finish_reason": "stop"}),
+        Kind::Completion => json!({"index": 0, "text": "", "finish_reason": "stop"}),
+    };
+    let first = (kind == Kind::Chat)
+        .then(|| json!({"index": 0, "delta": {"role": "assistant"}, "finish_reason": null}));
+    let chunk_for_pieces = chunk.clone();
+    let events = stream::iter(first.map(|choice| Event::default().data(chunk(choice).to_string())))
+        .chain(text.map(move |piece_text| {
+            match piece_text {
+                Ok(text) => Event::default().data(chunk_for_pieces(piece(text)).to_string()),
+                Err(err) => Event::default()
+                    .data(json!({"error": {"message": err, "type": "api_error"}}).to_string()),
+            }
+        }))
+        .chain(stream::iter([
+            Event::default().data(chunk(last).to_string()),
+            Event::default().data("[DONE]"),
+        ]))
+        .map(Ok);
+    Sse::new(events)
+}
+
+fn completion_id(kind: Kind) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let prefix = match kind {
+        Kind::Chat => "chatcmpl",
+        Kind::Completion => "cmpl",
+    };
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{:x}{:04x}", prefix, now(), count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{modelfile, registry::Registry};
+    use crate::gateway::{Backends, Catalog};
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn gateway() -> Arc<Gateway> {
+        let catalog = Catalog::default();
+        let modelfile =
+            modelfile::parse("FROM mlx-community/Llama-3.2-1B-Instruct-4bit\nSYSTEM Be brief.")
+                .unwrap();
+        catalog.insert(ServedModel::new("bot".parse().unwrap(), modelfile).unwrap());
+        // Nothing listens on port 9, so generations fail fast
+        let backends = Backends::start(Some("http://127.0.0.1:9".to_owned()), true).unwrap();
+        let registry = Registry::at(std::env::temp_dir().join("tiles-openai-test"));
+        Arc::new(Gateway {
+            catalog,
+            backends,
+            registry,
+        })
+    }
+
+    async fn call(method: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router()
+            .with_state(gateway())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_lists_models() {
+        let (status, body) = call("GET", "/v1/models", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"][0]["id"], "bot:latest");
+    }
+
+    #[tokio::test]
+    async fn test_errors_use_openai_shape() {
+        let (status, body) = call(
+            "POST",
+            "/v1/chat/completions",
+            json!({"model": "nope", "messages": []}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+
+        let (status, body) = call(
+            "POST",
+            "/v1/chat/completions",
+            json!({"model": "bot", "messages": [{"role": "user", "content": "Hi"}]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("mlx_lm.server is not reachable")
+        );
+    }
+
+    #[test]
+    fn test_sampling_fields() {
+        let options = sampling(&json!({
+            "model": "bot",
+            "stop": "\n",
+            "max_completion_tokens": 32,
+            "logprobs": true,
+        }));
+        assert_eq!(options["stop"], json!(["\n"]));
+        assert_eq!(options["max_tokens"], json!(32));
+        assert!(!options.contains_key("logprobs"));
+    }
+}
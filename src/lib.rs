@@ -1,4 +1,5 @@
 pub mod core;
+pub mod gateway;
 pub mod runner;
 
 #[cfg(test)]
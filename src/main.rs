@@ -1,25 +1,333 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use tiles::core::{
+    alias::Alias,
+    convert::Format,
+    hf_cache::Framework,
+    logging::{self, LogFormat},
+    modelfile::{Overrides, ResolveOptions, StopPolicy},
+};
+use tiles::gateway::Api;
 mod commands;
+use commands::ShowSection;
 #[derive(Debug, Parser)]
 #[command(name = "tiles")]
 #[command(version, about = "Run, fine-tune models locally with Modelfile", long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Writes logs on stderr as text or json, filtered by $TILES_LOG
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    /// Runs the given modelfile Path
-    Run { modelfile_path: String },
+    /// Runs the given modelfile Path or registered model name
+    Run {
+        modelfile_path: String,
+
+        /// Sets a Modelfile variable, e.g. --arg BOT_NAME=tilly
+        #[arg(long = "arg", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
+
+        /// Overrides a PARAMETER for this run, e.g. --set temperature=0.1
+        #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        parameters: Vec<(String, String)>,
+
+        /// Overrides the SYSTEM prompt for this run
+        #[arg(long)]
+        system: Option<String>,
+
+        /// Prints the effective configuration before running
+        #[arg(short, long)]
+        verbose: bool,
+    },
 
     /// Checks the status of dependencies
-    Health,
+    Health {
+        /// Also checks that model files are complete, for the given Modelfiles
+        /// or registered models, or for every registered model
+        #[arg(long)]
+        models: bool,
+
+        #[arg(requires = "models")]
+        modelfiles: Vec<String>,
+
+        /// Hashes weights against the digests they were downloaded under
+        #[arg(long, requires = "models")]
+        sha256: bool,
+
+        /// Prints the report as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Applies safe fixes such as syncing the server venv, then checks again
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Installs the Python server and syncs its environment with uv
+    Setup {
+        /// Syncs and verifies the environment even when it is already set up
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Serves Modelfiles as models over an OpenAI or Ollama compatible API
+    Serve {
+        /// Modelfile paths or registered names, every registered model when omitted
+        modelfiles: Vec<String>,
+
+        /// Also serves the Modelfiles in this directory
+        #[arg(long)]
+        dir: Option<PathBuf>,
+
+        /// API to speak: openai, or ollama for tools built on Ollama
+        #[arg(long, default_value = "openai")]
+        api: Api,
+
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// Defaults to 6970 for openai and 11434 for ollama
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// Uses a running mlx_lm.server instead of starting one
+        #[arg(long, value_name = "URL")]
+        mlx_url: Option<String>,
+    },
 
     /// start or stop the daemon server
     Server(ServerArgs),
+
+    /// Lists the models the server has loaded
+    Ps,
+
+    /// Shows or rotates the token clients need to talk to the daemon
+    Token(TokenArgs),
+
+    /// Converts a Modelfile to or from json, yaml and toml. Values are kept
+    /// as written, comments are dropped since structured formats have no place for them
+    Convert {
+        input: String,
+
+        /// Output format: json, yaml, toml or modelfile
+        #[arg(long)]
+        to: Format,
+
+        /// Input format, guessed from the file extension when omitted
+        #[arg(long)]
+        from: Option<Format>,
+    },
+
+    /// Prints the JSON Schema for structured Modelfiles
+    Schema,
+
+    /// Shows the effective configuration of a Modelfile
+    #[command(group(ArgGroup::new("section").args(["parameters", "template", "system", "license", "json"])))]
+    Show {
+        modelfile_path: String,
+
+        /// Prints only the parameters
+        #[arg(long)]
+        parameters: bool,
+
+        /// Prints only the template
+        #[arg(long)]
+        template: bool,
+
+        /// Prints only the system prompt
+        #[arg(long)]
+        system: bool,
+
+        /// Prints only the license
+        #[arg(long)]
+        license: bool,
+
+        /// Prints the summary as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Sets a Modelfile variable, e.g. --arg BOT_NAME=tilly
+        #[arg(long = "arg", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
+
+        /// Overrides a PARAMETER, e.g. --set temperature=0.1
+        #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        overrides: Vec<(String, String)>,
+    },
+
+    /// Registers a Modelfile under a name, e.g. mybot or mybot:v2
+    Create {
+        name: String,
+
+        /// Path to the Modelfile
+        #[arg(short = 'f', long = "file", default_value = "Modelfile")]
+        modelfile_path: String,
+
+        /// Sets a Modelfile variable, e.g. --arg BOT_NAME=tilly
+        #[arg(long = "arg", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
+    },
+
+    /// Lists registered models
+    #[command(alias = "ls")]
+    List,
+
+    /// Removes registered models
+    Rm {
+        #[arg(required = true)]
+        names: Vec<String>,
+    },
+
+    /// Copies a registered model to a new name
+    Cp { source: String, destination: String },
+
+    /// Manages aliases from Ollama model names to MLX and GGUF artifacts
+    Alias(AliasArgs),
+
+    /// Imports models from other tools into the local registry
+    Import(ImportArgs),
+
+    /// Exports models into the layout other tools read
+    Export(ExportArgs),
+
+    /// Browses models downloaded to the Hugging Face cache
+    Models(ModelsArgs),
+
+    /// Prints the Modelfile with its inheritance chain flattened
+    Resolve {
+        modelfile_path: String,
+
+        /// How inherited `stop` parameters combine: concat or replace
+        #[arg(long, default_value = "concat")]
+        stop_policy: StopPolicy,
+
+        /// Sets a Modelfile variable, e.g. --arg BOT_NAME=tilly
+        #[arg(long = "arg", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
+    },
+}
+
+#[derive(Debug, Args)]
+#[command(flatten_help = true)]
+struct AliasArgs {
+    #[command(subcommand)]
+    command: AliasCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum AliasCommands {
+    /// Lists built-in and user aliases
+    List,
+
+    /// Adds or replaces a user alias
+    Add {
+        /// Ollama style name, e.g. llama3.2:latest
+        name: String,
+
+        /// Hugging Face repo or local directory with MLX weights
+        #[arg(long)]
+        mlx: Option<String>,
+
+        /// Path to a GGUF file, used by `tiles export ollama`; tiles only runs MLX weights
+        #[arg(long)]
+        gguf: Option<String>,
+    },
+
+    /// Removes a user alias
+    Rm { name: String },
+}
+
+#[derive(Debug, Args)]
+#[command(flatten_help = true)]
+struct ImportArgs {
+    #[command(subcommand)]
+    command: ImportCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum ImportCommands {
+    /// Imports a model pulled with Ollama, read straight from its models dir
+    Ollama {
+        /// Ollama model name, e.g. llama3.2 or llama3.2:1b
+        name: String,
+
+        /// Registers the model under this name instead of the Ollama one,
+        /// which is `namespace-name:tag` outside the library namespace
+        #[arg(long = "as", value_name = "NAME")]
+        target: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+#[command(flatten_help = true)]
+struct ExportArgs {
+    #[command(subcommand)]
+    command: ExportCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum ExportCommands {
+    /// Writes the model as an Ollama manifest with sha256 addressed blobs
+    Ollama {
+        /// Modelfile path or registered model name
+        modelfile_path: String,
+
+        /// Ollama models dir to write into, e.g. ~/.ollama/models
+        #[arg(long)]
+        to: PathBuf,
+
+        /// Name Ollama lists the model under, defaults to the Modelfile name
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+#[command(flatten_help = true)]
+struct ModelsArgs {
+    #[command(subcommand)]
+    command: ModelsCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum ModelsCommands {
+    /// Lists cached models with their size, framework and quantization
+    #[command(alias = "ls")]
+    List {
+        /// Only lists models of this framework: mlx, gguf, pytorch, tokenizer or unknown
+        #[arg(long)]
+        framework: Option<Framework>,
+
+        /// Prints the list as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Shows the details of a cached model
+    Show {
+        /// Repo such as mlx-community/Llama-3.2-1B-Instruct-4bit, or a unique part of it
+        repo: String,
+
+        /// Prints the details as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Deletes a cached model and its download locks
+    Rm {
+        /// Exact repo such as mlx-community/Llama-3.2-1B-Instruct-4bit
+        repo: String,
+
+        /// Deletes without asking for confirmation
+        #[arg(short, long)]
+        force: bool,
+    },
 }
 
 #[derive(Debug, Args)]
@@ -33,26 +341,255 @@ struct ServerArgs {
 #[derive(Debug, Subcommand)]
 enum ServerCommands {
     /// Start the py server as a daemon
-    Start,
+    Start {
+        /// Listens on TCP port 6969 instead of a Unix socket only the current user can open
+        #[arg(long)]
+        tcp: bool,
+
+        /// Restarts the py server with a backoff when it crashes
+        #[arg(long)]
+        supervise: bool,
+
+        /// Stays in the foreground with logs on stderr, for systemd and the like
+        #[arg(long)]
+        foreground: bool,
+    },
 
     /// Stops the daemon py server
     Stop,
+
+    /// Installs a systemd user unit that runs the daemon from login to logout
+    InstallService {
+        /// Installs a system unit running it from boot instead, run with sudo
+        #[arg(long)]
+        system: bool,
+    },
+
+    /// Stops the systemd service and removes its unit
+    UninstallService {
+        /// Removes the system unit instead of the user one
+        #[arg(long)]
+        system: bool,
+    },
+
+    /// Shows whether the daemon is up, and why it crashed recently
+    Status,
+
+    /// Lists the conversations open on the daemon
+    Sessions,
+
+    /// Loads a Modelfile's model ahead of its first run
+    Load {
+        modelfile_path: String,
+
+        /// Idle time before it is unloaded, e.g. 10m, 2h, or -1 to keep it loaded
+        #[arg(long, allow_hyphen_values = true)]
+        keep_alive: Option<String>,
+    },
+
+    /// Unloads a Modelfile's model, or every loaded model when omitted
+    Unload { modelfile_path: Option<String> },
+
+    /// Runs the server behind the queueing proxy, started by `tiles server start`
+    #[command(hide = true)]
+    Proxy {
+        #[arg(long)]
+        tcp: bool,
+
+        #[arg(long)]
+        supervise: bool,
+    },
+}
+
+#[derive(Debug, Args)]
+#[command(flatten_help = true)]
+struct TokenArgs {
+    #[command(subcommand)]
+    command: TokenCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum TokenCommands {
+    /// Prints the current token
+    Show,
+
+    /// Replaces the token, clients holding the old one are rejected from then on
+    Rotate,
 }
+
+fn parse_key_value(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("expected NAME=VALUE, got `{}`", input)),
+    }
+}
+
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
+    // The proxy runs in the background, where its log is all there is to go on
+    let level = match &cli.command {
+        Commands::Server(ServerArgs {
+            command: Some(ServerCommands::Proxy { .. }),
+        }) => "info",
+        _ => "warn",
+    };
+    logging::init(cli.log_format, level);
     match cli.command {
-        Commands::Run { modelfile_path } => {
-            commands::run(modelfile_path.as_str()).await;
+        Commands::Run {
+            modelfile_path,
+            args,
+            parameters,
+            system,
+            verbose,
+        } => {
+            let options = ResolveOptions {
+                args,
+                ..Default::default()
+            };
+            let overrides = Overrides { parameters, system };
+            commands::run(modelfile_path.as_str(), options, overrides, verbose).await;
         }
-        Commands::Health => {
-            commands::check_health();
+        Commands::Health {
+            models,
+            modelfiles,
+            sha256,
+            json,
+            fix,
+        } => {
+            commands::check_health(models, &modelfiles, sha256, json, fix).await;
+        }
+        Commands::Setup { force } => commands::setup(force),
+        Commands::Serve {
+            modelfiles,
+            dir,
+            api,
+            host,
+            port,
+            mlx_url,
+        } => {
+            let addr = format!("{}:{}", host, port.unwrap_or(api.default_port()));
+            commands::serve(&modelfiles, dir.as_deref(), api, &addr, mlx_url).await;
         }
         Commands::Server(server) => match server.command {
-            Some(ServerCommands::Start) => commands::start_server(),
+            Some(ServerCommands::Start {
+                tcp,
+                supervise,
+                foreground,
+            }) => commands::start_server(tcp, supervise, foreground, cli.log_format).await,
             Some(ServerCommands::Stop) => commands::stop_server(),
-            _ => println!("Expected start or stop"),
+            Some(ServerCommands::InstallService { system }) => {
+                commands::install_service(system, cli.log_format)
+            }
+            Some(ServerCommands::UninstallService { system }) => {
+                commands::uninstall_service(system)
+            }
+            Some(ServerCommands::Status) => commands::server_status().await,
+            Some(ServerCommands::Sessions) => commands::list_sessions().await,
+            Some(ServerCommands::Load {
+                modelfile_path,
+                keep_alive,
+            }) => commands::load_on_server(&modelfile_path, keep_alive).await,
+            Some(ServerCommands::Unload { modelfile_path }) => {
+                commands::unload_on_server(modelfile_path.as_deref()).await
+            }
+            Some(ServerCommands::Proxy { tcp, supervise }) => {
+                commands::run_proxy(tcp, supervise).await
+            }
+            _ => println!("Expected start, stop, status, sessions, load or unload"),
+        },
+        Commands::Ps => commands::list_loaded().await,
+        Commands::Token(token) => match token.command {
+            TokenCommands::Show => commands::show_token(),
+            TokenCommands::Rotate => commands::rotate_token(),
         },
+        Commands::Convert { input, to, from } => {
+            commands::convert(input.as_str(), from, to);
+        }
+        Commands::Schema => {
+            commands::print_schema();
+        }
+        Commands::Show {
+            modelfile_path,
+            parameters,
+            template,
+            system,
+            license,
+            json,
+            args,
+            overrides,
+        } => {
+            let section = match (parameters, template, system, license, json) {
+                (true, ..) => ShowSection::Parameters,
+                (_, true, ..) => ShowSection::Template,
+                (_, _, true, ..) => ShowSection::System,
+                (.., true, _) => ShowSection::License,
+                (.., true) => ShowSection::Json,
+                _ => ShowSection::Summary,
+            };
+            let options = ResolveOptions {
+                args,
+                ..Default::default()
+            };
+            let overrides = Overrides {
+                parameters: overrides,
+                system: None,
+            };
+            commands::show(modelfile_path.as_str(), options, overrides, section);
+        }
+        Commands::Create {
+            name,
+            modelfile_path,
+            args,
+        } => {
+            let options = ResolveOptions {
+                args,
+                ..Default::default()
+            };
+            commands::create(name.as_str(), modelfile_path.as_str(), options);
+        }
+        Commands::List => commands::list_models(),
+        Commands::Rm { names } => commands::remove_models(&names),
+        Commands::Cp {
+            source,
+            destination,
+        } => commands::copy_model(source.as_str(), destination.as_str()),
+        Commands::Alias(alias) => match alias.command {
+            AliasCommands::List => commands::list_aliases(),
+            AliasCommands::Add { name, mlx, gguf } => {
+                commands::add_alias(name.as_str(), Alias { mlx, gguf })
+            }
+            AliasCommands::Rm { name } => commands::remove_alias(name.as_str()),
+        },
+        Commands::Import(import) => match import.command {
+            ImportCommands::Ollama { name, target } => {
+                commands::import_ollama(name.as_str(), target.as_deref())
+            }
+        },
+        Commands::Export(export) => match export.command {
+            ExportCommands::Ollama {
+                modelfile_path,
+                to,
+                name,
+            } => commands::export_ollama(modelfile_path.as_str(), &to, name.as_deref()),
+        },
+        Commands::Models(models) => match models.command {
+            ModelsCommands::List { framework, json } => {
+                commands::list_cached_models(framework, json)
+            }
+            ModelsCommands::Show { repo, json } => commands::show_cached_model(repo.as_str(), json),
+            ModelsCommands::Rm { repo, force } => {
+                commands::remove_cached_model(repo.as_str(), force)
+            }
+        },
+        Commands::Resolve {
+            modelfile_path,
+            stop_policy,
+            args,
+        } => {
+            let options = ResolveOptions { stop_policy, args };
+            commands::resolve(modelfile_path.as_str(), options);
+        }
     }
     Ok(())
 }
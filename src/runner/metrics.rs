@@ -0,0 +1,245 @@
+// Counters and histograms the proxy keeps about generations, rendered in
+// the Prometheus text format on `/metrics`
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+const TTFT_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+const TOKENS_PER_SECOND_BUCKETS: &[f64] = &[1.0, 2.5, 5.0, 10.0, 20.0, 40.0, 80.0, 160.0];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(&mut self.counts) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// What the server reported about a finished generation
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GenerationStats {
+    pub tokens: u64,
+    /// Seconds from the request reaching the proxy to the first token
+    pub time_to_first_token: Option<f64>,
+    /// Seconds spent generating, across every turn of the mem-agent
+    pub generation_seconds: f64,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    requests: BTreeMap<(String, String, u16), u64>,
+    errors: BTreeMap<(String, String), u64>,
+    tokens: BTreeMap<String, u64>,
+    time_to_first_token: BTreeMap<String, Histogram>,
+    tokens_per_second: BTreeMap<String, Histogram>,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    /// A request the server answered, `endpoint` is `start`, `load` or `chat`
+    pub fn record_request(&self, modelfile: &str, endpoint: &str, status: u16) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .requests
+            .entry((modelfile.to_owned(), endpoint.to_owned(), status))
+            .or_default() += 1;
+    }
+
+    /// A request that failed before or instead of an answer, e.g. `queue_full` or `cancelled`
+    pub fn record_error(&self, modelfile: &str, kind: &str) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .errors
+            .entry((modelfile.to_owned(), kind.to_owned()))
+            .or_default() += 1;
+    }
+
+    pub fn record_generation(&self, modelfile: &str, stats: GenerationStats) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.tokens.entry(modelfile.to_owned()).or_default() += stats.tokens;
+        if let Some(seconds) = stats.time_to_first_token {
+            registry
+                .time_to_first_token
+                .entry(modelfile.to_owned())
+                .or_insert_with(|| Histogram::new(TTFT_BUCKETS))
+                .observe(seconds);
+        }
+        if stats.tokens > 0 && stats.generation_seconds > 0.0 {
+            registry
+                .tokens_per_second
+                .entry(modelfile.to_owned())
+                .or_insert_with(|| Histogram::new(TOKENS_PER_SECOND_BUCKETS))
+                .observe(stats.tokens as f64 / stats.generation_seconds);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+        header(
+            &mut out,
+            "tiles_requests_total",
+            "counter",
+            "Requests the server answered, by Modelfile, endpoint and status",
+        );
+        for ((modelfile, endpoint, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "tiles_requests_total{{modelfile=\"{}\",endpoint=\"{}\",status=\"{}\"}} {}",
+                escape(modelfile),
+                escape(endpoint),
+                status,
+                count
+            );
+        }
+        header(
+            &mut out,
+            "tiles_errors_total",
+            "counter",
+            "Requests that were rejected, timed out, cancelled or failed",
+        );
+        for ((modelfile, kind), count) in &registry.errors {
+            let _ = writeln!(
+                out,
+                "tiles_errors_total{{modelfile=\"{}\",kind=\"{}\"}} {}",
+                escape(modelfile),
+                escape(kind),
+                count
+            );
+        }
+        header(
+            &mut out,
+            "tiles_generated_tokens_total",
+            "counter",
+            "Tokens generated, by Modelfile",
+        );
+        for (modelfile, count) in &registry.tokens {
+            let _ = writeln!(
+                out,
+                "tiles_generated_tokens_total{{modelfile=\"{}\"}} {}",
+                escape(modelfile),
+                count
+            );
+        }
+        histograms(
+            &mut out,
+            "tiles_time_to_first_token_seconds",
+            "Seconds from a request reaching tiles to its first token, queueing included",
+            &registry.time_to_first_token,
+        );
+        histograms(
+            &mut out,
+            "tiles_tokens_per_second",
+            "Generation speed of each request",
+            &registry.tokens_per_second,
+        );
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn histograms(out: &mut String, name: &str, help: &str, histograms: &BTreeMap<String, Histogram>) {
+    header(out, name, "histogram", help);
+    for (modelfile, histogram) in histograms {
+        let modelfile = escape(modelfile);
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{modelfile=\"{}\",le=\"{}\"}} {}",
+                name, modelfile, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{modelfile=\"{}\",le=\"+Inf\"}} {}",
+            name, modelfile, histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{{modelfile=\"{}\"}} {}",
+            name, modelfile, histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{{modelfile=\"{}\"}} {}",
+            name, modelfile, histogram.count
+        );
+    }
+}
+
+// Label values escape backslashes, quotes and newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_request("pirate:latest", "chat", 200);
+        metrics.record_request("pirate:latest", "chat", 200);
+        metrics.record_error("pirate:latest", "queue_full");
+        metrics.record_generation(
+            "pirate:latest",
+            GenerationStats {
+                tokens: 40,
+                time_to_first_token: Some(0.3),
+                generation_seconds: 2.0,
+            },
+        );
+        metrics.record_error("say \"hi\"", "cancelled");
+        let text = metrics.render();
+        assert!(text.contains(
+            "tiles_requests_total{modelfile=\"pirate:latest\",endpoint=\"chat\",status=\"200\"} 2"
+        ));
+        assert!(
+            text.contains("tiles_errors_total{modelfile=\"pirate:latest\",kind=\"queue_full\"} 1")
+        );
+        assert!(
+            text.contains("tiles_errors_total{modelfile=\"say \\\"hi\\\"\",kind=\"cancelled\"} 1")
+        );
+        assert!(text.contains("tiles_generated_tokens_total{modelfile=\"pirate:latest\"} 40"));
+        assert!(text.contains(
+            "tiles_time_to_first_token_seconds_bucket{modelfile=\"pirate:latest\",le=\"0.25\"} 0"
+        ));
+        assert!(text.contains(
+            "tiles_time_to_first_token_seconds_bucket{modelfile=\"pirate:latest\",le=\"0.5\"} 1"
+        ));
+        assert!(text.contains("tiles_tokens_per_second_sum{modelfile=\"pirate:latest\"} 20"));
+        assert!(text.contains("# TYPE tiles_tokens_per_second histogram"));
+    }
+}
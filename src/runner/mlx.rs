@@ -1,48 +1,71 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use reqwest::Client;
-use serde_json::{Value, json};
 use std::io::Write;
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::time::Duration;
 use std::{env, fs};
 use std::{io, process::Command};
+use tokio::{sync::mpsc, time};
 
+use crate::core::config::{get_config_dir, get_data_dir, get_runtime_dir, get_server_dir};
+use crate::core::health;
+use crate::core::logging::LogFormat;
 use crate::core::modelfile::Modelfile;
+use crate::core::setup::ServerEnv;
+use crate::core::token::TokenStore;
+use crate::runner::Backend;
+use crate::runner::protocol::{self, ChatMessage, ChatRequest, Position, StartRequest};
+use crate::runner::proxy;
+use crate::runner::server::{self, Handshake, Transport};
 
-pub async fn run(modelfile: Modelfile) {
+/// `name` is what the Modelfile goes by in server logs and metrics
+pub async fn run(modelfile: Modelfile, name: &str) {
     let model = modelfile.from.as_ref().unwrap();
-    if model.starts_with("driaforall/mem-agent") {
-        let _res = run_model_with_server(modelfile).await;
-    } else {
-        run_model_by_sub_process(modelfile);
+    let backend = Backend::for_model(model);
+    let (model_ref, model_argument) = match modelfile.model_ref().and_then(|model_ref| {
+        let model_argument = backend.model_argument(&model_ref)?;
+        Ok((model_ref, model_argument))
+    }) {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            eprintln!("❌ Error: {}", err);
+            return;
+        }
+    };
+    // Incomplete downloads otherwise surface as obscure loader errors
+    let check = health::check_model(&model_ref, false);
+    if !check.is_healthy() {
+        eprintln!("❌ Error: {} is incomplete", check.model);
+        for issue in &check.issues {
+            match &issue.file {
+                Some(file) => eprintln!("   {}: {}", file, issue.problem),
+                None => eprintln!("   {}", issue.problem),
+            }
+        }
+        eprintln!("💡 Hint: {}", check.issues[0].fix);
+        return;
+    }
+    for warning in backend.warnings(&modelfile) {
+        eprintln!("⚠️ {}", warning);
+    }
+    match backend {
+        Backend::Server => {
+            let _res = run_model_with_server(modelfile, name, &model_argument).await;
+        }
+        Backend::MlxChat => run_model_by_sub_process(modelfile, model_argument),
     }
 }
 
-fn run_model_by_sub_process(modelfile: Modelfile) {
+fn run_model_by_sub_process(modelfile: Modelfile, model: String) {
     // build the arg list from modelfile
     let mut args: Vec<String> = vec![];
     args.push("--model".to_owned());
-    args.push(modelfile.from.unwrap());
+    args.push(model);
     for parameter in modelfile.parameters {
-        let param_value = parameter.value.to_string();
-        match parameter.param_type.as_str() {
-            "num_predict" => {
-                args.push("--max-tokens".to_owned());
-                args.push(param_value);
-            }
-            "temperature" => {
-                args.push("--temp".to_owned());
-                args.push(param_value);
-            }
-            "top_p" => {
-                args.push("--top-p".to_owned());
-                args.push(param_value);
-            }
-            "seed" => {
-                args.push("--seed".to_owned());
-                args.push(param_value);
-            }
-            _ => {}
+        if let Some(capability) = Backend::MlxChat.capability(&parameter.param_type) {
+            args.push(capability.option.to_owned());
+            args.push(parameter.value.to_string());
         }
     }
     if let Some(system_prompt) = modelfile.system {
@@ -73,38 +96,113 @@ fn run_model_by_sub_process(modelfile: Modelfile) {
     }
 }
 
+// Checks a daemon can start and readies its token, `None` when one is
+// already running
+fn prepare_daemon(tcp: bool) -> Result<Option<Transport>> {
+    let server_dir = get_server_dir()?;
+    if health::daemon_pid().is_some() {
+        eprintln!("Server is already running");
+        return Ok(None);
+    }
+    if clean_stale_daemon()? {
+        eprintln!("Cleaned up after a server that is no longer running");
+    }
+    if !ServerEnv::at(server_dir.clone()).is_ready() {
+        bail!(
+            "The server environment in {} is not set up, run `tiles setup`",
+            server_dir.display()
+        );
+    }
+
+    let transport = if tcp {
+        Transport::tcp()
+    } else {
+        Transport::unix()?
+    };
+    let tokens = TokenStore::open()?;
+    tokens.get_or_create()?;
+    tokens.check_permissions()?;
+    Ok(Some(transport))
+}
+
 #[allow(clippy::zombie_processes)]
-pub fn start_server_daemon() -> Result<()> {
+pub fn start_server_daemon(tcp: bool, supervise: bool, log_format: LogFormat) -> Result<()> {
     // check if the server is running
     // start server as a child process
     // save the pid in a file under ~/.config/tiles/server_pid
     let config_dir = get_config_dir()?;
-    let server_dir = get_server_dir()?;
     let pid_file = config_dir.join("server.pid");
-    if pid_file.exists() {
-        eprintln!("Server is already running");
+    let Some(transport) = prepare_daemon(tcp)? else {
         return Ok(());
-    }
+    };
 
-    let child = Command::new("uv")
-        .args([
-            "run",
-            "--project",
-            server_dir.to_str().unwrap(),
-            "python",
-            "-m",
-            "server.main",
-        ])
+    // The proxy starts the server on a socket of its own and stays in front of it
+    let mut proxy = Command::new(env::current_exe().context("Failed to locate tiles")?);
+    proxy.args(["server", "proxy", "--log-format", &log_format.to_string()]);
+    if tcp {
+        proxy.arg("--tcp");
+    }
+    if supervise {
+        proxy.arg("--supervise");
+    }
+    fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config_dir.join("server.log"))
+        .context("Failed to open the server log")?;
+    let child = proxy
         .stdout(Stdio::null())
-        .stderr(Stdio::null())
+        .stderr(log)
         .spawn()
-        .expect("failed to start server");
-    fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+        .context("Failed to start the server")?;
     std::fs::write(pid_file, child.id().to_string()).unwrap();
-    println!("Server started with PID {}", child.id());
+    transport.record()?;
+    println!("Server started with PID {} on {}", child.id(), transport);
+    println!(
+        "Logs are written to {}",
+        config_dir.join("server.log").display()
+    );
     Ok(())
 }
 
+/// Runs the proxy in this process with logs on stderr, for a service
+/// manager such as systemd to look after. Exits with 1 when it can't start.
+pub async fn run_server_foreground(tcp: bool, supervise: bool) -> Result<()> {
+    let config_dir = get_config_dir()?;
+    let Some(transport) = prepare_daemon(tcp)? else {
+        std::process::exit(1);
+    };
+    fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+    fs::write(
+        config_dir.join("server.pid"),
+        std::process::id().to_string(),
+    )
+    .context("Failed to write the pid file")?;
+    transport.record()?;
+    println!(
+        "Server running with PID {} on {}",
+        std::process::id(),
+        transport
+    );
+    proxy::run(transport, supervise).await
+}
+
+/// Removes the pid file and socket of a daemon that died without cleaning
+/// up, returning whether there was one
+pub fn clean_stale_daemon() -> Result<bool> {
+    let pid_file = get_config_dir()?.join("server.pid");
+    if !pid_file.exists() || health::daemon_pid().is_some() {
+        return Ok(false);
+    }
+    fs::remove_file(&pid_file).context("Failed to remove the stale pid file")?;
+    Transport::current()?.clear()?;
+    let runtime_dir = get_runtime_dir()?;
+    let _ = fs::remove_file(runtime_dir.join("daemon.sock"));
+    let _ = fs::remove_dir_all(runtime_dir.join("cancel"));
+    Ok(true)
+}
+
 pub fn stop_server_daemon() -> Result<()> {
     let pid_file = get_config_dir()?.join("server.pid");
 
@@ -115,39 +213,108 @@ pub fn stop_server_daemon() -> Result<()> {
 
     let pid = std::fs::read_to_string(&pid_file).unwrap();
     Command::new("kill").arg(pid.trim()).status().unwrap();
-    std::fs::remove_file(pid_file).unwrap();
+    // The proxy cleans up as it exits, this covers one that was killed
+    let _ = std::fs::remove_file(pid_file);
+    Transport::current()?.clear()?;
     println!("Server stopped.");
     Ok(())
 }
-async fn run_model_with_server(modelfile: Modelfile) -> reqwest::Result<()> {
+async fn run_model_with_server(
+    modelfile: Modelfile,
+    name: &str,
+    modelname: &str,
+) -> reqwest::Result<()> {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
     // loading the model from mem-agent via daemon server
     let memory_path = get_memory_path()
         .context("Retrieving memory_path failed")
         .unwrap();
-    let modelname = modelfile.from.as_ref().unwrap();
-    load_model(modelname, &memory_path).await.unwrap();
+    let transport = match Transport::current() {
+        Ok(transport) => transport,
+        Err(err) => {
+            eprintln!("❌ Error: {:#}", err);
+            return Ok(());
+        }
+    };
+    let handshake = match server::handshake(&transport).await {
+        Ok(handshake) => handshake,
+        Err(err) => {
+            eprintln!("❌ Error: {:#}", err);
+            return Ok(());
+        }
+    };
+    if handshake == Handshake::Legacy {
+        eprintln!("⚠️ The tiles server predates version checks, run `tiles setup` and restart it");
+    }
+    let mut options = server::chat_options(&modelfile);
+    if !options.is_empty() && !handshake.supports("sampling_options") {
+        eprintln!("⚠️ The tiles server ignores sampling PARAMETERs, running with its defaults");
+        options.clear();
+    }
+    let client = transport.client()?;
+    let start = StartRequest {
+        model: modelname.to_owned(),
+        memory_path,
+        system_prompt: modelfile.system.clone(),
+        session_id: Some(protocol::new_id()),
+        keep_alive: modelfile.keep_alive(),
+    };
+    let session_id = match server::start_session(&transport, &client, &start, name).await {
+        Ok(session_id) => session_id,
+        Err(err) => {
+            eprintln!("❌ Error: {:#}", err);
+            return Ok(());
+        }
+    };
     println!("Running in interactive mode");
-    loop {
-        print!(">> ");
-        stdout.flush().unwrap();
-        let mut input = String::new();
-        stdin.read_line(&mut input).unwrap();
-        let input = input.trim();
-        match input {
-            "exit" => {
-                println!("Exiting interactive mode");
+    // Stdin is read on its own thread so ctrl-c still reaches the select below
+    let (lines_tx, mut lines) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in stdin.lines() {
+            let Ok(line) = line else { break };
+            if lines_tx.send(line).is_err() {
                 break;
             }
-            _ => {
-                if let Ok(response) = chat(input, modelname).await {
-                    println!(">> {}", response)
-                } else {
-                    println!(">> failed to respond")
+        }
+    });
+    let repl = async {
+        loop {
+            print!(">> ");
+            stdout.flush().unwrap();
+            let input = lines.recv().await;
+            match input.as_deref().map(str::trim) {
+                None | Some("exit") => {
+                    println!("Exiting interactive mode");
+                    break;
+                }
+                Some(input) => {
+                    let request = ChatRequest {
+                        model: modelname.to_owned(),
+                        messages: vec![ChatMessage {
+                            role: "user".to_owned(),
+                            content: input.to_owned(),
+                        }],
+                        session_id: session_id.clone(),
+                        prompt: None,
+                        options: options.clone(),
+                    };
+                    match chat(&transport, &client, &request, name).await {
+                        Ok(response) => println!(">> {}", response),
+                        Err(err) => println!(">> failed to respond: {:#}", err),
+                    }
                 }
             }
         }
+    };
+    tokio::select! {
+        _ = repl => {}
+        _ = tokio::signal::ctrl_c() => println!("\nExiting interactive mode"),
+    }
+    if let Some(session_id) = session_id
+        && let Err(err) = server::close_session(&transport, &client, &session_id).await
+    {
+        eprintln!("⚠️ Failed to close session {}: {:#}", session_id, err);
     }
     Ok(())
 }
@@ -159,114 +326,71 @@ async fn run_model_with_server(modelfile: Modelfile) -> reqwest::Result<()> {
 //     Ok(())
 // }
 
-async fn load_model(model_name: &str, memory_path: &str) -> Result<(), String> {
-    let client = Client::new();
-    let body = json!({
-        "model": model_name,
-        "memory_path": memory_path
-    });
-    let res = client
-        .post("http://127.0.0.1:6969/start")
-        .json(&body)
-        .send()
-        .await
-        .unwrap();
-    if res.status() == 200 {
-        Ok(())
-    } else {
-        Err(String::from("request failed"))
+// Shows the queue position while the proxy holds the generation back
+async fn chat(
+    transport: &Transport,
+    client: &Client,
+    request: &ChatRequest,
+    modelfile: &str,
+) -> Result<String> {
+    let id = protocol::new_id();
+    let reply = server::chat(transport, client, request, &id, modelfile);
+    tokio::pin!(reply);
+    let mut queued = false;
+    loop {
+        tokio::select! {
+            reply = &mut reply => {
+                if queued {
+                    eprint!("\r\x1b[K");
+                }
+                return reply;
+            }
+            _ = time::sleep(Duration::from_millis(500)) => {
+                match server::queue_position(transport, client, &id).await {
+                    Ok(Some(Position::Queued { ahead, .. })) => {
+                        queued = true;
+                        eprint!("\r\x1b[Kqueued, {} ahead", ahead);
+                    }
+                    Ok(Some(Position::Running { .. })) if queued => {
+                        queued = false;
+                        eprint!("\r\x1b[K");
+                    }
+                    _ => {}
+                }
+            }
+        }
     }
 }
 
-async fn chat(input: &str, model_name: &str) -> Result<String, String> {
-    let client = Client::new();
-    let body = json!({
-        "model": model_name,
-        "messages": [{"role": "user", "content": input}]
-    });
-    let res = client
-        .post("http://127.0.0.1:6969/v1/chat/completions")
-        .json(&body)
-        .send()
-        .await
-        .unwrap();
-    // println!("{:?}", res);
-    if res.status() == 200 {
-        let text = res.text().await.unwrap();
-        let v: Value = serde_json::from_str(&text).unwrap();
-        let content = v["choices"][0]["message"]["content"]
-            .as_str()
-            .unwrap_or("<no content>");
-        Ok(content.to_owned())
-    } else {
-        Err(String::from("request failed"))
+/// Memory dir the mem-agent reads and writes, created on first use
+pub fn get_memory_path() -> Result<String> {
+    if let Some(memory_path) = configured_memory_path()? {
+        return Ok(memory_path.to_string_lossy().to_string());
     }
+    let tiles_config_dir = get_config_dir()?;
+    let memory_path = default_memory_path()?;
+    fs::create_dir_all(&memory_path).context("Failed to create tiles memory directory")?;
+    fs::create_dir_all(&tiles_config_dir).context("Failed to create tiles config directory")?;
+    fs::write(
+        tiles_config_dir.join(".memory_path"),
+        memory_path.to_str().unwrap(),
+    )
+    .context("Failed to write the default path to .memory_path")?;
+    Ok(memory_path.to_string_lossy().to_string())
 }
 
-fn get_memory_path() -> Result<String> {
+/// Memory dir set in `.memory_path` in the config dir, if any
+pub fn configured_memory_path() -> Result<Option<PathBuf>> {
     let tiles_config_dir = get_config_dir()?;
-    let tiles_data_dir = get_data_dir()?;
-    let mut is_memory_path_found: bool = false;
-    let mut memory_path: String = String::from("");
     if tiles_config_dir.is_dir()
         && let Ok(content) = fs::read_to_string(tiles_config_dir.join(".memory_path"))
     {
-        memory_path = content;
-        is_memory_path_found = true;
-    }
-
-    if is_memory_path_found {
-        Ok(memory_path)
-    } else {
-        let memory_path = tiles_data_dir.join("memory");
-        fs::create_dir_all(&memory_path).context("Failed to create tiles memory directory")?;
-        fs::create_dir_all(&tiles_config_dir).context("Failed to create tiles config directory")?;
-        fs::write(
-            tiles_config_dir.join(".memory_path"),
-            memory_path.to_str().unwrap(),
-        )
-        .context("Failed to write the default path to .memory_path")?;
-        Ok(memory_path.to_string_lossy().to_string())
-    }
-}
-
-fn get_server_dir() -> Result<PathBuf> {
-    if cfg!(debug_assertions) {
-        let base_dir = env::current_dir().context("Failed to fetch CURRENT_DIR")?;
-        Ok(base_dir.join("server"))
-    } else {
-        let home_dir = env::home_dir().context("Failed to fetch $HOME")?;
-        let data_dir = match env::var("XDG_DATA_HOME") {
-            Ok(val) => PathBuf::from(val),
-            Err(_err) => home_dir.join(".local/share"),
-        };
-        Ok(data_dir.join("tiles/server"))
-    }
-}
-fn get_config_dir() -> Result<PathBuf> {
-    if cfg!(debug_assertions) {
-        let base_dir = env::current_dir().context("Failed to fetch CURRENT_DIR")?;
-        Ok(base_dir.join(".tiles_dev/tiles"))
-    } else {
-        let home_dir = env::home_dir().context("Failed to fetch $HOME")?;
-        let config_dir = match env::var("XDG_CONFIG_HOME") {
-            Ok(val) => PathBuf::from(val),
-            Err(_err) => home_dir.join(".config"),
-        };
-        Ok(config_dir.join("tiles"))
+        return Ok(Some(PathBuf::from(content.trim())));
     }
+    Ok(None)
 }
 
-fn get_data_dir() -> Result<PathBuf> {
-    if cfg!(debug_assertions) {
-        let base_dir = env::current_dir().context("Failed to fetch CURRENT_DIR")?;
-        Ok(base_dir.join(".tiles_dev/tiles"))
-    } else {
-        let home_dir = env::home_dir().context("Failed to fetch $HOME")?;
-        let data_dir = match env::var("XDG_DATA_HOME") {
-            Ok(val) => PathBuf::from(val),
-            Err(_err) => home_dir.join(".local/share"),
-        };
-        Ok(data_dir.join("tiles"))
-    }
+/// Memory dir used when `.memory_path` is not set
+pub fn default_memory_path() -> Result<PathBuf> {
+    Ok(get_data_dir()?.join("memory"))
 }
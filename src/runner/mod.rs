@@ -1 +1,219 @@
+pub mod metrics;
 pub mod mlx;
+pub mod protocol;
+pub mod proxy;
+pub mod scheduler;
+pub mod server;
+pub mod service;
+pub mod supervisor;
+
+use serde::Serialize;
+
+use crate::core::{
+    alias::{Alias, AliasTable},
+    hf_cache::HfCache,
+    model_ref::{ModelRef, WeightsFormat},
+    modelfile::Modelfile,
+};
+
+/// The ways tiles can run a Modelfile
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// `mlx_lm.chat` spawned as a sub process
+    MlxChat,
+    /// The python daemon started with `tiles server start`
+    Server,
+}
+
+/// A PARAMETER a backend understands, the option it is passed as and the
+/// value the backend uses when the Modelfile doesn't set it
+#[derive(Debug, Clone, Copy)]
+pub struct Capability {
+    pub parameter: &'static str,
+    pub option: &'static str,
+    pub default: Option<&'static str>,
+}
+
+const MLX_CHAT_CAPABILITIES: &[Capability] = &[
+    Capability {
+        parameter: "num_predict",
+        option: "--max-tokens",
+        default: Some("256"),
+    },
+    Capability {
+        parameter: "temperature",
+        option: "--temp",
+        default: Some("0"),
+    },
+    Capability {
+        parameter: "top_p",
+        option: "--top-p",
+        default: Some("1"),
+    },
+    Capability {
+        parameter: "seed",
+        option: "--seed",
+        default: None,
+    },
+];
+
+const SERVER_CAPABILITIES: &[Capability] = &[
+    Capability {
+        parameter: "temperature",
+        option: "temperature",
+        default: Some("0.7"),
+    },
+    Capability {
+        parameter: "top_p",
+        option: "top_p",
+        default: Some("0.9"),
+    },
+    Capability {
+        parameter: "num_predict",
+        option: "max_tokens",
+        default: None,
+    },
+    Capability {
+        parameter: "repeat_penalty",
+        option: "repetition_penalty",
+        default: Some("1.1"),
+    },
+    Capability {
+        parameter: "stop",
+        option: "stop",
+        default: None,
+    },
+];
+
+impl Backend {
+    /// Picks the backend `run` uses for the model in FROM
+    pub fn for_model(model: &str) -> Backend {
+        if model.starts_with("driaforall/mem-agent") {
+            Backend::Server
+        } else {
+            Backend::MlxChat
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::MlxChat => "mlx_lm.chat",
+            Backend::Server => "tiles server",
+        }
+    }
+
+    pub fn capabilities(&self) -> &'static [Capability] {
+        match self {
+            Backend::MlxChat => MLX_CHAT_CAPABILITIES,
+            Backend::Server => SERVER_CAPABILITIES,
+        }
+    }
+
+    pub fn capability(&self, parameter: &str) -> Option<&'static Capability> {
+        self.capabilities()
+            .iter()
+            .find(|capability| capability.parameter == parameter)
+    }
+
+    /// PARAMETERs the Modelfile sets that this backend accepts but won't act on
+    pub fn warnings(&self, modelfile: &Modelfile) -> Vec<String> {
+        let mut warnings = vec![];
+        // mlx_lm.chat loads the model for as long as the chat runs, only the
+        // server keeps it around between sessions
+        if *self == Backend::MlxChat && modelfile.keep_alive().is_some() {
+            warnings.push(format!(
+                "PARAMETER keep_alive has no effect with {}, it only applies to models run by the tiles server",
+                self.name()
+            ));
+        }
+        warnings
+    }
+
+    /// Validates the model reference and returns the value to load it with,
+    /// failing with an explanation when this backend can't load it
+    pub fn model_argument(&self, model_ref: &ModelRef) -> Result<String, String> {
+        // Both backends run MLX weights, so Ollama names go through the alias table
+        if let ModelRef::OllamaTag { name, tag } = model_ref {
+            let name = format!("{}:{}", name, tag);
+            let aliases = AliasTable::load().map_err(|err| err.to_string())?;
+            let target = match aliases.lookup(&name) {
+                Some(Alias { mlx: Some(mlx), .. }) => mlx,
+                Some(Alias { gguf: Some(_), .. }) => {
+                    return Err(format!(
+                        "{} only has GGUF weights, which {} can't load. Map it to MLX weights with `tiles alias add {} --mlx <repo>`",
+                        name,
+                        self.name(),
+                        name
+                    ));
+                }
+                _ => {
+                    return Err(format!(
+                        "{} can't load Ollama model {}, map it with `tiles alias add {} --mlx <repo>`",
+                        self.name(),
+                        name,
+                        name
+                    ));
+                }
+            };
+            return match target.parse::<ModelRef>()? {
+                ModelRef::OllamaTag { .. } => Err(format!(
+                    "Alias for {} must point at a Hugging Face repo or a local directory",
+                    name
+                )),
+                target => self.model_argument(&target),
+            };
+        }
+
+        model_ref.validate()?;
+        match (self, model_ref) {
+            (Backend::Server, ModelRef::HuggingFace { repo, revision }) => Ok(match revision {
+                Some(revision) => format!("{}@{}", repo, revision),
+                None => repo.clone(),
+            }),
+            (
+                Backend::MlxChat,
+                ModelRef::HuggingFace {
+                    repo,
+                    revision: None,
+                },
+            ) => Ok(repo.clone()),
+            (
+                Backend::MlxChat,
+                ModelRef::HuggingFace {
+                    repo,
+                    revision: Some(revision),
+                },
+            ) => {
+                // mlx_lm only downloads the default branch, so pinned
+                // revisions have to come from the local cache
+                HfCache::open()
+                    .map_err(|err| err.to_string())?
+                    .snapshot_path(repo, revision)
+                    .map(|snapshot| snapshot.to_string_lossy().to_string())
+                    .ok_or_else(|| {
+                        format!(
+                            "Revision {} of {} is not in the Hugging Face cache",
+                            revision, repo
+                        )
+                    })
+            }
+            (Backend::MlxChat, ModelRef::LocalDir { path }) => {
+                Ok(path.to_string_lossy().to_string())
+            }
+            (
+                _,
+                ModelRef::OllamaBlob { .. }
+                | ModelRef::LocalFile {
+                    format: WeightsFormat::Gguf,
+                    ..
+                },
+            ) => Err(format!(
+                "{} can't load {}, point FROM at an MLX model on Hugging Face or a local MLX directory",
+                self.name(),
+                model_ref
+            )),
+            _ => Err(format!("{} can't load {}", self.name(), model_ref)),
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// Request and response bodies exchanged with the Python server, which
+// mirrors them as pydantic models in server/api.py
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `GET /version`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub version: String,
+    pub api_version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+impl ServerInfo {
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// `POST /start`, loads the model and opens a session with its own history
+#[derive(Debug, Clone, Serialize)]
+pub struct StartRequest {
+    pub model: String,
+    pub memory_path: String,
+    pub system_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Seconds the model stays loaded while idle, the server's default when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<i64>,
+}
+
+/// Servers without sessions only answer with `message`
+#[derive(Debug, Clone, Deserialize)]
+pub struct StartResponse {
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// `POST /v1/chat/completions`, `messages` are appended to the session's history
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// Prompt rendered with the Modelfile's TEMPLATE, used instead of the
+    /// model's chat template when the server supports `prompt`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Sampling fields from `chat_options`
+    #[serde(flatten)]
+    pub options: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatResponse {
+    pub choices: Vec<ChatChoice>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatChoice {
+    pub message: ChatMessage,
+}
+
+impl ChatResponse {
+    pub fn content(&self) -> Option<&str> {
+        self.choices
+            .first()
+            .map(|choice| choice.message.content.as_str())
+    }
+}
+
+/// An entry of `GET /sessions`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub model: String,
+    /// Messages in the history, the system prompt included
+    pub messages: usize,
+    /// Unix timestamps
+    pub created: u64,
+    pub last_active: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionList {
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// `GET /queue/{id}` on the proxy, where a generation is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum Position {
+    Queued { model: String, ahead: usize },
+    Running { model: String },
+}
+
+/// An entry of `GET /queue` on the proxy, generations running and waiting for a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub model: String,
+    pub running: usize,
+    pub waiting: usize,
+    pub limit: usize,
+}
+
+/// `POST /models/load`, loads a model ahead of its first chat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadRequest {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<i64>,
+}
+
+/// `POST /models/unload`, every model when `model` is unset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnloadRequest {
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnloadResponse {
+    pub unloaded: Vec<String>,
+}
+
+/// An entry of `GET /models`, a model the server holds in memory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadedModel {
+    pub model: String,
+    /// Name of the Modelfile that last used it
+    pub modelfile: Option<String>,
+    /// Bytes of memory the model took when it was loaded
+    pub size: u64,
+    pub loaded_at: u64,
+    pub last_used: u64,
+    pub keep_alive: i64,
+    /// When it is unloaded if it stays idle, `None` keeps it loaded
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoadedModels {
+    pub models: Vec<LoadedModel>,
+}
+
+/// `GET /status` on the proxy, how the Python server behind it is doing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub proxy_pid: u32,
+    /// Whether the proxy restarts the server when it exits
+    pub supervised: bool,
+    pub server: ServerState,
+    pub restarts: u32,
+    /// Most recent last
+    pub crashes: Vec<Crash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum ServerState {
+    Running {
+        pid: u32,
+        since: u64,
+    },
+    /// Waiting out the backoff before starting the server again
+    Restarting {
+        at: u64,
+    },
+    Exited,
+}
+
+/// A time the server exited on its own
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Crash {
+    pub at: u64,
+    /// Exit code or signal
+    pub status: String,
+    /// The error the server logged last, if any
+    pub reason: Option<String>,
+}
+
+/// Seconds since the epoch, what timestamps in the protocol are in
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Names sessions and generations, ids only need to be unique on one daemon.
+/// The counter keeps ids apart within a process, where the clock can repeat
+pub fn new_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}-{:x}", std::process::id(), nanos, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_chat_request_flattens_options() {
+        let mut options = Map::new();
+        options.insert("temperature".to_owned(), json!(0.2));
+        let request = ChatRequest {
+            model: "driaforall/mem-agent".to_owned(),
+            messages: vec![ChatMessage {
+                role: "user".to_owned(),
+                content: "Hi".to_owned(),
+            }],
+            session_id: Some("abc".to_owned()),
+            prompt: None,
+            options,
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({
+                "model": "driaforall/mem-agent",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "session_id": "abc",
+                "temperature": 0.2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_start_response_from_server_without_sessions() {
+        let response: StartResponse =
+            serde_json::from_str(r#"{"message": "Model loaded"}"#).unwrap();
+        assert_eq!(response.session_id, None);
+        assert_ne!(new_id(), "");
+        let ids: std::collections::HashSet<String> = (0..1000).map(|_| new_id()).collect();
+        assert_eq!(ids.len(), 1000);
+    }
+}
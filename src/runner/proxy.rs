@@ -0,0 +1,625 @@
+// Proxy `tiles server start` runs in front of the Python server. It owns the
+// socket clients connect to, checks their token, queues generations per
+// model and asks the server to stop generating for clients that went away.
+// The server itself is run by the `Supervisor`.
+
+use anyhow::{Context, Result, bail};
+use axum::{
+    Json, Router,
+    body::{Body, Bytes, to_bytes},
+    extract::{Path as UrlPath, Request, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    process::Command,
+    signal::unix::{SignalKind, signal},
+    sync::{Notify, oneshot},
+    time,
+};
+use tracing::{Instrument, debug, info, info_span, warn};
+
+use crate::core::{
+    config::{get_config_dir, get_runtime_dir, get_server_dir},
+    settings::Settings,
+    token::TokenStore,
+};
+use crate::runner::{
+    metrics::{GenerationStats, Metrics},
+    protocol,
+    scheduler::{Rejection, Scheduler},
+    server::{MODELFILE_HEADER, REQUEST_ID_HEADER, Transport},
+    supervisor::Supervisor,
+};
+
+/// Paths whose requests wait for a slot of the model in their body
+const SCHEDULED: &[&str] = &["/start", "/models/load", "/v1/chat/completions"];
+const MAX_BODY: usize = 16 * 1024 * 1024;
+// Headers describing the connection rather than the message
+const HOP_BY_HOP: &[&str] = &["host", "connection", "content-length", "transfer-encoding"];
+
+pub struct Proxy {
+    client: Client,
+    /// Socket the Python server listens on, only the proxy talks to it
+    upstream: PathBuf,
+    scheduler: Arc<Scheduler>,
+    tokens: TokenStore,
+    /// A file named after a generation asks the server to stop it
+    cancel_dir: PathBuf,
+    metrics: Metrics,
+    supervisor: Arc<Supervisor>,
+}
+
+/// Runs the server behind a proxy listening on `listen` until a signal, or
+/// until the server exits unless `supervise` restarts it
+pub async fn run(listen: Transport, supervise: bool) -> Result<()> {
+    let settings = Settings::load()?;
+    let server_dir = get_server_dir()?;
+    let runtime_dir = get_runtime_dir()?;
+    fs::create_dir_all(&runtime_dir).context("Failed to create the runtime directory")?;
+    fs::set_permissions(&runtime_dir, fs::Permissions::from_mode(0o700))
+        .context("Failed to restrict the runtime directory")?;
+    let upstream = runtime_dir.join("daemon.sock");
+    let _ = fs::remove_file(&upstream);
+    let cancel_dir = runtime_dir.join("cancel");
+    let _ = fs::remove_dir_all(&cancel_dir);
+    fs::create_dir_all(&cancel_dir).context("Failed to create the cancel directory")?;
+    let tokens = TokenStore::open()?;
+
+    let command = {
+        let server = settings.server.clone();
+        let upstream = upstream.clone();
+        let token_file = tokens.path().to_owned();
+        let cancel_dir = cancel_dir.clone();
+        move || {
+            // A server that crashed leaves its socket behind
+            let _ = fs::remove_file(&upstream);
+            let mut command = Command::new("uv");
+            command
+                .args(["run", "--project"])
+                .arg(&server_dir)
+                .args(["python", "-m", "server.main", "--uds"])
+                .arg(&upstream)
+                .arg("--token-file")
+                .arg(&token_file)
+                .arg("--cancel-dir")
+                .arg(&cancel_dir)
+                .arg("--keep-alive")
+                .arg(server.keep_alive.to_string())
+                .arg("--max-loaded")
+                .arg(server.max_loaded.to_string())
+                .arg("--watch-pid")
+                .arg(std::process::id().to_string())
+                .stdout(Stdio::null());
+            command
+        }
+    };
+    let supervisor = Supervisor::new(supervise);
+    let exited = Arc::new(Notify::new());
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn({
+        let supervisor = supervisor.clone();
+        let exited = exited.clone();
+        async move {
+            supervisor.run(command, stopped).await;
+            exited.notify_one();
+        }
+    });
+
+    let proxy = Arc::new(Proxy {
+        client: Client::builder().unix_socket(upstream.clone()).build()?,
+        upstream,
+        scheduler: Scheduler::new(settings.scheduler),
+        tokens,
+        cancel_dir,
+        metrics: Metrics::default(),
+        supervisor,
+    });
+    let scrape = settings.metrics.enabled.then(|| {
+        let address = settings.metrics.address.clone();
+        let app = Router::new()
+            .route("/metrics", get(metrics))
+            .with_state(proxy.clone());
+        tokio::spawn(async move {
+            match TcpListener::bind(&address).await {
+                Ok(listener) => {
+                    info!(%address, "serving metrics");
+                    if let Err(err) = axum::serve(listener, app).await {
+                        warn!(%err, "the metrics listener failed");
+                    }
+                }
+                Err(err) => warn!(%address, %err, "can't serve metrics"),
+            }
+        })
+    });
+    info!(%listen, "starting the proxy");
+    let shutdown = async move {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(_) => return,
+        };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+            _ = exited.notified() => {}
+        }
+    };
+    let app = router(proxy.clone());
+    let served = match &listen {
+        Transport::Unix(path) => {
+            let _ = fs::remove_file(path);
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to listen on {}", path.display()))?;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+                .context("Failed to restrict the socket")?;
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+        Transport::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to listen on {}", addr))?;
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await
+        }
+    };
+
+    if let Some(scrape) = scrape {
+        scrape.abort();
+    }
+    // The supervisor dropped its end when the server exited on its own
+    let server_exited = stop.send(()).is_err();
+    let _ = server.await;
+    let _ = fs::remove_file(&proxy.upstream);
+    let _ = fs::remove_dir_all(&proxy.cancel_dir);
+    let _ = fs::remove_file(get_config_dir()?.join("server.pid"));
+    listen.clear()?;
+    served.context("The proxy failed")?;
+    if server_exited {
+        match proxy.supervisor.status().crashes.pop() {
+            Some(crash) => bail!(
+                "The server exited with {}{}",
+                crash.status,
+                crash
+                    .reason
+                    .map(|reason| format!(": {}", reason))
+                    .unwrap_or_default()
+            ),
+            None => bail!("The server exited"),
+        }
+    }
+    Ok(())
+}
+
+pub fn router(proxy: Arc<Proxy>) -> Router {
+    Router::new()
+        .route("/queue", get(queue))
+        .route("/queue/{id}", get(position))
+        .route("/metrics", get(metrics))
+        .route("/status", get(status))
+        .fallback(forward)
+        .layer(middleware::from_fn_with_state(proxy.clone(), authorize))
+        .with_state(proxy)
+}
+
+// Errors in the shape FastAPI uses, which the client reads `detail` from
+fn error(status: StatusCode, detail: impl Into<String>) -> Response {
+    (status, Json(json!({"detail": detail.into()}))).into_response()
+}
+
+async fn authorize(State(proxy): State<Arc<Proxy>>, request: Request, next: Next) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !proxy.tokens.verify(authorization) {
+        return error(StatusCode::UNAUTHORIZED, "missing or invalid token");
+    }
+    next.run(request).await
+}
+
+async fn queue(State(proxy): State<Arc<Proxy>>) -> Response {
+    Json(json!({"queues": proxy.scheduler.stats()})).into_response()
+}
+
+async fn status(State(proxy): State<Arc<Proxy>>) -> Response {
+    Json(proxy.supervisor.status()).into_response()
+}
+
+async fn position(State(proxy): State<Arc<Proxy>>, UrlPath(id): UrlPath<String>) -> Response {
+    match proxy.scheduler.position(&id) {
+        Some(position) => Json(position).into_response(),
+        None => error(StatusCode::NOT_FOUND, format!("no generation {}", id)),
+    }
+}
+
+async fn metrics(State(proxy): State<Arc<Proxy>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        proxy.metrics.render(),
+    )
+        .into_response()
+}
+
+async fn forward(State(proxy): State<Arc<Proxy>>, request: Request) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_BODY).await {
+        Ok(body) => body,
+        Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    let id = parts
+        .headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| valid_id(id))
+        .map(str::to_owned)
+        .unwrap_or_else(protocol::new_id);
+    let path = parts.uri.path();
+    let model = (parts.method == "POST" && SCHEDULED.contains(&path))
+        .then(|| serde_json::from_slice::<Value>(&body).ok())
+        .flatten()
+        .and_then(|body| body["model"].as_str().map(str::to_owned));
+    let Some(model) = model else {
+        debug!(request_id = %id, method = %parts.method, path, "forwarding");
+        return match proxy.exchange(&parts, body, &id).await {
+            Ok(reply) => reply.into_response(),
+            Err(response) => response,
+        };
+    };
+    let modelfile = parts
+        .headers
+        .get(MODELFILE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|name| valid_label(name))
+        .unwrap_or(&model)
+        .to_owned();
+    let span = info_span!("generation", request_id = %id, path, %model, %modelfile);
+    proxy
+        .generate(parts, body, id, model, modelfile)
+        .instrument(span)
+        .await
+}
+
+/// An answer from the server, read whole
+struct Reply {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl IntoResponse for Reply {
+    fn into_response(self) -> Response {
+        (self.status, self.headers, Body::from(self.body)).into_response()
+    }
+}
+
+impl Proxy {
+    async fn generate(
+        self: Arc<Self>,
+        parts: Parts,
+        body: Bytes,
+        id: String,
+        model: String,
+        modelfile: String,
+    ) -> Response {
+        let arrived = Instant::now();
+        let endpoint = match parts.uri.path() {
+            "/start" => "start",
+            "/models/load" => "load",
+            _ => "chat",
+        };
+        let _permit = match self.scheduler.acquire(&model, &id).await {
+            Ok(permit) => permit,
+            Err(rejection) => {
+                warn!(%rejection, "turned away");
+                let (status, kind) = match rejection {
+                    Rejection::QueueFull { .. } => (StatusCode::TOO_MANY_REQUESTS, "queue_full"),
+                    Rejection::TimedOut { .. } => {
+                        (StatusCode::SERVICE_UNAVAILABLE, "queue_timeout")
+                    }
+                    Rejection::DuplicateId { .. } => (StatusCode::CONFLICT, "duplicate_id"),
+                };
+                self.metrics.record_error(&modelfile, kind);
+                return error(status, rejection.to_string());
+            }
+        };
+        let waited = arrived.elapsed();
+        info!(waited = waited.as_secs_f64(), "started");
+        // Dropped with the request when the client goes away or time runs out
+        let mut cancellation = Cancellation {
+            path: Some(self.cancel_dir.join(&id)),
+            proxy: self.clone(),
+            modelfile: modelfile.clone(),
+            kind: "cancelled",
+        };
+        let timeout = self.scheduler.settings().request_timeout();
+        let reply = match time::timeout(timeout, self.exchange(&parts, body, &id)).await {
+            Ok(reply) => {
+                cancellation.finish();
+                reply
+            }
+            Err(_) => {
+                cancellation.kind = "request_timeout";
+                return error(
+                    StatusCode::GATEWAY_TIMEOUT,
+                    format!(
+                        "generation took longer than {}s and was cancelled",
+                        timeout.as_secs()
+                    ),
+                );
+            }
+        };
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(response) => {
+                warn!(status = %response.status(), "couldn't reach the server");
+                self.metrics.record_error(&modelfile, "unreachable");
+                return response;
+            }
+        };
+        self.metrics
+            .record_request(&modelfile, endpoint, reply.status.as_u16());
+        if reply.status.is_server_error() {
+            warn!(status = %reply.status, "the server failed");
+            self.metrics.record_error(&modelfile, "server_error");
+        } else if endpoint == "chat"
+            && reply.status.is_success()
+            && let Some(stats) = generation_stats(&reply.body, waited)
+        {
+            info!(
+                tokens = stats.tokens,
+                time_to_first_token = stats.time_to_first_token,
+                generation_seconds = stats.generation_seconds,
+                "finished"
+            );
+            self.metrics.record_generation(&modelfile, stats);
+        } else {
+            info!(status = %reply.status, "finished");
+        }
+        reply.into_response()
+    }
+
+    // The whole response is read so the slot is held until the server is done
+    async fn exchange(&self, parts: &Parts, body: Bytes, id: &str) -> Result<Reply, Response> {
+        let path_and_query = parts
+            .uri
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+        let mut headers = HeaderMap::new();
+        for (name, value) in &parts.headers {
+            if !HOP_BY_HOP.contains(&name.as_str()) {
+                headers.append(name, value.clone());
+            }
+        }
+        let response = self
+            .client
+            .request(
+                parts.method.clone(),
+                format!("http://tiles{}", path_and_query),
+            )
+            .headers(headers)
+            .header(REQUEST_ID_HEADER, id)
+            .body(body)
+            .send()
+            .await;
+        let response = match response {
+            Ok(response) => response,
+            Err(err) if err.is_connect() => {
+                return Err(error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "the tiles server is still starting",
+                ));
+            }
+            Err(err) => return Err(error(StatusCode::BAD_GATEWAY, err.to_string())),
+        };
+        let status = response.status();
+        let mut headers = response.headers().clone();
+        for name in HOP_BY_HOP {
+            headers.remove(*name);
+        }
+        match response.bytes().await {
+            Ok(body) => Ok(Reply {
+                status,
+                headers,
+                body,
+            }),
+            Err(err) => Err(error(StatusCode::BAD_GATEWAY, err.to_string())),
+        }
+    }
+}
+
+// The server reports `usage` and `timings` with each chat, its time to first
+// token starts when the server picks the request up so the wait here is added
+fn generation_stats(body: &[u8], waited: Duration) -> Option<GenerationStats> {
+    let body: Value = serde_json::from_slice(body).ok()?;
+    let tokens = body["usage"]["completion_tokens"].as_u64()?;
+    let timings = &body["timings"];
+    Some(GenerationStats {
+        tokens,
+        time_to_first_token: timings["time_to_first_token"]
+            .as_f64()
+            .map(|seconds| seconds + waited.as_secs_f64()),
+        generation_seconds: timings["generation_seconds"].as_f64().unwrap_or_default(),
+    })
+}
+
+// Modelfile names end up as metric labels, which keep to one short line
+fn valid_label(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 128 && !name.chars().any(char::is_control)
+}
+
+// Ids name files in the cancel dir, so they stay plain
+fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Asks the server to stop a generation unless it finished
+struct Cancellation {
+    path: Option<PathBuf>,
+    proxy: Arc<Proxy>,
+    modelfile: String,
+    /// What the metrics count the stop as
+    kind: &'static str,
+}
+
+impl Cancellation {
+    fn finish(mut self) {
+        self.path = None;
+    }
+}
+
+impl Drop for Cancellation {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            warn!(reason = self.kind, "cancelling");
+            self.proxy.metrics.record_error(&self.modelfile, self.kind);
+            let _ = fs::write(path, "");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::Request, routing::post};
+    use tower::ServiceExt;
+
+    // A stand-in for the Python server that takes a while to answer chats
+    async fn serve_upstream(path: PathBuf) {
+        let app = Router::new()
+            .route(
+                "/version",
+                get(|| async { Json(json!({"version": "0.1.0"})) }),
+            )
+            .route(
+                "/v1/chat/completions",
+                post(|| async {
+                    time::sleep(time::Duration::from_millis(300)).await;
+                    Json(json!({
+                        "choices": [],
+                        "usage": {"completion_tokens": 12},
+                        "timings": {"time_to_first_token": 0.1, "generation_seconds": 0.2},
+                    }))
+                }),
+            );
+        let listener = UnixListener::bind(path).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    }
+
+    fn proxy(upstream: PathBuf, tokens: TokenStore, cancel_dir: PathBuf) -> Arc<Proxy> {
+        Arc::new(Proxy {
+            client: Client::builder()
+                .unix_socket(upstream.clone())
+                .build()
+                .unwrap(),
+            upstream,
+            scheduler: Scheduler::new(Default::default()),
+            tokens,
+            cancel_dir,
+            metrics: Metrics::default(),
+            supervisor: Supervisor::new(false),
+        })
+    }
+
+    fn request(method: &str, uri: &str, token: &str, body: Value) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", token))
+            .header(REQUEST_ID_HEADER, "req-1")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_forwards_and_cancels() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("daemon.sock");
+        serve_upstream(socket.clone()).await;
+        let tokens = TokenStore::at(dir.path().join("server.token"));
+        let token = tokens.rotate().unwrap();
+        let cancel_dir = dir.path().join("cancel");
+        fs::create_dir_all(&cancel_dir).unwrap();
+        let proxy = proxy(socket, tokens, cancel_dir.clone());
+
+        let response = router(proxy.clone())
+            .oneshot(request("GET", "/version", "wrong", Value::Null))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = router(proxy.clone())
+            .oneshot(request("GET", "/version", &token, Value::Null))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A chat whose client goes away before the answer is cancelled
+        let chat = request(
+            "POST",
+            "/v1/chat/completions",
+            &token,
+            json!({"model": "bot", "messages": []}),
+        );
+        let abandoned = tokio::spawn(router(proxy.clone()).oneshot(chat));
+        time::sleep(time::Duration::from_millis(100)).await;
+        assert!(proxy.scheduler.position("req-1").is_some());
+        abandoned.abort();
+        let _ = abandoned.await;
+        assert!(cancel_dir.join("req-1").exists());
+        assert_eq!(proxy.scheduler.position("req-1"), None);
+
+        let chat = request(
+            "POST",
+            "/v1/chat/completions",
+            &token,
+            json!({"model": "bot", "messages": []}),
+        );
+        fs::remove_file(cancel_dir.join("req-1")).unwrap();
+        let response = router(proxy.clone()).oneshot(chat).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!cancel_dir.join("req-1").exists());
+
+        let response = router(proxy.clone())
+            .oneshot(request("GET", "/metrics", &token, Value::Null))
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), MAX_BODY).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("tiles_errors_total{modelfile=\"bot\",kind=\"cancelled\"} 1"));
+        assert!(text.contains(
+            "tiles_requests_total{modelfile=\"bot\",endpoint=\"chat\",status=\"200\"} 1"
+        ));
+        assert!(text.contains("tiles_generated_tokens_total{modelfile=\"bot\"} 12"));
+        assert!(text.contains("tiles_tokens_per_second_sum{modelfile=\"bot\"} 60"));
+    }
+
+    #[test]
+    fn test_valid_ids() {
+        assert!(valid_id(&protocol::new_id()));
+        assert!(!valid_id("../server.token"));
+        assert!(!valid_id(""));
+        assert!(valid_label("pirate:latest"));
+        assert!(!valid_label("two\nlines"));
+    }
+}
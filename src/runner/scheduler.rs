@@ -0,0 +1,300 @@
+// Per model FIFO queue with a concurrency limit, used by the proxy in front
+// of the server. A generation whose client goes away leaves the queue, or
+// frees its slot, when its future is dropped.
+
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+use tokio::{sync::oneshot, time};
+
+use crate::core::settings::SchedulerSettings;
+use crate::runner::protocol::{Position, QueueStats};
+
+#[derive(Debug, Default)]
+struct ModelQueue {
+    running: HashSet<String>,
+    waiting: VecDeque<Waiter>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: String,
+    wake: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    QueueFull {
+        model: String,
+        max_queue: usize,
+    },
+    TimedOut {
+        model: String,
+        seconds: u64,
+    },
+    /// Another generation goes by the same id
+    DuplicateId {
+        id: String,
+    },
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::QueueFull { model, max_queue } => write!(
+                f,
+                "{} already has {} generations queued, try again later",
+                model, max_queue
+            ),
+            Rejection::TimedOut { model, seconds } => write!(
+                f,
+                "gave up after waiting {}s in the queue for {}",
+                seconds, model
+            ),
+            Rejection::DuplicateId { id } => write!(
+                f,
+                "generation {} is already queued or running, send a new request id",
+                id
+            ),
+        }
+    }
+}
+
+pub struct Scheduler {
+    settings: SchedulerSettings,
+    queues: Mutex<BTreeMap<String, ModelQueue>>,
+}
+
+/// A running slot for a model, handed to the next waiter when dropped
+pub struct Permit {
+    scheduler: Arc<Scheduler>,
+    model: String,
+    id: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.scheduler.release(&self.model, &self.id);
+    }
+}
+
+// Takes a waiter that gave up out of the queue, or passes on the slot it
+// was handed just before it gave up
+struct Abandon<'a> {
+    scheduler: &'a Arc<Scheduler>,
+    model: &'a str,
+    id: &'a str,
+}
+
+impl Drop for Abandon<'_> {
+    fn drop(&mut self) {
+        let mut queues = self.scheduler.queues.lock().unwrap();
+        let queue = queues.entry(self.model.to_owned()).or_default();
+        if let Some(index) = queue.waiting.iter().position(|w| w.id == self.id) {
+            queue.waiting.remove(index);
+        } else if queue.running.contains(self.id) {
+            drop(queues);
+            self.scheduler.release(self.model, self.id);
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn new(settings: SchedulerSettings) -> Arc<Scheduler> {
+        Arc::new(Scheduler {
+            settings,
+            queues: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn settings(&self) -> &SchedulerSettings {
+        &self.settings
+    }
+
+    /// Waits for a slot for `model`, `id` names the generation in `position`
+    pub async fn acquire(self: &Arc<Self>, model: &str, id: &str) -> Result<Permit, Rejection> {
+        let permit = || Permit {
+            scheduler: self.clone(),
+            model: model.to_owned(),
+            id: id.to_owned(),
+        };
+        let woken = {
+            let mut queues = self.queues.lock().unwrap();
+            // Ids name cancel files and queue positions, so they are unique across models
+            let taken = queues.values().any(|queue| {
+                queue.running.contains(id) || queue.waiting.iter().any(|waiter| waiter.id == id)
+            });
+            if taken {
+                return Err(Rejection::DuplicateId { id: id.to_owned() });
+            }
+            let queue = queues.entry(model.to_owned()).or_default();
+            if queue.waiting.is_empty() && queue.running.len() < self.settings.limit(model) {
+                queue.running.insert(id.to_owned());
+                return Ok(permit());
+            }
+            if queue.waiting.len() >= self.settings.max_queue {
+                return Err(Rejection::QueueFull {
+                    model: model.to_owned(),
+                    max_queue: self.settings.max_queue,
+                });
+            }
+            let (wake, woken) = oneshot::channel();
+            queue.waiting.push_back(Waiter {
+                id: id.to_owned(),
+                wake,
+            });
+            woken
+        };
+        let abandon = Abandon {
+            scheduler: self,
+            model,
+            id,
+        };
+        match time::timeout(self.settings.queue_timeout(), woken).await {
+            Ok(Ok(())) => {
+                std::mem::forget(abandon);
+                Ok(permit())
+            }
+            _ => Err(Rejection::TimedOut {
+                model: model.to_owned(),
+                seconds: self.settings.queue_timeout,
+            }),
+        }
+    }
+
+    // Hands the slot to the first waiter still waiting
+    fn release(&self, model: &str, id: &str) {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(model.to_owned()).or_default();
+        if !queue.running.remove(id) {
+            return;
+        }
+        while let Some(waiter) = queue.waiting.pop_front() {
+            if waiter.wake.send(()).is_ok() {
+                queue.running.insert(waiter.id);
+                break;
+            }
+        }
+    }
+
+    pub fn position(&self, id: &str) -> Option<Position> {
+        let queues = self.queues.lock().unwrap();
+        queues.iter().find_map(|(model, queue)| {
+            if queue.running.contains(id) {
+                return Some(Position::Running {
+                    model: model.clone(),
+                });
+            }
+            queue
+                .waiting
+                .iter()
+                .position(|waiter| waiter.id == id)
+                .map(|ahead| Position::Queued {
+                    model: model.clone(),
+                    ahead,
+                })
+        })
+    }
+
+    pub fn stats(&self) -> Vec<QueueStats> {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .iter()
+            .filter(|(_, queue)| !queue.running.is_empty() || !queue.waiting.is_empty())
+            .map(|(model, queue)| QueueStats {
+                model: model.clone(),
+                running: queue.running.len(),
+                waiting: queue.waiting.len(),
+                limit: self.settings.limit(model),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn limited(max_queue: usize, queue_timeout: u64) -> Arc<Scheduler> {
+        Scheduler::new(SchedulerSettings {
+            max_queue,
+            queue_timeout,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_waiters_run_in_order() {
+        let scheduler = limited(4, 60);
+        let first = scheduler.acquire("bot", "a").await.unwrap();
+        let second = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire("bot", "b").await.map(|_| ()) }
+        });
+        let third = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire("bot", "c").await.map(|_| ()) }
+        });
+        time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            scheduler.position("c"),
+            Some(Position::Queued {
+                model: "bot".to_owned(),
+                ahead: 1
+            })
+        );
+        // Other models have their own queue
+        assert!(scheduler.acquire("other", "d").await.is_ok());
+
+        drop(first);
+        assert_eq!(second.await.unwrap(), Ok(()));
+        assert_eq!(third.await.unwrap(), Ok(()));
+        assert_eq!(scheduler.stats(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_waiter_leaves_queue() {
+        let scheduler = limited(4, 60);
+        let first = scheduler.acquire("bot", "a").await.unwrap();
+        let abandoned = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire("bot", "b").await.map(|_| ()) }
+        });
+        time::sleep(Duration::from_millis(20)).await;
+        abandoned.abort();
+        let _ = abandoned.await;
+        assert_eq!(scheduler.position("b"), None);
+
+        drop(first);
+        assert!(scheduler.acquire("bot", "c").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejections() {
+        let scheduler = limited(1, 0);
+        let _first = scheduler.acquire("bot", "a").await.unwrap();
+        let waiting = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire("bot", "b").await.map(|_| ()) }
+        });
+        assert!(matches!(
+            waiting.await.unwrap(),
+            Err(Rejection::TimedOut { .. })
+        ));
+
+        let scheduler = limited(0, 60);
+        let _first = scheduler.acquire("bot", "a").await.unwrap();
+        assert!(matches!(
+            scheduler.acquire("bot", "b").await,
+            Err(Rejection::QueueFull { .. })
+        ));
+        assert_eq!(scheduler.stats()[0].running, 1);
+        assert!(matches!(
+            scheduler.acquire("other", "a").await,
+            Err(Rejection::DuplicateId { .. })
+        ));
+    }
+}
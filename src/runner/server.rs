@@ -0,0 +1,459 @@
+// Client side of the contract with the Python server
+
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::{
+    Client, ClientBuilder, RequestBuilder, Response, StatusCode,
+    header::{AUTHORIZATION, HeaderMap, HeaderValue, InvalidHeaderValue},
+};
+use serde_json::{Map, Value, json};
+use std::{fmt::Display, fs, path::PathBuf, str::FromStr, time::Duration};
+use tracing::{debug, instrument, warn};
+
+use crate::core::config::{SERVER_PORT, get_config_dir, get_runtime_dir};
+use crate::core::modelfile::{Modelfile, ParamValue};
+use crate::core::token::TokenStore;
+use crate::runner::Backend;
+use crate::runner::protocol::{
+    self, ChatRequest, ChatResponse, LoadRequest, LoadedModel, LoadedModels, Position, ServerInfo,
+    ServerStatus, SessionInfo, SessionList, StartRequest, StartResponse, UnloadRequest,
+    UnloadResponse,
+};
+
+/// The endpoint shapes this client speaks, servers report theirs on `/version`
+pub const API_VERSION: u32 = 1;
+/// Oldest server API this client still works with
+pub const MIN_API_VERSION: u32 = 1;
+
+/// Names a generation for the proxy's queue and cancellation
+pub const REQUEST_ID_HEADER: &str = "x-tiles-request-id";
+/// Name of the Modelfile a request runs, the proxy labels metrics with it
+pub const MODELFILE_HEADER: &str = "x-tiles-modelfile";
+
+/// Capabilities `tiles run` can't do without
+const REQUIRED_CAPABILITIES: &[&str] = &["start", "chat_completions"];
+
+/// How the CLI reaches the daemon, a Unix socket unless it was started with `--tcp`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl Transport {
+    pub fn unix() -> Result<Transport> {
+        Ok(Transport::Unix(get_runtime_dir()?.join("server.sock")))
+    }
+
+    pub fn tcp() -> Transport {
+        Transport::Tcp(format!("127.0.0.1:{}", SERVER_PORT))
+    }
+
+    /// The transport the running daemon was started with
+    pub fn current() -> Result<Transport> {
+        match fs::read_to_string(get_config_dir()?.join("server.addr")) {
+            Ok(addr) => addr.trim().parse().map_err(|err: String| anyhow!(err)),
+            Err(_) => Transport::unix(),
+        }
+    }
+
+    /// Remembers the transport for clients of the daemon being started
+    pub fn record(&self) -> Result<()> {
+        let config_dir = get_config_dir()?;
+        fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
+        fs::write(config_dir.join("server.addr"), self.to_string())
+            .context("Failed to record the server address")
+    }
+
+    /// Forgets the recorded transport and removes the socket
+    pub fn clear(&self) -> Result<()> {
+        if let Transport::Unix(path) = self {
+            let _ = fs::remove_file(path);
+        }
+        let _ = fs::remove_file(get_config_dir()?.join("server.addr"));
+        Ok(())
+    }
+
+    /// Requests over a Unix socket still need a URL, its host is ignored
+    pub fn base_url(&self) -> String {
+        match self {
+            Transport::Unix(_) => "http://tiles".to_owned(),
+            Transport::Tcp(addr) => format!("http://{}", addr),
+        }
+    }
+
+    /// Sends the daemon's bearer token with every request, when there is one
+    pub fn client_builder(&self) -> ClientBuilder {
+        let builder = match self {
+            Transport::Unix(path) => Client::builder().unix_socket(path.clone()),
+            Transport::Tcp(_) => Client::builder(),
+        };
+        // A missing token is left for the server to reject, with an error saying so
+        let token = TokenStore::open().and_then(|store| store.read());
+        match token.ok().flatten().map(|token| bearer(&token)) {
+            Some(Ok(value)) => {
+                builder.default_headers(HeaderMap::from_iter([(AUTHORIZATION, value)]))
+            }
+            _ => builder,
+        }
+    }
+
+    pub fn client(&self) -> reqwest::Result<Client> {
+        self.client_builder().build()
+    }
+}
+
+fn bearer(token: &str) -> Result<HeaderValue, InvalidHeaderValue> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Unix(path) => write!(f, "unix:{}", path.display()),
+            Transport::Tcp(addr) => write!(f, "tcp:{}", addr),
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("unix", path)) if !path.is_empty() => Ok(Transport::Unix(PathBuf::from(path))),
+            Some(("tcp", addr)) if addr.contains(':') => Ok(Transport::Tcp(addr.to_owned())),
+            _ => Err(format!(
+                "Invalid server address `{}`, expected unix:<path> or tcp:<host>:<port>",
+                s
+            )),
+        }
+    }
+}
+
+/// How the client may talk to the server it connected to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Handshake {
+    Compatible(ServerInfo),
+    /// A server from before `/version`, which is assumed to speak API 1
+    Legacy,
+}
+
+impl Handshake {
+    pub fn supports(&self, capability: &str) -> bool {
+        match self {
+            Handshake::Compatible(info) => info.supports(capability),
+            Handshake::Legacy => true,
+        }
+    }
+}
+
+/// Fetches `/version`, `None` when the server predates the endpoint
+pub async fn server_info(
+    transport: &Transport,
+    client: &Client,
+) -> reqwest::Result<Option<ServerInfo>> {
+    let res = client
+        .get(format!("{}/version", transport.base_url()))
+        .send()
+        .await?;
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    res.error_for_status()?.json().await.map(Some)
+}
+
+/// Checks the running server speaks an API this client understands
+pub async fn handshake(transport: &Transport) -> Result<Handshake> {
+    let info = match server_info(transport, &transport.client()?).await {
+        Err(err) if err.status() == Some(StatusCode::UNAUTHORIZED) => bail!(
+            "The tiles server rejected the token in {}, restart it with `tiles server stop` and `tiles server start`",
+            TokenStore::open()?.path().display()
+        ),
+        info => info.context("Can't reach the tiles server, start it with `tiles server start`")?,
+    };
+    match info {
+        Some(info) => {
+            check_compatibility(&info)?;
+            Ok(Handshake::Compatible(info))
+        }
+        None => Ok(Handshake::Legacy),
+    }
+}
+
+/// Loads the model and opens the session in `request`, returning the id
+/// the server keeps it under or `None` when it predates sessions
+#[instrument(skip(transport, client, request), fields(model = %request.model))]
+pub async fn start_session(
+    transport: &Transport,
+    client: &Client,
+    request: &StartRequest,
+    modelfile: &str,
+) -> Result<Option<String>> {
+    let request_id = protocol::new_id();
+    debug!(%request_id, "loading the model");
+    let response: StartResponse = send(
+        client
+            .post(format!("{}/start", transport.base_url()))
+            .header(REQUEST_ID_HEADER, &request_id)
+            .header(MODELFILE_HEADER, modelfile)
+            .json(request),
+    )
+    .await
+    .context("Failed to load the model")?
+    .json()
+    .await?;
+    Ok(response.session_id)
+}
+
+/// `request_id` lets the caller follow the generation through `queue_position`
+#[instrument(skip(transport, client, request), fields(model = %request.model))]
+pub async fn chat(
+    transport: &Transport,
+    client: &Client,
+    request: &ChatRequest,
+    request_id: &str,
+    modelfile: &str,
+) -> Result<String> {
+    debug!("sending the chat");
+    let response: ChatResponse = send(
+        client
+            .post(format!("{}/v1/chat/completions", transport.base_url()))
+            .header(REQUEST_ID_HEADER, request_id)
+            .header(MODELFILE_HEADER, modelfile)
+            .json(request),
+    )
+    .await?
+    .json()
+    .await?;
+    debug!("got the reply");
+    Ok(response.content().unwrap_or_default().to_owned())
+}
+
+/// Drops the session's history, a session the server no longer has is fine
+#[instrument(skip(transport, client))]
+pub async fn close_session(transport: &Transport, client: &Client, id: &str) -> Result<()> {
+    let response = client
+        .delete(format!("{}/sessions/{}", transport.base_url(), id))
+        .send()
+        .await?;
+    if response.status() != StatusCode::NOT_FOUND {
+        response.error_for_status()?;
+    }
+    Ok(())
+}
+
+/// Where the proxy has a generation, `None` once it finished
+pub async fn queue_position(
+    transport: &Transport,
+    client: &Client,
+    request_id: &str,
+) -> Result<Option<Position>> {
+    let response = client
+        .get(format!("{}/queue/{}", transport.base_url(), request_id))
+        .send()
+        .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(response.error_for_status()?.json().await?))
+}
+
+pub async fn list_sessions(transport: &Transport) -> Result<Vec<SessionInfo>> {
+    let client = transport.client()?;
+    let list: SessionList = send(client.get(format!("{}/sessions", transport.base_url())))
+        .await
+        .context("Can't list sessions, is the server running? Start it with `tiles server start`")?
+        .json()
+        .await?;
+    Ok(list.sessions)
+}
+
+/// Loads the model ahead of its first chat
+pub async fn load_model(
+    transport: &Transport,
+    client: &Client,
+    request: &LoadRequest,
+    modelfile: &str,
+) -> Result<LoadedModel> {
+    Ok(send(
+        client
+            .post(format!("{}/models/load", transport.base_url()))
+            .header(MODELFILE_HEADER, modelfile)
+            .json(request),
+    )
+    .await
+    .context("Failed to load the model")?
+    .json()
+    .await?)
+}
+
+/// Unloads `model`, or every model, returning the models unloaded
+pub async fn unload_models(
+    transport: &Transport,
+    client: &Client,
+    model: Option<&str>,
+) -> Result<Vec<String>> {
+    let request = UnloadRequest {
+        model: model.map(str::to_owned),
+    };
+    let response: UnloadResponse = send(
+        client
+            .post(format!("{}/models/unload", transport.base_url()))
+            .json(&request),
+    )
+    .await?
+    .json()
+    .await?;
+    Ok(response.unloaded)
+}
+
+/// Models the server holds in memory, most recently used last
+pub async fn loaded_models(transport: &Transport, client: &Client) -> Result<Vec<LoadedModel>> {
+    let list: LoadedModels = send(client.get(format!("{}/models", transport.base_url())))
+        .await?
+        .json()
+        .await?;
+    Ok(list.models)
+}
+
+/// How the proxy and the server behind it are doing
+pub async fn server_status(transport: &Transport) -> Result<ServerStatus> {
+    let client = transport
+        .client_builder()
+        .timeout(Duration::from_secs(2))
+        .build()?;
+    Ok(send(client.get(format!("{}/status", transport.base_url())))
+        .await?
+        .json()
+        .await?)
+}
+
+// Turns error statuses into errors carrying the server's `detail`
+async fn send(request: RequestBuilder) -> Result<Response> {
+    let response = request.send().await?;
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    warn!(%status, detail = body["detail"].as_str(), "the tiles server refused a request");
+    match body["detail"].as_str() {
+        Some(detail) => bail!("tiles server answered {}: {}", status, detail),
+        None => bail!("tiles server answered {}", status),
+    }
+}
+
+pub fn check_compatibility(info: &ServerInfo) -> Result<()> {
+    if info.api_version < MIN_API_VERSION {
+        bail!(
+            "tiles server {} speaks API {}, tiles {} needs at least API {}. Run `tiles setup` and restart the server",
+            info.version,
+            info.api_version,
+            env!("CARGO_PKG_VERSION"),
+            MIN_API_VERSION
+        );
+    }
+    if info.api_version > API_VERSION {
+        bail!(
+            "tiles server {} speaks API {}, newer than the API {} of tiles {}. Update tiles",
+            info.version,
+            info.api_version,
+            API_VERSION,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    let missing: Vec<&str> = REQUIRED_CAPABILITIES
+        .iter()
+        .copied()
+        .filter(|capability| !info.supports(capability))
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "tiles server {} lacks {}. Run `tiles setup` and restart the server",
+            info.version,
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+// Maps Modelfile parameters onto the server's chat completion fields
+pub fn chat_options(modelfile: &Modelfile) -> Map<String, Value> {
+    let mut options = Map::new();
+    let mut stop = vec![];
+    for parameter in &modelfile.parameters {
+        let value = match &parameter.value {
+            ParamValue::Int(value) => json!(value),
+            ParamValue::Float(value) => json!(value),
+            ParamValue::Str(value) => json!(value),
+        };
+        match Backend::Server.capability(&parameter.param_type) {
+            Some(capability) if capability.parameter == "stop" => stop.push(value),
+            Some(capability) => {
+                options.insert(capability.option.to_owned(), value);
+            }
+            None => {}
+        }
+    }
+    if !stop.is_empty() {
+        options.insert("stop".to_owned(), Value::Array(stop));
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(api_version: u32, capabilities: &[&str]) -> ServerInfo {
+        ServerInfo {
+            version: "0.1.0".to_owned(),
+            api_version,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_compatible_server() {
+        let server = info(API_VERSION, &["start", "chat_completions"]);
+        assert!(check_compatibility(&server).is_ok());
+        assert!(!Handshake::Compatible(server).supports("sampling_options"));
+        assert!(Handshake::Legacy.supports("sampling_options"));
+    }
+
+    #[test]
+    fn test_incompatible_servers() {
+        let too_old =
+            check_compatibility(&info(MIN_API_VERSION - 1, &["start", "chat_completions"]));
+        assert!(too_old.unwrap_err().to_string().contains("tiles setup"));
+        let too_new = check_compatibility(&info(API_VERSION + 1, &["start", "chat_completions"]));
+        assert!(too_new.unwrap_err().to_string().contains("Update tiles"));
+        let missing = check_compatibility(&info(API_VERSION, &["start"]));
+        assert!(
+            missing
+                .unwrap_err()
+                .to_string()
+                .contains("lacks chat_completions")
+        );
+    }
+
+    #[test]
+    fn test_transport_round_trip() {
+        let unix: Transport = "unix:/run/user/1000/tiles/server.sock".parse().unwrap();
+        assert_eq!(unix.to_string(), "unix:/run/user/1000/tiles/server.sock");
+        assert_eq!(unix.base_url(), "http://tiles");
+        let tcp = Transport::tcp();
+        assert_eq!(tcp.to_string().parse::<Transport>().unwrap(), tcp);
+        assert_eq!(tcp.base_url(), "http://127.0.0.1:6969");
+        assert!("tcp:nowhere".parse::<Transport>().is_err());
+    }
+
+    #[test]
+    fn test_parse_version_response() {
+        let body = r#"{"version": "0.1.0", "api_version": 1, "capabilities": ["start"]}"#;
+        let info: ServerInfo = serde_json::from_str(body).unwrap();
+        assert_eq!(info.api_version, 1);
+        assert!(info.supports("start"));
+    }
+}
@@ -0,0 +1,258 @@
+// systemd units running `tiles server start --foreground`, so the daemon
+// comes up at login (user units) or at boot (system units)
+
+use anyhow::{Context, Result, bail};
+use std::{collections::BTreeMap, env, fmt::Write, fs, path::PathBuf, process::Command};
+
+use crate::core::{
+    config::{get_config_dir_in, get_server_dir_in},
+    logging::LogFormat,
+    settings::Settings,
+};
+
+pub const UNIT_NAME: &str = "tiles.service";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Runs as the user from login to logout
+    User,
+    /// Runs as the user who ran sudo from boot, installing it needs root
+    System,
+}
+
+impl Scope {
+    pub fn unit_path(&self) -> Result<PathBuf> {
+        match self {
+            Scope::User => {
+                let config_dir = match env::var("XDG_CONFIG_HOME") {
+                    Ok(val) if !val.is_empty() => PathBuf::from(val),
+                    _ => env::home_dir()
+                        .context("Failed to fetch $HOME")?
+                        .join(".config"),
+                };
+                Ok(config_dir.join("systemd/user").join(UNIT_NAME))
+            }
+            Scope::System => Ok(PathBuf::from("/etc/systemd/system").join(UNIT_NAME)),
+        }
+    }
+
+    fn systemctl(&self, args: &[&str]) -> Result<()> {
+        let mut command = Command::new("systemctl");
+        if *self == Scope::User {
+            command.arg("--user");
+        }
+        let status = command
+            .args(args)
+            .status()
+            .context("Failed to run systemctl")?;
+        if !status.success() {
+            bail!("`systemctl {}` failed with {}", args.join(" "), status);
+        }
+        Ok(())
+    }
+}
+
+/// What the unit runs and with which environment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub exe: PathBuf,
+    pub working_dir: PathBuf,
+    /// Unset for user units
+    pub user: Option<String>,
+    pub log_format: LogFormat,
+    pub environment: BTreeMap<String, String>,
+}
+
+impl Unit {
+    /// A unit for this tiles binary and the user's server dir, with the PATH
+    /// uv was found on and the `[service.environment]` of `tiles.toml`. A
+    /// system unit runs as the user who ran sudo, with their directories
+    pub fn current(scope: Scope, log_format: LogFormat) -> Result<Unit> {
+        let sudo_user = env::var("SUDO_USER").ok().filter(|user| !user.is_empty());
+        let (user, home) = match scope {
+            Scope::User if sudo_user.is_some() => {
+                bail!("A user unit is installed without sudo, pass --system for a system one")
+            }
+            Scope::User => (None, env::home_dir().context("Failed to fetch $HOME")?),
+            Scope::System => {
+                let user = match sudo_user {
+                    Some(user) => user,
+                    None => env::var("USER").context("Failed to fetch $USER")?,
+                };
+                let home = home_of(&user)?;
+                (Some(user), home)
+            }
+        };
+        let settings = Settings::load_from(&get_config_dir_in(&home)?.join("tiles.toml"))?;
+        let mut environment = BTreeMap::new();
+        let mut path = env::var("PATH").unwrap_or_default();
+        if user.is_some() {
+            // sudo resets PATH, uv installs itself to one of these
+            for dir in [".cargo/bin", ".local/bin"] {
+                path = format!("{}:{}", home.join(dir).display(), path);
+            }
+        }
+        environment.insert("PATH".to_owned(), path);
+        for name in ["XDG_CONFIG_HOME", "XDG_DATA_HOME"] {
+            if let Ok(value) = env::var(name) {
+                environment.insert(name.to_owned(), value);
+            }
+        }
+        environment.insert(
+            "TILES_SERVER_DIR".to_owned(),
+            get_server_dir_in(&home)?.to_string_lossy().to_string(),
+        );
+        environment.extend(settings.service.environment);
+        Ok(Unit {
+            exe: env::current_exe().context("Failed to locate tiles")?,
+            working_dir: home,
+            user,
+            log_format,
+            environment,
+        })
+    }
+
+    pub fn render(&self) -> String {
+        let mut unit = String::new();
+        let flag = match self.user {
+            Some(_) => " --system",
+            None => "",
+        };
+        let _ = writeln!(
+            unit,
+            "# Written by `tiles server install-service{}`, remove it with `tiles server uninstall-service{}`",
+            flag, flag
+        );
+        let _ = writeln!(unit, "[Unit]");
+        let _ = writeln!(unit, "Description=tiles model server");
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Service]");
+        let _ = writeln!(unit, "Type=simple");
+        let _ = writeln!(
+            unit,
+            "ExecStart={} server start --foreground --log-format {}",
+            quote(&self.exe.to_string_lossy()),
+            self.log_format
+        );
+        let _ = writeln!(
+            unit,
+            "WorkingDirectory={}",
+            self.working_dir.to_string_lossy().replace('%', "%%")
+        );
+        if let Some(user) = &self.user {
+            let _ = writeln!(unit, "User={}", user);
+        }
+        for (name, value) in &self.environment {
+            let _ = writeln!(
+                unit,
+                "Environment={}",
+                quote(&format!("{}={}", name, value))
+            );
+        }
+        // The proxy exits with an error when the server behind it dies
+        let _ = writeln!(unit, "Restart=on-failure");
+        let _ = writeln!(unit, "RestartSec=5");
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Install]");
+        let target = match self.user {
+            Some(_) => "multi-user.target",
+            None => "default.target",
+        };
+        let _ = writeln!(unit, "WantedBy={}", target);
+        unit
+    }
+}
+
+// systemd unquotes double quoted words, and expands % specifiers even there
+fn quote(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%")
+    )
+}
+
+// Home directory of `user` from /etc/passwd
+fn home_of(user: &str) -> Result<PathBuf> {
+    let passwd = fs::read_to_string("/etc/passwd").context("Failed to read /etc/passwd")?;
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<&str>>())
+        .find(|fields| fields.len() > 5 && fields[0] == user)
+        .map(|fields| PathBuf::from(fields[5]))
+        .with_context(|| format!("No home directory for {} in /etc/passwd", user))
+}
+
+/// Writes the unit and enables it, starting it now unless `start` is false
+pub fn install(scope: Scope, unit: &Unit, start: bool) -> Result<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        bail!("Services are only supported with systemd on Linux");
+    }
+    let path = scope.unit_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(&path, unit.render())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    scope.systemctl(&["daemon-reload"])?;
+    if start {
+        scope.systemctl(&["enable", "--now", UNIT_NAME])?;
+    } else {
+        scope.systemctl(&["enable", UNIT_NAME])?;
+    }
+    Ok(path)
+}
+
+/// Stops and disables the service and removes its unit, returning the unit's path
+pub fn uninstall(scope: Scope) -> Result<Option<PathBuf>> {
+    let path = scope.unit_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    scope.systemctl(&["disable", "--now", UNIT_NAME])?;
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    scope.systemctl(&["daemon-reload"])?;
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_unit() {
+        let unit = Unit {
+            exe: PathBuf::from("/home/tilly/.local/bin/tiles"),
+            working_dir: PathBuf::from("/home/tilly"),
+            user: None,
+            log_format: LogFormat::Json,
+            environment: BTreeMap::from([
+                (
+                    "PATH".to_owned(),
+                    "/home/tilly/.cargo/bin:/usr/bin".to_owned(),
+                ),
+                ("GREETING".to_owned(), "say \"100%\"".to_owned()),
+            ]),
+        };
+        let rendered = unit.render();
+        assert!(rendered.contains(
+            "ExecStart=\"/home/tilly/.local/bin/tiles\" server start --foreground --log-format json\n"
+        ));
+        assert!(rendered.contains("Environment=\"PATH=/home/tilly/.cargo/bin:/usr/bin\"\n"));
+        assert!(rendered.contains("Environment=\"GREETING=say \\\"100%%\\\"\"\n"));
+        assert!(rendered.contains("WorkingDirectory=/home/tilly\n"));
+        assert!(rendered.contains("WantedBy=default.target\n"));
+        assert!(!rendered.contains("User="));
+
+        let system = Unit {
+            user: Some("tilly".to_owned()),
+            ..unit
+        };
+        let rendered = system.render();
+        assert!(rendered.contains("User=tilly\n"));
+        assert!(rendered.contains("`tiles server uninstall-service --system`"));
+        assert!(rendered.contains("WantedBy=multi-user.target\n"));
+    }
+}
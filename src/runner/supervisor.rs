@@ -0,0 +1,262 @@
+// Runs the Python server for the proxy. Supervised, a server that exits is
+// started again after a backoff, otherwise the proxy stops with it. Either
+// way its stderr is passed on to the proxy's log and the last error in it is
+// kept as the reason of a crash.
+
+use std::{
+    collections::VecDeque,
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    sync::oneshot,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::runner::protocol::{self, Crash, ServerState, ServerStatus};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A server that ran this long before exiting restarts without waiting long
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+const MAX_CRASHES: usize = 10;
+/// Lines of stderr searched for the reason of a crash
+const TAIL_LINES: usize = 50;
+
+pub struct Supervisor {
+    supervise: bool,
+    status: Mutex<ServerStatus>,
+}
+
+impl Supervisor {
+    pub fn new(supervise: bool) -> Arc<Supervisor> {
+        Arc::new(Supervisor {
+            supervise,
+            status: Mutex::new(ServerStatus {
+                proxy_pid: std::process::id(),
+                supervised: supervise,
+                server: ServerState::Exited,
+                restarts: 0,
+                crashes: vec![],
+            }),
+        })
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Keeps a server from `command` running until `stop` fires, returns
+    /// early when an unsupervised server exits
+    pub async fn run(&self, command: impl Fn() -> Command, mut stop: oneshot::Receiver<()>) {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let started = Instant::now();
+            let crash = match command().stderr(Stdio::piped()).kill_on_drop(true).spawn() {
+                Ok(mut child) => {
+                    let pid = child.id().unwrap_or_default();
+                    info!(pid, "started the server");
+                    self.set_state(ServerState::Running {
+                        pid,
+                        since: protocol::now(),
+                    });
+                    let tail = child.stderr.take().map(relay);
+                    let status = tokio::select! {
+                        status = child.wait() => status,
+                        _ = &mut stop => {
+                            let _ = child.kill().await;
+                            self.set_state(ServerState::Exited);
+                            return;
+                        }
+                    };
+                    let lines = match tail {
+                        Some(tail) => time::timeout(Duration::from_secs(1), tail)
+                            .await
+                            .ok()
+                            .and_then(Result::ok)
+                            .unwrap_or_default(),
+                        None => vec![],
+                    };
+                    Crash {
+                        at: protocol::now(),
+                        status: match status {
+                            Ok(status) => describe(status),
+                            Err(err) => err.to_string(),
+                        },
+                        reason: crash_reason(&lines),
+                    }
+                }
+                Err(err) => Crash {
+                    at: protocol::now(),
+                    status: "failed to start".to_owned(),
+                    reason: Some(err.to_string()),
+                },
+            };
+            error!(status = %crash.status, reason = crash.reason, "the server exited");
+            self.record(crash);
+            if !self.supervise {
+                return;
+            }
+
+            if started.elapsed() >= STABLE_AFTER {
+                backoff = MIN_BACKOFF;
+            }
+            warn!(seconds = backoff.as_secs(), "restarting the server");
+            self.set_state(ServerState::Restarting {
+                at: protocol::now() + backoff.as_secs(),
+            });
+            tokio::select! {
+                _ = time::sleep(backoff) => {}
+                _ = &mut stop => {
+                    self.set_state(ServerState::Exited);
+                    return;
+                }
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            self.status.lock().unwrap().restarts += 1;
+        }
+    }
+
+    fn set_state(&self, state: ServerState) {
+        self.status.lock().unwrap().server = state;
+    }
+
+    fn record(&self, crash: Crash) {
+        let mut status = self.status.lock().unwrap();
+        status.server = ServerState::Exited;
+        status.crashes.push(crash);
+        if status.crashes.len() > MAX_CRASHES {
+            status.crashes.remove(0);
+        }
+    }
+}
+
+// Passes the server's stderr on to ours, returning its last lines at the end
+fn relay(stderr: impl AsyncRead + Unpin + Send + 'static) -> JoinHandle<Vec<String>> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut tail = VecDeque::with_capacity(TAIL_LINES);
+        while let Ok(Some(line)) = lines.next_line().await {
+            eprintln!("{}", line);
+            if tail.len() == TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        tail.into()
+    })
+}
+
+fn describe(status: ExitStatus) -> String {
+    use std::os::unix::process::ExitStatusExt;
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exit code {}", code),
+        // The kernel's OOM killer sends SIGKILL
+        (_, Some(9)) => "killed by SIGKILL, possibly out of memory".to_owned(),
+        (_, Some(signal)) => format!("killed by signal {}", signal),
+        _ => status.to_string(),
+    }
+}
+
+// The last exception Python printed, or the last thing it printed at all
+fn crash_reason(lines: &[String]) -> Option<String> {
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines
+        .iter()
+        .rev()
+        .find(|line| {
+            line.split_once(':').is_some_and(|(name, _)| {
+                !name.contains(' ') && (name.ends_with("Error") || name.ends_with("Exception"))
+            })
+        })
+        .or(lines.last())
+        .map(|line| line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_reason() {
+        let lines: Vec<String> = [
+            "INFO:     Started server process",
+            "Traceback (most recent call last):",
+            "  File \"server/mlx_runner.py\", line 120, in load_model",
+            "RuntimeError: [metal::malloc] Attempting to allocate 19327352832 bytes",
+            "",
+        ]
+        .map(str::to_owned)
+        .to_vec();
+        assert_eq!(
+            crash_reason(&lines).as_deref(),
+            Some("RuntimeError: [metal::malloc] Attempting to allocate 19327352832 bytes")
+        );
+        assert_eq!(
+            crash_reason(&["Killed".to_owned()]).as_deref(),
+            Some("Killed")
+        );
+        assert_eq!(crash_reason(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_restarts_crashed_server() {
+        let supervisor = Supervisor::new(true);
+        let (stop, stopped) = oneshot::channel();
+        let run = tokio::spawn({
+            let supervisor = supervisor.clone();
+            async move {
+                supervisor
+                    .run(
+                        || {
+                            let mut command = Command::new("sh");
+                            command.args(["-c", "echo 'MemoryError: out of memory' >&2; exit 3"]);
+                            command
+                        },
+                        stopped,
+                    )
+                    .await
+            }
+        });
+        time::sleep(Duration::from_millis(1500)).await;
+        let status = supervisor.status();
+        assert_eq!(status.restarts, 1);
+        assert_eq!(status.crashes.len(), 2);
+        assert_eq!(status.crashes[0].status, "exit code 3");
+        assert_eq!(
+            status.crashes[0].reason.as_deref(),
+            Some("MemoryError: out of memory")
+        );
+        assert!(matches!(status.server, ServerState::Restarting { .. }));
+        stop.send(()).unwrap();
+        run.await.unwrap();
+        assert_eq!(supervisor.status().server, ServerState::Exited);
+    }
+
+    #[tokio::test]
+    async fn test_unsupervised_server_stops_the_proxy() {
+        let supervisor = Supervisor::new(false);
+        let (_stop, stopped) = oneshot::channel();
+        supervisor
+            .run(
+                || {
+                    let mut command = Command::new("sh");
+                    command.args(["-c", "exit 1"]);
+                    command
+                },
+                stopped,
+            )
+            .await;
+        assert_eq!(supervisor.status().crashes.len(), 1);
+        assert_eq!(supervisor.status().restarts, 0);
+    }
+}